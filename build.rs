@@ -1,4 +1,3 @@
-use std::collections::BTreeMap;
 use std::{env, fs};
 use std::fs::File;
 use std::io::{Read, Write};
@@ -35,13 +34,15 @@ fn main() {
 
     let apache_mappings = parse_apache_mappings(apache_mime_types_path, '\t');
     let supp_mappings = parse_csv_mappings(supp_mappings_path, ',');
+    // order is preserved so that the first extension listed for a media type is preferred,
+    // and later mappings for an extension take precedence
     let mut combined_mappings = apache_mappings;
     combined_mappings.extend(supp_mappings);
 
     write_mappings_to_disk(combined_mappings);
 }
 
-fn parse_apache_mappings(path: impl AsRef<Path>, separator: char) -> BTreeMap<String, String> {
+fn parse_apache_mappings(path: impl AsRef<Path>, separator: char) -> Vec<(String, String)> {
     let contents = File::open(path.as_ref())
         .and_then(|mut f| {
             let mut s = String::new();
@@ -69,7 +70,7 @@ fn parse_apache_mappings(path: impl AsRef<Path>, separator: char) -> BTreeMap<St
         .collect()
 }
 
-fn parse_csv_mappings(path: impl AsRef<Path>, separator: char) -> BTreeMap<String, String> {
+fn parse_csv_mappings(path: impl AsRef<Path>, separator: char) -> Vec<(String, String)> {
     let contents = File::open(path.as_ref())
         .and_then(|mut f| {
             let mut s = String::new();
@@ -93,7 +94,7 @@ fn parse_csv_mappings(path: impl AsRef<Path>, separator: char) -> BTreeMap<Strin
         .collect()
 }
 
-fn write_mappings_to_disk(mappings: BTreeMap<String, String>) {
+fn write_mappings_to_disk(mappings: Vec<(String, String)>) {
     let out_dir_path = PathBuf::from(env::var("OUT_DIR").unwrap());
     let gen_dir_path = out_dir_path.join(GENERATED_OUT_PREFIX);
     let out_file_path = gen_dir_path.join(OUT_FILE_NAME);
@@ -1,26 +1,63 @@
+use std::collections::BTreeMap;
 use std::env;
-use std::fs::File;
-use std::io::Write;
-use std::path::PathBuf;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::thread;
 use clap::{Args, Parser, Subcommand, ValueEnum};
-use arp::{create_arp_from_fs, CompressionType, Package, PackingOptions, ResourceIdentifier};
+use arp::{
+    create_arp_from_fs, extract_resource, extract_to_dir, CompressionLevel, CompressionType,
+    ConflictPolicy, ExistingFilePolicy, ExtractOptions, MediaTypeRegistry, Package, PackageBuilder,
+    PackageDiff, PackageSet, PackingOptions, ResourceDescriptor, ResourceIdentifier, SniffMode,
+    SymlinkPolicy,
+};
 
 const LIST_HEADER_TYPE: &str = "TYPE";
 const LIST_HEADER_UID: &str = "IDENTIFIER";
+const LIST_HEADER_PACKED: &str = "PACKED";
+const LIST_HEADER_SIZE: &str = "SIZE";
+const LIST_HEADER_CRC: &str = "CRC";
+const LIST_HEADER_PART: &str = "PART";
+const INFO_DEFAULT_LARGEST: usize = 10;
 
-pub fn main() {
+pub fn main() -> ExitCode {
     let args = Cli::parse();
 
-    match args.command {
+    let result = match args.command {
         Commands::Pack(subargs) => do_pack(subargs),
         Commands::Unpack(subargs) => do_unpack(subargs),
+        Commands::Cat(subargs) => do_cat(subargs),
         Commands::List(subargs) => do_list(subargs),
+        Commands::Info(subargs) => do_info(subargs),
+        Commands::Verify(subargs) => do_verify(subargs),
+        Commands::Diff(subargs) => do_diff(subargs),
+        Commands::MakePatch(subargs) => do_make_patch(subargs),
+        Commands::Merge(subargs) => do_merge(subargs),
+        Commands::Repack(subargs) => do_repack(subargs),
+        Commands::Update(subargs) => do_update(subargs),
+        Commands::Compact(subargs) => do_compact(subargs),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        }
     }
 }
 
-fn do_pack(args: PackArgs) {
-    let src_path = args.source_path.canonicalize().unwrap();
-    let name = args.name.unwrap_or(src_path.file_name().unwrap().to_string_lossy().to_string());
+fn do_pack(args: PackArgs) -> Result<(), String> {
+    let src_path = args.source_path.canonicalize()
+        .map_err(|e| format!("Unable to read source directory: {}", e))?;
+    let name = match args.name {
+        Some(name) => name,
+        None => src_path.file_name()
+            .ok_or_else(|| "Unable to derive package name from source path".to_owned())?
+            .to_string_lossy()
+            .to_string(),
+    };
     let namespace = args.namespace.unwrap_or_else(|| name.clone());
     let max_part_len = args.part_size;
     let compression_type = if args.deflate {
@@ -32,105 +69,704 @@ fn do_pack(args: PackArgs) {
         }
     };
     let media_types_path = args.mappings;
-    let dest_path = args.output_dir.unwrap_or(env::current_dir().unwrap());
+    let dest_path = args.output_dir.map_or_else(current_dir, Ok)?;
+    let compression_level = match args.level {
+        Some(level) => CompressionLevel::new(level)?,
+        None => CompressionLevel::default(),
+    };
+    // an explicitly passed file takes precedence over the system database
+    let mut media_types = MediaTypeRegistry::builtin();
+    if args.system_mime_types {
+        media_types.load_system()
+            .map_err(|e| format!("Unable to load system media types: {}", e))?;
+    }
+    if let Some(mime_types_path) = &args.mime_types {
+        media_types.load_mime_types_file(mime_types_path)
+            .map_err(|e| format!("Unable to load media types: {}", e))?;
+    }
+    let threads = args.threads
+        .unwrap_or_else(|| thread::available_parallelism().map(|n| n.get()).unwrap_or(1));
 
-    let opts = PackingOptions::new_v1(
+    let opts = PackingOptions::new_v2(
         name,
         namespace,
         max_part_len,
         compression_type,
         media_types_path,
-    ).unwrap();
-    create_arp_from_fs(&src_path, &dest_path, opts).unwrap();
+    ).map_err(|e| format!("Invalid package options: {}", e))?
+        .with_media_types(media_types)
+        .with_compression_level(compression_level)
+        .with_threads(threads)
+        .with_incremental(args.incremental)
+        .with_include(&args.include)
+        .with_exclude(&args.exclude)
+        .with_skip_hidden(args.skip_hidden)
+        .with_symlink_policy(match args.symlinks {
+            Some(SymlinkPolicyArg::Skip) | None => SymlinkPolicy::Skip,
+            Some(SymlinkPolicyArg::Follow) => SymlinkPolicy::Follow,
+            Some(SymlinkPolicyArg::Error) => SymlinkPolicy::Error,
+        })
+        .with_sniff_mode(match args.sniff {
+            Some(SniffModeArg::Off) | None => SniffMode::Disabled,
+            Some(SniffModeArg::Fallback) => SniffMode::Fallback,
+            Some(SniffModeArg::Override) => SniffMode::Override,
+        });
+    create_arp_from_fs(&src_path, &dest_path, opts)
+        .map_err(|e| format!("Unable to create package: {}", e))
 }
 
-fn do_unpack(args: UnpackArgs) {
-    let Some(res_path) = args.resource_path else {
-        eprintln!("Resource path is required for now");
-        return;
-    };
+fn do_unpack(args: UnpackArgs) -> Result<(), String> {
+    let package = Package::load_from_file(&args.source_path)
+        .map_err(|e| format!("Unable to load package at given path: {}", e))?;
+    let opts = ExtractOptions::default()
+        .with_flatten(args.flatten)
+        .with_existing(match args.existing {
+            None | Some(ExistingFileArg::Overwrite) => ExistingFilePolicy::Overwrite,
+            Some(ExistingFileArg::Skip) => ExistingFilePolicy::Skip,
+            Some(ExistingFileArg::Error) => ExistingFilePolicy::Error,
+        });
 
-    let package = match Package::load_from_file(args.source_path) {
-        Ok(package) => package,
-        Err(err) => {
-            eprintln!("Unable to load package at given path: {}", err);
-            return;
+    let resources = match &args.resource_path {
+        None => package.get_all_resource_descriptors(),
+        Some(res_path) => {
+            let Some((namespace, path)) = res_path.split_once(':') else {
+                return Err("Unable to parse resource UID".to_owned());
+            };
+            if namespace != package.get_namespace() {
+                return Err("Resource not found: Namespace does not match".to_owned());
+            }
+
+            if path.contains(['*', '?', '[']) {
+                package.find_resources_matching(path)
+            } else {
+                let Ok(res_uid) = ResourceIdentifier::parse(res_path) else {
+                    return Err("Unable to parse resource UID".to_owned());
+                };
+
+                // a single resource is written straight to the output path
+                if let Ok(desc) = package.find_resource(&res_uid) {
+                    let file_name = opts.file_name(&desc);
+                    let out_path = match args.output {
+                        Some(output) if output.is_dir() => output.join(file_name),
+                        Some(output) => output,
+                        None => current_dir()?.join(file_name),
+                    };
+                    let written = extract_resource(&desc, &out_path, &opts).map_err(|e| {
+                        format!("Unable to unpack resource {}: {}", desc.identifier, e)
+                    })?;
+                    if written {
+                        println!("Wrote resource {} to {}", desc.identifier, out_path.display());
+                    } else {
+                        println!("Skipping existing file {}", out_path.display());
+                    }
+                    return Ok(());
+                }
+
+                package.get_all_resource_descriptors().into_iter()
+                    .filter(|desc| desc.identifier.components.starts_with(&res_uid.components))
+                    .collect()
+            }
         }
     };
-    let Ok(res_uid) = ResourceIdentifier::parse(&res_path) else {
-        eprintln!("Unable to parse resource UID");
-        return;
-    };
-    let desc = match package.find_resource(&res_uid) {
-        Ok(desc) => desc,
-        Err(e) => {
-            eprintln!("Resource not found: {}", e);
-            return;
-        },
+
+    if resources.is_empty() {
+        return Err("Resource not found: No resources match the given UID".to_owned());
+    }
+
+    let out_dir = args.output.map_or_else(current_dir, Ok)?;
+    let written = extract_to_dir(&resources, &out_dir, &opts)
+        .map_err(|e| format!("Unable to unpack resources: {}", e))?;
+    println!(
+        "Unpacked {} of {} resources to {}",
+        written.len(),
+        resources.len(),
+        out_dir.display(),
+    );
+
+    Ok(())
+}
+
+fn do_cat(args: CatArgs) -> Result<(), String> {
+    let uid = ResourceIdentifier::parse(&args.resource_uid)
+        .map_err(|e| format!("Unable to parse resource UID: {}", e))?;
+    // a directory is searched as a set, so that patches are applied
+    let desc = if args.source_path.is_dir() {
+        PackageSet::load_from_dir(&args.source_path)
+            .map_err(|e| format!("Unable to load packages in given directory: {}", e))?
+            .find_resource(&uid)
+    } else {
+        Package::load_from_file(&args.source_path)
+            .map_err(|e| format!("Unable to load package at given path: {}", e))?
+            .find_resource(&uid)
+    }.map_err(|e| format!("Resource not found: {}", e))?;
+
+    let mut stdout = io::stdout().lock();
+    let result = if args.raw {
+        desc.write_packed_to(&mut stdout)
+    } else {
+        desc.write_to(&mut stdout)
     };
+    result.and_then(|_| stdout.flush().map_err(|e| e.to_string()))
+        .map_err(|e| format!("Unable to write resource {}: {}", desc.identifier, e))
+}
 
-    let loaded_data = match desc.load() {
-        Ok(desc) => desc,
-        Err(e) => {
-            eprintln!("Resource not found: {}", e);
-            return;
-        },
+fn do_list(args: ListArgs) -> Result<(), String> {
+    let package = Package::load_from_file(args.source_path)
+        .map_err(|e| format!("Unable to load package at given path: {}", e))?;
+
+    let mut resources = match &args.glob {
+        Some(pattern) => package.find_resources_matching(pattern),
+        None => package.get_all_resource_descriptors(),
     };
+    if let Some(media_type) = &args.media_type {
+        // a trailing wildcard matches a whole top-level type, e.g. image/*
+        resources.retain(|res| match media_type.strip_suffix('*') {
+            Some(prefix) => res.media_type.starts_with(prefix),
+            None => res.media_type == *media_type,
+        });
+    }
+
+    match args.sort.unwrap_or(ListSortArg::Uid) {
+        ListSortArg::Uid => resources.sort_by(|a, b| a.identifier.cmp(&b.identifier)),
+        ListSortArg::Size => resources.sort_by(|a, b| {
+            a.size.cmp(&b.size).then_with(|| a.identifier.cmp(&b.identifier))
+        }),
+        ListSortArg::Packed => resources.sort_by(|a, b| {
+            a.packed_size.cmp(&b.packed_size).then_with(|| a.identifier.cmp(&b.identifier))
+        }),
+        ListSortArg::Type => resources.sort_by(|a, b| {
+            a.media_type.cmp(&b.media_type).then_with(|| a.identifier.cmp(&b.identifier))
+        }),
+    }
+    if args.reverse {
+        resources.reverse();
+    }
+
+    if args.json {
+        let entries = resources.iter()
+            .map(|res| format!(
+                "{{\"uid\":{},\"media_type\":{},\"size\":{},\"packed_size\":{},\"crc\":{},\
+                \"part\":{}}}",
+                json_string(&res.identifier.to_string()),
+                json_string(&res.media_type),
+                res.size,
+                res.packed_size,
+                res.get_crc(),
+                res.get_part(),
+            ))
+            .collect::<Vec<_>>();
+        println!("[{}]", entries.join(","));
+        return Ok(());
+    }
 
-    let file_name = if desc.extension.len() > 0 {
-        format!("{}.{}", desc.name, desc.extension)
+    if args.csv {
+        println!("uid,media_type,size,packed_size,crc,part");
+        for res in &resources {
+            println!(
+                "{},{},{},{},{},{}",
+                csv_field(&res.identifier.to_string()),
+                csv_field(&res.media_type),
+                res.size,
+                res.packed_size,
+                res.get_crc(),
+                res.get_part(),
+            );
+        }
+        return Ok(());
+    }
+
+    if args.tree {
+        print_tree(&resources, args.long);
+        return Ok(());
+    }
+
+    let columns: &[&str] = if args.long {
+        &[LIST_HEADER_TYPE, LIST_HEADER_PACKED, LIST_HEADER_SIZE, LIST_HEADER_CRC, LIST_HEADER_PART]
     } else {
-        desc.name.clone()
+        &[LIST_HEADER_TYPE]
+    };
+    let rows = resources.iter()
+        .map(|res| {
+            let mut row = vec![res.media_type.clone()];
+            if args.long {
+                row.push(res.packed_size.to_string());
+                row.push(res.size.to_string());
+                row.push(format!("{:08x}", res.get_crc()));
+                row.push(res.get_part().to_string());
+            }
+            row.push(res.identifier.to_string());
+            row
+        })
+        .collect::<Vec<_>>();
+
+    let headers = columns.iter().chain([&LIST_HEADER_UID]).collect::<Vec<_>>();
+    let widths = headers.iter().enumerate()
+        .map(|(i, header)| {
+            rows.iter().map(|row| row[i].chars().count())
+                .chain([header.chars().count()])
+                .max()
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    let format_row = |row: &[&str]| {
+        row.iter().zip(&widths)
+            .map(|(cell, width)| format!("{: <width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("   ")
+            .trim_end()
+            .to_owned()
     };
-    let out_path = match args.output {
-        Some(output) => {
-            if output.is_dir() {
-                output.join(file_name)
+
+    println!("{}", format_row(&headers.iter().map(|header| **header).collect::<Vec<_>>()));
+    println!("{}", "-".repeat(widths.iter().sum::<usize>() + 3 * (widths.len() - 1)));
+    for row in &rows {
+        println!("{}", format_row(&row.iter().map(String::as_str).collect::<Vec<_>>()));
+    }
+
+    Ok(())
+}
+
+// prints resources as an indented hierarchy, with directories ordered by name
+fn print_tree(resources: &[ResourceDescriptor], long: bool) {
+    #[derive(Default)]
+    struct TreeDir<'a> {
+        dirs: BTreeMap<&'a str, TreeDir<'a>>,
+        resources: Vec<&'a ResourceDescriptor>,
+    }
+
+    fn print_dir(dir: &TreeDir, depth: usize, long: bool) {
+        let indent = "  ".repeat(depth);
+        for (name, child) in &dir.dirs {
+            println!("{}{}/", indent, name);
+            print_dir(child, depth + 1, long);
+        }
+        for res in &dir.resources {
+            let name = if res.extension.is_empty() {
+                res.name.clone()
             } else {
-                output
+                format!("{}.{}", res.name, res.extension)
+            };
+            if long {
+                println!("{}{} ({}, {} bytes)", indent, name, res.media_type, res.size);
+            } else {
+                println!("{}{}", indent, name);
             }
         }
-        None => env::current_dir().unwrap().join(file_name),
-    };
-    let mut file = File::create(&out_path).expect("Failed to open output file");
-    file.write(&loaded_data).expect("Failed to write to output file");
+    }
 
-    println!("Wrote resource {} to {}", desc.identifier, out_path.display());
+    let mut root = TreeDir::default();
+    for res in resources {
+        let components = &res.identifier.components;
+        let dir = components[..(components.len() - 1)].iter()
+            .fold(&mut root, |dir, component| dir.dirs.entry(component).or_default());
+        dir.resources.push(res);
+    }
+    print_dir(&root, 0, long);
 }
 
-fn do_list(args: ListArgs) {
-    let package = match Package::load_from_file(args.source_path) {
-        Ok(package) => package,
-        Err(err) => {
-            eprintln!("Unable to load package at given path: {}", err);
-            return;
+fn do_info(args: InfoArgs) -> Result<(), String> {
+    let package = Package::load_from_file(&args.source_path)
+        .map_err(|e| format!("Unable to load package at given path: {}", e))?;
+    let meta = package.get_meta();
+    let compression = match meta.compression_type {
+        Some(compression_type) => {
+            String::from_utf8_lossy(&compression_type.get_magic()).into_owned()
         }
+        None => "none".to_owned(),
     };
-    let resources = package.get_all_resource_descriptors();
-    let max_type_len = resources.iter().map(|r| r.media_type.chars().count())
-        .max().unwrap()
-        .max(LIST_HEADER_TYPE.chars().count());
-    let max_uid_len = resources.iter().map(|r| r.identifier.to_string().chars().count())
-        .max().unwrap()
-        .max(LIST_HEADER_UID.chars().count());
 
-    println!(
-        "{: <type_width$}   {: <uid_width$}",
-        LIST_HEADER_TYPE,
-        LIST_HEADER_UID,
-        type_width = max_type_len,
-        uid_width = max_uid_len,
-    );
-    println!("{}", "-".repeat(max_type_len + max_uid_len + 3));
-    for res in resources {
+    let mut resources = package.get_all_resource_descriptors();
+    let packed_size: u64 = resources.iter().map(|res| res.packed_size).sum();
+    let unpacked_size: u64 = resources.iter().map(|res| res.size).sum();
+    let ratio = (unpacked_size > 0).then(|| packed_size as f64 / unpacked_size as f64);
+
+    let mut by_media_type: BTreeMap<&str, (usize, u64)> = BTreeMap::new();
+    for res in &resources {
+        let entry = by_media_type.entry(&res.media_type).or_default();
+        entry.0 += 1;
+        entry.1 += res.size;
+    }
+    // (media type, resource count, unpacked bytes), largest first
+    let mut by_media_type: Vec<_> = by_media_type.into_iter()
+        .map(|(media_type, (count, size))| (media_type.to_owned(), count, size))
+        .collect();
+    by_media_type.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(&b.0)));
+
+    resources.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.identifier.cmp(&b.identifier)));
+    resources.truncate(args.largest.unwrap_or(INFO_DEFAULT_LARGEST));
+
+    if args.json {
+        let largest = resources.iter()
+            .map(|res| format!(
+                "{{\"uid\":{},\"size\":{},\"packed_size\":{}}}",
+                json_string(&res.identifier.to_string()),
+                res.size,
+                res.packed_size,
+            ))
+            .collect::<Vec<_>>();
+        let media_types = by_media_type.iter()
+            .map(|(media_type, count, size)| format!(
+                "{{\"media_type\":{},\"count\":{},\"size\":{}}}",
+                json_string(media_type),
+                count,
+                size,
+            ))
+            .collect::<Vec<_>>();
         println!(
-            "{: <type_width$}   {: <uid_width$}",
-            &res.media_type,
-            res.identifier.to_string(),
-            type_width = max_type_len,
-            uid_width = max_uid_len,
+            "{{\"version\":{},\"kind\":{},\"compression\":{},\"namespace\":{},\"parts\":{},\
+            \"cat_off\":{},\"cat_len\":{},\"node_count\":{},\"directory_count\":{},\
+            \"resource_count\":{},\"body_off\":{},\"body_len\":{},\"packed_size\":{},\
+            \"unpacked_size\":{},\"compression_ratio\":{},\"largest\":[{}],\
+            \"media_types\":[{}]}}",
+            meta.major_version,
+            json_string(&format!("{:?}", meta.kind)),
+            json_string(&compression),
+            json_string(&meta.namespace),
+            meta.total_parts,
+            meta.cat_off,
+            meta.cat_len,
+            meta.node_count,
+            meta.directory_count,
+            meta.resource_count,
+            meta.body_off,
+            meta.body_len,
+            packed_size,
+            unpacked_size,
+            ratio.map(|ratio| format!("{:.4}", ratio)).unwrap_or("null".to_owned()),
+            largest.join(","),
+            media_types.join(","),
         );
+        return Ok(());
+    }
+
+    println!("Format version:    {}", meta.major_version);
+    println!("Kind:              {:?}", meta.kind);
+    println!("Compression:       {}", compression);
+    println!("Namespace:         {}", meta.namespace);
+    println!("Parts:             {}", meta.total_parts);
+    println!("Catalogue offset:  {:#x}", meta.cat_off);
+    println!("Catalogue length:  {}", meta.cat_len);
+    println!("Nodes:             {}", meta.node_count);
+    println!("Directories:       {}", meta.directory_count);
+    println!("Resources:         {}", meta.resource_count);
+    println!("Body offset:       {:#x}", meta.body_off);
+    println!("Body length:       {}", meta.body_len);
+    println!();
+    println!("Packed size:       {}", packed_size);
+    println!("Unpacked size:     {}", unpacked_size);
+    match ratio {
+        Some(ratio) => println!("Compression ratio: {:.1}%", ratio * 100.0),
+        None => println!("Compression ratio: n/a"),
+    }
+
+    if !resources.is_empty() {
+        println!();
+        println!("Largest resources:");
+        for res in &resources {
+            println!("  {: >12}  {}", res.size, res.identifier);
+        }
+    }
+
+    if !by_media_type.is_empty() {
+        println!();
+        println!("Size by media type:");
+        let type_width = by_media_type.iter().map(|(media_type, _, _)| media_type.chars().count())
+            .max().unwrap();
+        for (media_type, count, size) in &by_media_type {
+            println!(
+                "  {: <type_width$}  {: >12}  ({} resources)",
+                media_type,
+                size,
+                count,
+                type_width = type_width,
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn do_verify(args: VerifyArgs) -> Result<(), String> {
+    let package = Package::load_from_file(&args.source_path)
+        .map_err(|e| format!("Package is invalid: {}", e))?;
+    let report = arp::verify(&package);
+
+    for err in &report.errors {
+        println!("ERROR  {}", err);
+    }
+    for res in &report.resources {
+        match &res.error {
+            Some(err) => println!("FAIL   {} ({})", res.identifier, err),
+            None if !args.quiet => println!("OK     {} ({} bytes)", res.identifier, res.size),
+            None => {}
+        }
+    }
+
+    let failed = report.resources.iter().filter(|res| res.error.is_some()).count();
+    if !report.is_ok() {
+        return Err(format!(
+            "Package is invalid: {} package errors, {} of {} resources failed",
+            report.errors.len(),
+            failed,
+            report.resources.len(),
+        ));
+    }
+
+    println!("Package is valid: {} resources verified", report.resources.len());
+    Ok(())
+}
+
+fn do_diff(args: DiffArgs) -> Result<(), String> {
+    let old = load_package(&args.old_path)?;
+    let new = load_package(&args.new_path)?;
+
+    let diff = arp::diff(&old, &new)
+        .map_err(|e| format!("Unable to compare packages: {}", e))?;
+
+    if args.json {
+        println!("{}", diff_to_json(&diff));
+        return Ok(());
+    }
+
+    if diff.is_empty() {
+        println!("Packages are identical");
+        return Ok(());
+    }
+
+    for change in &diff.header {
+        println!("{}: {} -> {}", change.field, change.old, change.new);
+    }
+    for path in &diff.added {
+        println!("A  {}", path);
+    }
+    for path in &diff.removed {
+        println!("D  {}", path);
+    }
+    for res in &diff.modified {
+        let mut details = Vec::new();
+        if res.content_changed {
+            details.push(format!("{} -> {} bytes", res.old_size, res.new_size));
+        }
+        if res.old_extension != res.new_extension {
+            details.push(format!("extension '{}' -> '{}'", res.old_extension, res.new_extension));
+        }
+        if res.old_media_type != res.new_media_type {
+            details.push(format!("media type {} -> {}", res.old_media_type, res.new_media_type));
+        }
+        println!("M  {} ({})", res.path, details.join(", "));
+    }
+
+    Ok(())
+}
+
+fn do_make_patch(args: MakePatchArgs) -> Result<(), String> {
+    let old = load_package(&args.old_path)?;
+    let new = load_package(&args.new_path)?;
+
+    let name = args.name.unwrap_or_else(|| {
+        format!("{}_patch", new.get_base_file_name().unwrap_or(new.get_namespace()))
+    });
+    let dest_path = args.output_dir.map_or_else(current_dir, Ok)?;
+    // the patch is compressed the same way as the package it brings the old one up to date with
+    let opts = PackingOptions::new_v2(
+        name,
+        new.get_namespace(),
+        args.part_size,
+        new.get_meta().compression_type,
+        None::<PathBuf>,
+    ).map_err(|e| format!("Invalid patch options: {}", e))?;
+
+    let builder = arp::make_patch(&old, &new, opts)
+        .map_err(|e| format!("Unable to create patch: {}", e))?;
+    builder.write_to_dir(&dest_path)
+        .map_err(|e| format!("Unable to write patch: {}", e))
+}
+
+fn do_merge(args: MergeArgs) -> Result<(), String> {
+    let mut packages = Vec::with_capacity(args.source_paths.len());
+    for path in &args.source_paths {
+        packages.push(load_package(path)?);
+    }
+    let Some(first) = packages.first() else {
+        return Err("At least one package is required".to_owned());
+    };
+
+    let namespace = args.namespace.unwrap_or_else(|| first.get_namespace().to_owned());
+    // packed data can only be copied as-is if the compression type is left alone
+    let compression_type = match args.compression_type {
+        Some(CompressionTypeArg::None) => None,
+        Some(CompressionTypeArg::Deflate) => Some(CompressionType::Deflate),
+        None => first.get_meta().compression_type,
+    };
+    let dest_path = args.output_dir.map_or_else(current_dir, Ok)?;
+    let opts = PackingOptions::new_v2(
+        args.name,
+        namespace,
+        args.part_size,
+        compression_type,
+        None::<PathBuf>,
+    ).map_err(|e| format!("Invalid package options: {}", e))?;
+    let policy = match args.on_conflict {
+        Some(ConflictPolicyArg::First) | None => ConflictPolicy::FirstWins,
+        Some(ConflictPolicyArg::Last) => ConflictPolicy::LastWins,
+        Some(ConflictPolicyArg::Error) => ConflictPolicy::Error,
+    };
+
+    let builder = arp::merge(&packages, opts, policy)
+        .map_err(|e| format!("Unable to merge packages: {}", e))?;
+    builder.write_to_dir(&dest_path)
+        .map_err(|e| format!("Unable to write package: {}", e))
+}
+
+fn do_repack(args: RepackArgs) -> Result<(), String> {
+    let package = Package::load_from_file(&args.source_path)
+        .map_err(|e| format!("Unable to load package at given path: {}", e))?;
+
+    let name = args.name
+        .unwrap_or_else(|| package.get_base_file_name().unwrap().to_owned());
+    let namespace = args.namespace.unwrap_or_else(|| package.get_namespace().to_owned());
+    // packed data can only be copied as-is if the compression type is left alone
+    let compression_type = match args.compression_type {
+        Some(CompressionTypeArg::None) => None,
+        Some(CompressionTypeArg::Deflate) => Some(CompressionType::Deflate),
+        None => package.get_meta().compression_type,
+    };
+    let compression_level = match args.level {
+        Some(level) => CompressionLevel::new(level)?,
+        None => CompressionLevel::default(),
+    };
+    let threads = args.threads
+        .unwrap_or_else(|| thread::available_parallelism().map(|n| n.get()).unwrap_or(1));
+    let dest_path = args.output_dir.map_or_else(current_dir, Ok)?;
+    let opts = PackingOptions::new_v2(
+        name,
+        namespace,
+        args.part_size,
+        compression_type,
+        None::<PathBuf>,
+    ).map_err(|e| format!("Invalid package options: {}", e))?;
+    let opts = opts
+        .with_compression_level(compression_level)
+        .with_threads(threads);
+
+    let builder = arp::repack(&package, opts)
+        .map_err(|e| format!("Unable to repack package: {}", e))?;
+    builder.write_to_dir(&dest_path)
+        .map_err(|e| format!("Unable to write package: {}", e))
+}
+
+fn do_update(args: UpdateArgs) -> Result<(), String> {
+    let package = Package::load_from_file(&args.package_path)
+        .map_err(|e| format!("Unable to load package at given path: {}", e))?;
+
+    // the update has to be written the same way as the rest of the package
+    let meta = package.get_meta();
+    let new_options = if meta.major_version == 1 {
+        PackingOptions::new_v1
+    } else {
+        PackingOptions::new_v2
+    };
+    let compression_level = match args.level {
+        Some(level) => CompressionLevel::new(level)?,
+        None => CompressionLevel::default(),
+    };
+    let opts = new_options(
+        package.get_base_file_name().unwrap().to_owned(),
+        meta.namespace.clone(),
+        None,
+        meta.compression_type,
+        None::<PathBuf>,
+    ).map_err(|e| format!("Invalid package options: {}", e))?
+        .with_kind(meta.kind)
+        .with_compression_level(compression_level);
+    // release the package's files before they're modified
+    drop(package);
+
+    let mut builder = PackageBuilder::new(opts)?;
+    builder.add_directory("", &args.source_path)
+        .map_err(|e| format!("Unable to read updated resources: {}", e))?;
+    builder.update_in_place(&args.package_path)
+        .map_err(|e| format!("Unable to update package: {}", e))
+}
+
+fn do_compact(args: CompactArgs) -> Result<(), String> {
+    let new_path = arp::compact(&args.package_path, args.part_size)
+        .map_err(|e| format!("Unable to compact package: {}", e))?;
+    if new_path != args.package_path {
+        println!("Compacted package written to {}", new_path.display());
     }
+    Ok(())
+}
+
+fn load_package(path: &Path) -> Result<Arc<Package>, String> {
+    Package::load_from_file(path)
+        .map_err(|e| format!("Unable to load package at {}: {}", path.display(), e))
+}
+
+fn current_dir() -> Result<PathBuf, String> {
+    env::current_dir().map_err(|e| format!("Unable to get current directory: {}", e))
+}
+
+fn diff_to_json(diff: &PackageDiff) -> String {
+    let header = diff.header.iter()
+        .map(|change| format!(
+            "{{\"field\":{},\"old\":{},\"new\":{}}}",
+            json_string(change.field),
+            json_string(&change.old),
+            json_string(&change.new),
+        ))
+        .collect::<Vec<_>>();
+    let added = diff.added.iter().map(|path| json_string(path)).collect::<Vec<_>>();
+    let removed = diff.removed.iter().map(|path| json_string(path)).collect::<Vec<_>>();
+    let modified = diff.modified.iter()
+        .map(|res| format!(
+            "{{\"path\":{},\"content_changed\":{},\"old_size\":{},\"new_size\":{},\
+            \"old_extension\":{},\"new_extension\":{},\"old_media_type\":{},\
+            \"new_media_type\":{}}}",
+            json_string(&res.path),
+            res.content_changed,
+            res.old_size,
+            res.new_size,
+            json_string(&res.old_extension),
+            json_string(&res.new_extension),
+            json_string(&res.old_media_type),
+            json_string(&res.new_media_type),
+        ))
+        .collect::<Vec<_>>();
+
+    format!(
+        "{{\"header\":[{}],\"added\":[{}],\"removed\":[{}],\"modified\":[{}]}}",
+        header.join(","),
+        added.join(","),
+        removed.join(","),
+        modified.join(","),
+    )
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
 }
 
 #[derive(Parser)]
@@ -144,7 +780,16 @@ struct Cli {
 enum Commands {
     Pack(PackArgs),
     Unpack(UnpackArgs),
+    Cat(CatArgs),
     List(ListArgs),
+    Info(InfoArgs),
+    Verify(VerifyArgs),
+    Diff(DiffArgs),
+    MakePatch(MakePatchArgs),
+    Merge(MergeArgs),
+    Repack(RepackArgs),
+    Update(UpdateArgs),
+    Compact(CompactArgs),
 }
 
 #[derive(Args)]
@@ -155,10 +800,30 @@ struct PackArgs {
     compression_type: Option<CompressionTypeArg>,
     #[arg(long = "deflate")]
     deflate: bool,
+    #[arg(long = "incremental")]
+    incremental: bool,
+    #[arg(short = 'j', long = "threads", value_name = "count")]
+    threads: Option<usize>,
+    #[arg(short = 'l', long = "level", value_name = "level")]
+    level: Option<u8>,
     #[arg(short = 'f', long = "name", value_name = "name")]
     name: Option<String>,
+    #[arg(long = "include", value_name = "glob")]
+    include: Vec<String>,
+    #[arg(long = "exclude", value_name = "glob")]
+    exclude: Vec<String>,
+    #[arg(long = "skip-hidden")]
+    skip_hidden: bool,
+    #[arg(long = "symlinks", value_name = "policy")]
+    symlinks: Option<SymlinkPolicyArg>,
+    #[arg(long = "sniff", value_name = "mode")]
+    sniff: Option<SniffModeArg>,
     #[arg(short = 'm', long = "mappings", value_name = "file")]
     mappings: Option<PathBuf>,
+    #[arg(long = "mime-types", value_name = "file")]
+    mime_types: Option<PathBuf>,
+    #[arg(long = "system-mime-types")]
+    system_mime_types: bool,
     #[arg(short = 'n', long = "namespace", value_name = "namespace")]
     namespace: Option<String>,
     #[arg(short = 'o', long = "output", value_name = "directory")]
@@ -173,14 +838,142 @@ struct UnpackArgs {
     source_path: PathBuf,
     #[arg(short = 'o', long = "output", value_name = "directory")]
     output: Option<PathBuf>,
-    #[arg(short = 'r', long = "resource", value_name = "UID")]
+    #[arg(short = 'r', long = "resource", value_name = "UID or glob")]
     resource_path: Option<String>,
+    #[arg(long = "existing", value_name = "policy")]
+    existing: Option<ExistingFileArg>,
+    #[arg(long = "flatten")]
+    flatten: bool,
+}
+
+#[derive(Args)]
+struct CatArgs {
+    #[arg(value_name = "ARP file or directory")]
+    source_path: PathBuf,
+    #[arg(value_name = "UID")]
+    resource_uid: String,
+    #[arg(long = "raw")]
+    raw: bool,
 }
 
 #[derive(Args)]
 struct ListArgs {
     #[arg(value_name = "ARP file")]
     source_path: PathBuf,
+    #[arg(short = 'g', long = "glob", value_name = "pattern")]
+    glob: Option<String>,
+    #[arg(short = 't', long = "type", value_name = "media type")]
+    media_type: Option<String>,
+    #[arg(short = 's', long = "sort", value_name = "key")]
+    sort: Option<ListSortArg>,
+    #[arg(long = "reverse")]
+    reverse: bool,
+    #[arg(short = 'l', long = "long")]
+    long: bool,
+    #[arg(long = "tree", conflicts_with_all = ["json", "csv"])]
+    tree: bool,
+    #[arg(long = "json", conflicts_with = "csv")]
+    json: bool,
+    #[arg(long = "csv")]
+    csv: bool,
+}
+
+#[derive(Args)]
+struct InfoArgs {
+    #[arg(value_name = "ARP file")]
+    source_path: PathBuf,
+    #[arg(long = "largest", value_name = "count")]
+    largest: Option<usize>,
+    #[arg(long = "json")]
+    json: bool,
+}
+
+#[derive(Args)]
+struct VerifyArgs {
+    #[arg(value_name = "ARP file")]
+    source_path: PathBuf,
+    #[arg(short = 'q', long = "quiet")]
+    quiet: bool,
+}
+
+#[derive(Args)]
+struct DiffArgs {
+    #[arg(value_name = "old ARP file")]
+    old_path: PathBuf,
+    #[arg(value_name = "new ARP file")]
+    new_path: PathBuf,
+    #[arg(long = "json")]
+    json: bool,
+}
+
+#[derive(Args)]
+struct MakePatchArgs {
+    #[arg(value_name = "old ARP file")]
+    old_path: PathBuf,
+    #[arg(value_name = "new ARP file")]
+    new_path: PathBuf,
+    #[arg(short = 'f', long = "name", value_name = "name")]
+    name: Option<String>,
+    #[arg(short = 'o', long = "output", value_name = "directory")]
+    output_dir: Option<PathBuf>,
+    #[arg(short = 'p', long = "part-size", value_name = "size")]
+    part_size: Option<u64>,
+}
+
+#[derive(Args)]
+struct MergeArgs {
+    #[arg(value_name = "ARP files", required = true)]
+    source_paths: Vec<PathBuf>,
+    #[arg(short = 'f', long = "name", value_name = "name")]
+    name: String,
+    #[arg(short = 'c', long = "compress", value_name = "type", default_value = None)]
+    compression_type: Option<CompressionTypeArg>,
+    #[arg(long = "on-conflict", value_name = "policy")]
+    on_conflict: Option<ConflictPolicyArg>,
+    #[arg(short = 'n', long = "namespace", value_name = "namespace")]
+    namespace: Option<String>,
+    #[arg(short = 'o', long = "output", value_name = "directory")]
+    output_dir: Option<PathBuf>,
+    #[arg(short = 'p', long = "part-size", value_name = "size")]
+    part_size: Option<u64>,
+}
+
+#[derive(Args)]
+struct RepackArgs {
+    #[arg(value_name = "ARP file")]
+    source_path: PathBuf,
+    #[arg(short = 'c', long = "compress", value_name = "type", default_value = None)]
+    compression_type: Option<CompressionTypeArg>,
+    #[arg(short = 'j', long = "threads", value_name = "count")]
+    threads: Option<usize>,
+    #[arg(short = 'l', long = "level", value_name = "level")]
+    level: Option<u8>,
+    #[arg(short = 'f', long = "name", value_name = "name")]
+    name: Option<String>,
+    #[arg(short = 'n', long = "namespace", value_name = "namespace")]
+    namespace: Option<String>,
+    #[arg(short = 'o', long = "output", value_name = "directory")]
+    output_dir: Option<PathBuf>,
+    #[arg(short = 'p', long = "part-size", value_name = "size")]
+    part_size: Option<u64>,
+}
+
+#[derive(Args)]
+struct UpdateArgs {
+    #[arg(value_name = "ARP file")]
+    package_path: PathBuf,
+    #[arg(value_name = "directory")]
+    source_path: PathBuf,
+    #[arg(short = 'l', long = "level", value_name = "level")]
+    level: Option<u8>,
+}
+
+#[derive(Args)]
+struct CompactArgs {
+    #[arg(value_name = "ARP file")]
+    package_path: PathBuf,
+    #[arg(short = 'p', long = "part-size", value_name = "size")]
+    part_size: Option<u64>,
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
@@ -188,3 +981,39 @@ enum CompressionTypeArg {
     None,
     Deflate,
 }
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+enum SymlinkPolicyArg {
+    Skip,
+    Follow,
+    Error,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+enum ListSortArg {
+    Uid,
+    Size,
+    Packed,
+    Type,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+enum SniffModeArg {
+    Off,
+    Fallback,
+    Override,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+enum ConflictPolicyArg {
+    First,
+    Last,
+    Error,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+enum ExistingFileArg {
+    Overwrite,
+    Skip,
+    Error,
+}
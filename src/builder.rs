@@ -0,0 +1,713 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+use crate::defines::*;
+use crate::cache::{remove_cache, BuildCache};
+use crate::pack::{part_file_name, write_package, NodeSource, PackNode, WriteSeekSink};
+use crate::update::update_package;
+use crate::util::ignore::{IgnoreRules, IGNORE_FILE_NAME};
+use crate::util::uid::validate_path_component;
+use crate::{
+    sniff_media_type, MediaTypeRegistry, Package, PackageKind, PackingOptions, SniffMode, SymlinkPolicy,
+    DEFAULT_MEDIA_TYPE, SNIFF_LEN,
+};
+
+/// Assembles a package from resources supplied in memory, from readers, or from the
+/// filesystem.
+///
+/// Resources are addressed by their path within the package, e.g. `textures/stone.png`. A file
+/// extension on the final path component is stored as the resource's extension and does not
+/// form part of its UID, so the example above is identified as `<namespace>:textures/stone`.
+///
+/// Nodes are ordered by name rather than by the order in which they were added, so identical
+/// inputs always produce byte-for-byte identical packages.
+pub struct PackageBuilder {
+    options: PackingOptions,
+    media_types: MediaTypeRegistry,
+    root: BuilderDir,
+}
+
+#[derive(Default)]
+struct BuilderDir {
+    // keyed by UID component, and sorted by it when the nodes are built
+    children: Vec<(String, BuilderEntry)>,
+}
+
+enum BuilderEntry {
+    Directory(BuilderDir),
+    Resource(PendingResource),
+    Tombstone,
+}
+
+struct PendingResource {
+    ext: String,
+    media_type: String,
+    source: NodeSource,
+}
+
+impl BuilderDir {
+    fn get_mut(&mut self, name: &str) -> Option<&mut BuilderEntry> {
+        self.children.iter_mut()
+            .find(|(child_name, _)| child_name == name)
+            .map(|(_, entry)| entry)
+    }
+}
+
+impl PackageBuilder {
+    pub fn new(options: PackingOptions) -> Result<PackageBuilder, String> {
+        if options.kind == PackageKind::Patch && options.version < 2 {
+            return Err("Patch packages require format version 2".to_owned());
+        }
+
+        let mut media_types = options.media_types.clone();
+        if options.system_media_types {
+            media_types.load_system()?;
+        }
+        if let Some(mt_path) = &options.media_types_path {
+            media_types.load_csv_file(mt_path)?;
+        }
+
+        Ok(Self {
+            options,
+            media_types,
+            root: BuilderDir::default(),
+        })
+    }
+
+    /// Adds a resource with the given contents. If `media_type` is `None`, it is determined from
+    /// the path's file extension and, depending on the [SniffMode], the contents.
+    pub fn add_bytes(
+        &mut self,
+        uid_path: impl AsRef<str>,
+        data: impl Into<Vec<u8>>,
+        media_type: Option<&str>,
+    ) -> Result<(), String> {
+        self.add_resource(uid_path.as_ref(), NodeSource::Bytes(data.into()), media_type)
+    }
+
+    /// Adds a resource whose contents are read from `reader` when the package is written.
+    ///
+    /// Since readers can't be rewound, the raw data is written to the package first and then
+    /// compressed from there, so that it can still be stored raw if compression doesn't shrink
+    /// it. Sinks passed to [PackageBuilder::write_to] can't be read back, so in that case the
+    /// data is kept compressed even if that turns out to be larger. If the contents need to be
+    /// sniffed to determine the media type, the first [SNIFF_LEN] bytes are read immediately.
+    pub fn add_reader(
+        &mut self,
+        uid_path: impl AsRef<str>,
+        reader: impl Read + Send + 'static,
+        media_type: Option<&str>,
+    ) -> Result<(), String> {
+        let source = NodeSource::Reader(Mutex::new(Some(Box::new(reader))));
+        self.add_resource(uid_path.as_ref(), source, media_type)
+    }
+
+    /// Adds a resource whose contents are read from the file at `path` when the package is
+    /// written.
+    pub fn add_file(
+        &mut self,
+        uid_path: impl AsRef<str>,
+        path: impl AsRef<Path>,
+        media_type: Option<&str>,
+    ) -> Result<(), String> {
+        let path = path.as_ref();
+        let meta = path.metadata().map_err(|e| e.to_string())?;
+        if !meta.is_file() {
+            return Err(format!("'{}' is not a regular file", path.display()));
+        }
+
+        let source = NodeSource::File { path: path.to_path_buf(), size: meta.len() };
+        self.add_resource(uid_path.as_ref(), source, media_type)
+    }
+
+    /// Marks the resource identified by `uid_path`, e.g. `textures/stone`, as deleted, along
+    /// with any resources beneath it. Only permitted in patch packages.
+    ///
+    /// Unlike the paths passed when adding resources, `uid_path` must not include a file
+    /// extension, since tombstones apply to UIDs.
+    pub fn add_tombstone(&mut self, uid_path: impl AsRef<str>) -> Result<(), String> {
+        if self.options.kind != PackageKind::Patch {
+            return Err("Tombstones may only be added to patch packages".to_owned());
+        }
+
+        let mut components = split_uid_path(uid_path.as_ref())?;
+        let Some(name) = components.pop() else {
+            return Err("Tombstone path cannot be empty".to_owned());
+        };
+
+        let parent = self.get_or_create_dir(&components)?;
+        if parent.get_mut(&name).is_some() {
+            components.push(name);
+            return Err(format!("Resource path '{}' is already in use", components.join("/")));
+        }
+
+        parent.children.push((name, BuilderEntry::Tombstone));
+
+        Ok(())
+    }
+
+    /// Recursively adds the contents of the directory at `path` beneath `uid_path`. An empty
+    /// `uid_path` adds them to the root of the package.
+    ///
+    /// Paths are filtered according to the include, exclude and hidden file options, and any
+    /// `.arpignore` files found in the tree, which follow gitignore syntax and apply to the
+    /// directory containing them. Symlinks are handled according to the configured
+    /// [SymlinkPolicy].
+    pub fn add_directory(
+        &mut self,
+        uid_path: impl AsRef<str>,
+        path: impl AsRef<Path>,
+    ) -> Result<(), String> {
+        let root_path = path.as_ref();
+        if !root_path.metadata().map_err(|e| e.to_string())?.is_dir() {
+            return Err(format!("'{}' is not a directory", root_path.display()));
+        }
+
+        let root_components = split_uid_path(uid_path.as_ref())?;
+        self.get_or_create_dir(&root_components)?;
+
+        let follow_symlinks = self.options.symlink_policy == SymlinkPolicy::Follow;
+        // canonical paths of the directories above each pending directory, used to detect
+        // cycles when following symlinks
+        let root_ancestors = if follow_symlinks {
+            vec![root_path.canonicalize().map_err(|e| e.to_string())?]
+        } else {
+            Vec::new()
+        };
+
+        let mut dir_queue: VecDeque<PendingDir> = VecDeque::from([PendingDir {
+            path: root_path.to_owned(),
+            components: root_components,
+            rel_path: String::new(),
+            ignore_rules: Vec::new(),
+            ancestors: root_ancestors,
+        }]);
+
+        while let Some(mut pending) = dir_queue.pop_front() {
+            let ignore_path = pending.path.join(IGNORE_FILE_NAME);
+            if ignore_path.is_file() {
+                let contents = fs::read_to_string(&ignore_path).map_err(|err| err.to_string())?;
+                pending.ignore_rules.push(Rc::new((
+                    pending.rel_path.clone(),
+                    IgnoreRules::parse(&contents),
+                )));
+            }
+
+            let dir = pending.path.read_dir().map_err(|err| err.to_string())?;
+            for child in dir {
+                let child = child.map_err(|err| err.to_string())?;
+                let child_path = child.path();
+                let mut child_meta = child.metadata().map_err(|err| err.to_string())?;
+                let is_symlink = child_meta.is_symlink();
+                if is_symlink && follow_symlinks {
+                    child_meta = fs::metadata(&child_path).map_err(|err| {
+                        format!("Failed to follow symlink '{}': {}", child_path.display(), err)
+                    })?;
+                }
+                let file_name = child.file_name();
+                let Some(file_name) = file_name.to_str() else {
+                    return Err(format!(
+                        "File name '{}' is not valid UTF-8",
+                        file_name.to_string_lossy(),
+                    ));
+                };
+
+                let rel_path = if pending.rel_path.is_empty() {
+                    file_name.to_owned()
+                } else {
+                    format!("{}/{}", pending.rel_path, file_name)
+                };
+                let is_dir = child_meta.is_dir();
+
+                if (file_name == IGNORE_FILE_NAME && !is_dir)
+                    || is_ignored(&pending.ignore_rules, &rel_path, is_dir)
+                    || !self.options.should_pack_path(&rel_path, is_dir) {
+                    continue;
+                }
+
+                if is_symlink {
+                    match self.options.symlink_policy {
+                        SymlinkPolicy::Skip => continue,
+                        SymlinkPolicy::Follow => {}
+                        SymlinkPolicy::Error => {
+                            return Err(format!("'{}' is a symlink", child_path.display()));
+                        }
+                    }
+                }
+
+                check_path_component(file_name)?;
+
+                let mut child_components = pending.components.clone();
+                child_components.push(file_name.to_owned());
+
+                if is_dir {
+                    let mut ancestors = Vec::new();
+                    if follow_symlinks {
+                        let canonical_path = child_path.canonicalize()
+                            .map_err(|err| err.to_string())?;
+                        if pending.ancestors.contains(&canonical_path) {
+                            return Err(format!(
+                                "Symlink '{}' forms a cycle",
+                                child_path.display(),
+                            ));
+                        }
+                        ancestors = pending.ancestors.clone();
+                        ancestors.push(canonical_path);
+                    }
+
+                    // with an include filter, directories only appear if they're included
+                    // themselves or contain something which was
+                    if self.options.is_included(&rel_path, true) {
+                        self.get_or_create_dir(&child_components)?;
+                    }
+                    dir_queue.push_back(PendingDir {
+                        path: child_path,
+                        components: child_components,
+                        rel_path,
+                        ignore_rules: pending.ignore_rules.clone(),
+                        ancestors,
+                    });
+                } else if child_meta.is_file() {
+                    let source = NodeSource::File { path: child_path, size: child_meta.len() };
+                    self.insert_resource(child_components, source, None)?;
+                } else if self.options.symlink_policy == SymlinkPolicy::Error {
+                    return Err(format!(
+                        "'{}' is not a directory or regular file",
+                        child_path.display(),
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the package to a single sink. Fails if the package would need to be split into
+    /// multiple parts.
+    pub fn write_to<W: Write + Seek>(mut self, sink: &mut W) -> Result<(), String> {
+        let nodes = self.build_nodes();
+        write_package(
+            &nodes,
+            &self.options,
+            WriteSeekSink(sink),
+            |_| Err("Package does not fit in a single part".to_owned()),
+            None,
+        )?;
+        Ok(())
+    }
+
+    /// Writes the package to a new buffer as a single part. Fails if the package would need to
+    /// be split into multiple parts.
+    pub fn write_to_vec(mut self) -> Result<Vec<u8>, String> {
+        let nodes = self.build_nodes();
+        let mut sink = Cursor::new(Vec::new());
+        write_package(
+            &nodes,
+            &self.options,
+            &mut sink,
+            |_| Err("Package does not fit in a single part".to_owned()),
+            None,
+        )?;
+        Ok(sink.into_inner())
+    }
+
+    /// Writes the package to the given directory, splitting it into multiple part files if
+    /// necessary.
+    pub fn write_to_dir(self, target_dir: impl AsRef<Path>) -> Result<(), String> {
+        self.write_parts_to_dir(target_dir.as_ref())?;
+        Ok(())
+    }
+
+    // returns the number of parts written
+    pub(crate) fn write_parts_to_dir(mut self, target_dir: &Path) -> Result<u16, String> {
+        let nodes = self.build_nodes();
+
+        if !target_dir.exists() {
+            fs::create_dir_all(target_dir).map_err(|e| e.to_string())?;
+        }
+
+        // parts are staged next to their final paths so that they can be renamed into place
+        let mut staged = StagedParts::new(target_dir);
+        let part_1_file = staged.create_part()?;
+        let mut cache = self.options.incremental
+            .then(|| BuildCache::load(target_dir, &self.options));
+        let total_parts = write_package(
+            &nodes,
+            &self.options,
+            part_1_file,
+            |_| staged.create_part(),
+            cache.as_mut(),
+        )?;
+
+        staged.commit(&self.options.name)?;
+
+        match cache {
+            Some(cache) => cache.save(target_dir, &self.options, total_parts)?,
+            None => remove_cache(target_dir, &self.options.name)?,
+        }
+        Ok(total_parts)
+    }
+
+    /// Adds the resources to the existing package at `path` in place, replacing any with the
+    /// same UIDs. The options must match the package's format version, compression type, kind
+    /// and namespace.
+    ///
+    /// New data is appended to the package's last part, which may grow beyond the part size it
+    /// was originally written with. Replaced data is left behind as dead space until the
+    /// package is passed to [compact](crate::compact). The package's current contents remain
+    /// valid until the update has been fully written, so a failed update leaves it unchanged.
+    pub fn update_in_place(mut self, path: impl AsRef<Path>) -> Result<(), String> {
+        let nodes = self.build_nodes();
+        update_package(&nodes, &self.options, path.as_ref())
+    }
+
+    fn add_resource(
+        &mut self,
+        uid_path: &str,
+        source: NodeSource,
+        media_type: Option<&str>,
+    ) -> Result<(), String> {
+        let components = split_uid_path(uid_path)?;
+        if components.is_empty() {
+            return Err("Resource path cannot be empty".to_owned());
+        }
+
+        self.insert_resource(components, source, media_type)
+    }
+
+    // the final component is the resource's file name, including its extension
+    fn insert_resource(
+        &mut self,
+        mut components: Vec<String>,
+        source: NodeSource,
+        media_type: Option<&str>,
+    ) -> Result<(), String> {
+        let file_name = components.pop().unwrap();
+        let file_path = Path::new(&file_name);
+        let name = file_path.file_stem().and_then(|s| s.to_str()).unwrap_or_default().to_owned();
+        let ext = file_path.extension().and_then(|s| s.to_str()).unwrap_or_default().to_owned();
+
+        self.insert_named_resource(components, name, ext, source, media_type)
+    }
+
+    /// Adds a resource beneath the directory identified by `components`, with its name and
+    /// extension given separately.
+    pub(crate) fn insert_named_resource(
+        &mut self,
+        mut components: Vec<String>,
+        name: String,
+        ext: String,
+        mut source: NodeSource,
+        media_type: Option<&str>,
+    ) -> Result<(), String> {
+        check_path_component(&name)?;
+        if ext.len() > NODE_EXT_MAX_LEN {
+            return Err(format!("Extension of resource '{}' is too long", name));
+        }
+
+        let media_type = match media_type {
+            Some(mt) => mt.to_owned(),
+            None => self.detect_media_type(&ext, &mut source)?,
+        };
+        if media_type.len() > NODE_MT_MAX_LEN {
+            return Err(format!("Media type of resource '{}' is too long", name));
+        }
+
+        let parent = self.get_or_create_dir(&components)?;
+        if parent.get_mut(&name).is_some() {
+            components.push(name);
+            return Err(format!("Resource path '{}' is already in use", components.join("/")));
+        }
+
+        parent.children.push((name, BuilderEntry::Resource(PendingResource {
+            ext,
+            media_type,
+            source,
+        })));
+
+        Ok(())
+    }
+
+    /// Adds a resource from an existing package at the path identified by `components`,
+    /// copying its packed data as-is if it's compatible with the new package.
+    pub(crate) fn insert_packed_resource(
+        &mut self,
+        mut components: Vec<String>,
+        package: &Arc<Package>,
+        index: u32,
+    ) -> Result<(), String> {
+        let node = &package.catalogue.resources[&index];
+        // the resource's name is passed separately from the directory containing it
+        components.pop();
+        self.insert_named_resource(
+            components,
+            node.name.clone(),
+            node.ext.clone(),
+            NodeSource::Packed { package: Arc::clone(package), index },
+            Some(&node.media_type),
+        )
+    }
+
+    /// Adds an empty directory at the path identified by `components`, if nothing exists there
+    /// yet.
+    pub(crate) fn insert_directory(&mut self, components: &[String]) -> Result<(), String> {
+        self.get_or_create_dir(components).map(|_| ())
+    }
+
+    fn detect_media_type(&self, ext: &str, source: &mut NodeSource) -> Result<String, String> {
+        // the default type says nothing about the contents, so it doesn't count as a match
+        let ext_type = self.media_types.get_media_type(ext)
+            .filter(|&mt| mt != DEFAULT_MEDIA_TYPE);
+
+        let sniffed = match (self.options.sniff_mode, ext_type) {
+            (SniffMode::Disabled, _) | (SniffMode::Fallback, Some(_)) => None,
+            (SniffMode::Fallback, None) | (SniffMode::Override, _) => {
+                sniff_media_type(&source.peek(SNIFF_LEN)?)
+            }
+        };
+
+        let media_type = match (ext_type, sniffed) {
+            (Some(ext_type), Some(sniffed)) if sniffed.generic => ext_type,
+            (_, Some(sniffed)) => sniffed.media_type,
+            (Some(ext_type), None) => ext_type,
+            (None, None) => DEFAULT_MEDIA_TYPE,
+        };
+        Ok(media_type.to_owned())
+    }
+
+    fn get_or_create_dir(&mut self, components: &[String]) -> Result<&mut BuilderDir, String> {
+        let mut cur_dir = &mut self.root;
+        for (i, component) in components.iter().enumerate() {
+            if cur_dir.get_mut(component).is_none() {
+                cur_dir.children.push(
+                    (component.clone(), BuilderEntry::Directory(BuilderDir::default()))
+                );
+            }
+
+            cur_dir = match cur_dir.get_mut(component).unwrap() {
+                BuilderEntry::Directory(dir) => dir,
+                BuilderEntry::Resource(_) | BuilderEntry::Tombstone => {
+                    return Err(format!(
+                        "Resource path '{}' is already in use",
+                        components[..=i].join("/"),
+                    ));
+                }
+            };
+        }
+
+        Ok(cur_dir)
+    }
+
+    // flattens the tree into nodes with directories listed first in breadth-first order,
+    // followed by resources and tombstones in the order they were encountered
+    fn build_nodes(&mut self) -> Vec<PackNode> {
+        struct FlatDir {
+            name: String,
+            child_dir_slots: Vec<u32>,
+            child_res_slots: Vec<u32>,
+        }
+
+        let mut dir_queue: VecDeque<(String, BuilderDir)> =
+            VecDeque::from([(String::new(), std::mem::take(&mut self.root))]);
+        let mut flat_dirs: Vec<FlatDir> = Vec::new();
+        let mut leaves: Vec<PackNode> = Vec::new();
+        // the root directory occupies the first slot
+        let mut next_dir_slot = 1;
+
+        while let Some((name, mut dir)) = dir_queue.pop_front() {
+            // node order must not depend on the order in which resources were added or
+            // discovered, so that the same inputs always produce the same bytes
+            dir.children.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+            let mut flat_dir = FlatDir { name, child_dir_slots: vec![], child_res_slots: vec![] };
+            for (child_name, child) in dir.children {
+                match child {
+                    BuilderEntry::Directory(child_dir) => {
+                        flat_dir.child_dir_slots.push(next_dir_slot);
+                        next_dir_slot += 1;
+                        dir_queue.push_back((child_name, child_dir));
+                    }
+                    BuilderEntry::Resource(res) => {
+                        flat_dir.child_res_slots.push(leaves.len() as u32);
+                        leaves.push(PackNode {
+                            name: child_name,
+                            ext: res.ext,
+                            media_type: res.media_type,
+                            source: res.source,
+                        });
+                    }
+                    BuilderEntry::Tombstone => {
+                        flat_dir.child_res_slots.push(leaves.len() as u32);
+                        leaves.push(PackNode {
+                            name: child_name,
+                            ext: String::new(),
+                            media_type: String::new(),
+                            source: NodeSource::Tombstone,
+                        });
+                    }
+                }
+            }
+            flat_dirs.push(flat_dir);
+        }
+
+        let dir_count = flat_dirs.len() as u32;
+
+        let dir_nodes = flat_dirs.into_iter().map(|dir| {
+            let child_indices = dir.child_dir_slots.into_iter()
+                .chain(dir.child_res_slots.into_iter().map(|slot| dir_count + slot))
+                .collect();
+            PackNode {
+                name: dir.name,
+                ext: String::new(),
+                media_type: String::new(),
+                source: NodeSource::Directory(child_indices),
+            }
+        });
+        dir_nodes.chain(leaves).collect()
+    }
+}
+
+/// A directory discovered by [PackageBuilder::add_directory] which has yet to be traversed.
+struct PendingDir {
+    path: PathBuf,
+    components: Vec<String>,
+    // relative to the directory being added
+    rel_path: String,
+    // ignore files from this directory and its ancestors, along with the relative paths of the
+    // directories containing them
+    ignore_rules: Vec<Rc<(String, IgnoreRules)>>,
+    // only populated when following symlinks
+    ancestors: Vec<PathBuf>,
+}
+
+fn is_ignored(ignore_rules: &[Rc<(String, IgnoreRules)>], rel_path: &str, is_dir: bool) -> bool {
+    // rules from deeper ignore files take precedence
+    ignore_rules.iter().rev()
+        .find_map(|rules| {
+            let (base, rules) = rules.as_ref();
+            let path = if base.is_empty() {
+                rel_path
+            } else {
+                rel_path.strip_prefix(base.as_str())?.strip_prefix('/')?
+            };
+            rules.check(path, is_dir)
+        })
+        .unwrap_or(false)
+}
+
+fn split_uid_path(uid_path: &str) -> Result<Vec<String>, String> {
+    if uid_path.is_empty() {
+        return Ok(vec![]);
+    }
+
+    uid_path.split(UID_PATH_SEPARATOR)
+        .map(|component| {
+            check_path_component(component)?;
+            Ok(component.to_owned())
+        })
+        .collect()
+}
+
+fn check_path_component(component: &str) -> Result<(), String> {
+    if component.is_empty() {
+        return Err("Resource path contains empty component".to_owned());
+    }
+    if component.len() > NODE_NAME_MAX_LEN {
+        return Err(format!("Path component '{}' is too long", component));
+    }
+    validate_path_component(component)
+}
+
+/// Temporary part files in the target directory. Any which have not been committed are
+/// deleted when this is dropped.
+struct StagedParts {
+    dir: PathBuf,
+    paths: Vec<PathBuf>,
+}
+
+impl StagedParts {
+    fn new(dir: &Path) -> Self {
+        Self { dir: dir.to_owned(), paths: Vec::new() }
+    }
+
+    fn create_part(&mut self) -> Result<File, String> {
+        let path = self.dir.join(format!(".{}.arp.tmp", Uuid::new_v4()));
+        let file = create_part_file(&path)?;
+        self.paths.push(path);
+        Ok(file)
+    }
+
+    fn commit(mut self, name: &str) -> Result<(), String> {
+        let total_parts = self.paths.len();
+
+        // make sure everything has hit the disk before any existing files are replaced
+        for path in &self.paths {
+            File::options().write(true).open(path)
+                .and_then(|file| file.sync_all())
+                .map_err(|e| e.to_string())?;
+        }
+
+        // the first part is renamed last since it's the entry point to the package
+        while let Some(path) = self.paths.pop() {
+            let dest = self.dir.join(part_file_name(name, self.paths.len() + 1, total_parts));
+            if let Err(e) = fs::rename(&path, dest) {
+                self.paths.push(path);
+                return Err(e.to_string());
+            }
+        }
+
+        remove_stale_parts(&self.dir, name, total_parts)
+    }
+}
+
+impl Drop for StagedParts {
+    fn drop(&mut self) {
+        for path in &self.paths {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Removes files belonging to a previous version of the package which the new version does
+/// not overwrite.
+fn remove_stale_parts(dir: &Path, name: &str, total_parts: usize) -> Result<(), String> {
+    for entry in fs::read_dir(dir).map_err(|e| e.to_string())? {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let file_name = entry.file_name();
+        let Some(file_name) = file_name.to_str() else {
+            continue;
+        };
+
+        let stale = if file_name == format!("{}.arp", name) {
+            total_parts > 1
+        } else {
+            file_name.strip_prefix(name)
+                .and_then(|rest| rest.strip_prefix(".part"))
+                .and_then(|rest| rest.strip_suffix(".arp"))
+                .filter(|index| index.len() == 3)
+                .and_then(|index| index.parse::<usize>().ok())
+                .is_some_and(|index| total_parts == 1 || index > total_parts)
+        };
+
+        if stale {
+            fs::remove_file(entry.path()).map_err(|e| e.to_string())?;
+        }
+    }
+
+    Ok(())
+}
+
+fn create_part_file(path: impl AsRef<Path>) -> Result<File, String> {
+    // parts must be readable so that streamed nodes can be moved between them
+    File::options()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use uuid::Uuid;
+use crate::pack::{part_file_name, push_u16_le, push_u32_le, push_u64_le, NodeSource, PackNode};
+use crate::util::crc32c::crc32c_continue;
+use crate::PackingOptions;
+
+const CACHE_MAGIC: [u8; 8] = *b"ARPCACHE";
+const CACHE_FORMAT_VERSION: u16 = 1;
+const CACHE_FILE_EXT: &str = "arpcache";
+
+/// Identifies the state of a source file when it was packed.
+#[derive(Clone, PartialEq)]
+pub(crate) struct CacheKey {
+    path: String,
+    size: u64,
+    // nanoseconds since the epoch, or 0 if unknown
+    mtime: u64,
+}
+
+impl CacheKey {
+    /// Returns the current key for the node's source, if it's a file.
+    pub(crate) fn for_node(node: &PackNode) -> Result<Option<CacheKey>, String> {
+        let NodeSource::File { path, .. } = &node.source else {
+            return Ok(None);
+        };
+
+        let meta = path.metadata().map_err(|e| e.to_string())?;
+        let mtime = meta.modified().ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|time| time.as_nanos() as u64)
+            .unwrap_or(0);
+        Ok(Some(CacheKey { path: path.to_string_lossy().into_owned(), size: meta.len(), mtime }))
+    }
+}
+
+/// Records where a file's packed data was written by a previous build.
+pub(crate) struct CacheEntry {
+    pub(crate) key: CacheKey,
+    pub(crate) media_type: String,
+    // whether the packer wanted to compress the file, which may change with the options
+    pub(crate) compress: bool,
+    // CRC of the unpacked data
+    pub(crate) content_crc: u32,
+    pub(crate) part: u16,
+    // absolute offset within the part file
+    pub(crate) offset: u64,
+    pub(crate) packed_len: u64,
+    pub(crate) unpacked_len: u64,
+    pub(crate) crc: u32,
+    pub(crate) flags: u8,
+}
+
+/// Packed data from the previous build of a package, along with entries for the build in
+/// progress.
+pub(crate) struct BuildCache {
+    pub(crate) prev: PrevBuild,
+    pub(crate) new_entries: Vec<CacheEntry>,
+}
+
+pub(crate) struct PrevBuild {
+    part_paths: Vec<PathBuf>,
+    entries: HashMap<String, CacheEntry>,
+}
+
+impl BuildCache {
+    /// Loads the cache left next to a package by a previous incremental build. A missing,
+    /// corrupt or incompatible cache is treated as empty, since it can always be rebuilt.
+    pub(crate) fn load(dir: &Path, options: &PackingOptions) -> BuildCache {
+        let mut cache = BuildCache {
+            prev: PrevBuild { part_paths: Vec::new(), entries: HashMap::new() },
+            new_entries: Vec::new(),
+        };
+
+        let Ok(data) = fs::read(cache_path(dir, &options.name)) else {
+            return cache;
+        };
+        if let Some((total_parts, entries)) = parse_cache(&data, options) {
+            cache.prev.part_paths = (1..=total_parts as usize)
+                .map(|i| dir.join(part_file_name(&options.name, i, total_parts as usize)))
+                .collect();
+            cache.prev.entries = entries.into_iter()
+                .map(|entry| (entry.key.path.clone(), entry))
+                .collect();
+        }
+        cache
+    }
+
+    /// Writes the entries for the build in progress next to the package.
+    pub(crate) fn save(&self, dir: &Path, options: &PackingOptions, total_parts: u16)
+        -> Result<(), String> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&CACHE_MAGIC);
+        push_u16_le(&mut buf, CACHE_FORMAT_VERSION);
+        push_settings(&mut buf, options);
+        push_u16_le(&mut buf, total_parts);
+        push_u32_le(&mut buf, self.new_entries.len() as u32);
+        for entry in &self.new_entries {
+            push_str(&mut buf, &entry.key.path);
+            push_u64_le(&mut buf, entry.key.size);
+            push_u64_le(&mut buf, entry.key.mtime);
+            push_str(&mut buf, &entry.media_type);
+            buf.push(entry.compress as u8);
+            push_u32_le(&mut buf, entry.content_crc);
+            push_u16_le(&mut buf, entry.part);
+            push_u64_le(&mut buf, entry.offset);
+            push_u64_le(&mut buf, entry.packed_len);
+            push_u64_le(&mut buf, entry.unpacked_len);
+            push_u32_le(&mut buf, entry.crc);
+            buf.push(entry.flags);
+        }
+
+        // written to a temp file first so a failure can't leave a truncated cache behind
+        let temp_path = dir.join(format!(".{}.{}.tmp", Uuid::new_v4(), CACHE_FILE_EXT));
+        let res = File::create_new(&temp_path)
+            .and_then(|mut file| file.write_all(&buf))
+            .and_then(|_| fs::rename(&temp_path, cache_path(dir, &options.name)));
+        if res.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        res.map_err(|e| e.to_string())
+    }
+}
+
+impl PrevBuild {
+    /// Returns the entry for a file which is unchanged since the previous build. If the file's
+    /// modification time differs but its size doesn't, its contents are compared.
+    pub(crate) fn lookup(&self, key: &CacheKey, media_type: &str, compress: bool)
+        -> Result<Option<&CacheEntry>, String> {
+        let Some(entry) = self.entries.get(&key.path) else {
+            return Ok(None);
+        };
+        if entry.key.size != key.size
+            || entry.media_type != media_type
+            || entry.compress != compress {
+            return Ok(None);
+        }
+
+        if key.mtime != 0 && entry.key.mtime == key.mtime {
+            return Ok(Some(entry));
+        }
+
+        let content_crc = file_crc(Path::new(&key.path))?;
+        Ok((content_crc == entry.content_crc).then_some(entry))
+    }
+
+    /// Opens the part file containing an entry's packed data, positioned at its start.
+    pub(crate) fn open_packed(&self, entry: &CacheEntry) -> Option<File> {
+        let path = self.part_paths.get(entry.part as usize - 1)?;
+        let mut file = File::open(path).ok()?;
+        file.seek(SeekFrom::Start(entry.offset)).ok()?;
+        Some(file)
+    }
+
+    /// Reads an entry's packed data from the previous build, or returns `None` if it's no
+    /// longer intact.
+    pub(crate) fn read_packed(&self, entry: &CacheEntry) -> Option<Vec<u8>> {
+        let mut data = Vec::with_capacity(entry.packed_len as usize);
+        self.open_packed(entry)?.take(entry.packed_len).read_to_end(&mut data).ok()?;
+        (data.len() as u64 == entry.packed_len && crc32c_continue(0, &data) == entry.crc)
+            .then_some(data)
+    }
+}
+
+/// Removes the cache left by a previous incremental build, which no longer describes the
+/// package once it's rebuilt without it.
+pub(crate) fn remove_cache(dir: &Path, name: &str) -> Result<(), String> {
+    match fs::remove_file(cache_path(dir, name)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.to_string()),
+        _ => Ok(()),
+    }
+}
+
+fn cache_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.{}", name, CACHE_FILE_EXT))
+}
+
+fn file_crc(path: &Path) -> Result<u32, String> {
+    let mut file = File::open(path).map_err(|e| e.to_string())?;
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut crc = 0;
+    loop {
+        let read = file.read(&mut buf).map_err(|e| e.to_string())?;
+        if read == 0 {
+            return Ok(crc);
+        }
+        crc = crc32c_continue(crc, &buf[..read]);
+    }
+}
+
+// the options which affect how a given file is packed
+fn push_settings(buf: &mut Vec<u8>, options: &PackingOptions) {
+    push_u16_le(buf, options.version);
+    buf.extend_from_slice(&options.compression_type.map(|c| c.get_magic()).unwrap_or([0; 2]));
+    buf.push(options.compression_level.get());
+}
+
+fn push_str(buf: &mut Vec<u8>, s: &str) {
+    push_u32_le(buf, s.len() as u32);
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn parse_cache(data: &[u8], options: &PackingOptions) -> Option<(u16, Vec<CacheEntry>)> {
+    let mut reader = ByteReader(data);
+    if reader.take(CACHE_MAGIC.len())? != CACHE_MAGIC
+        || reader.u16()? != CACHE_FORMAT_VERSION {
+        return None;
+    }
+
+    let mut settings = Vec::new();
+    push_settings(&mut settings, options);
+    if reader.take(settings.len())? != settings {
+        return None;
+    }
+
+    let total_parts = reader.u16()?;
+    let entry_count = reader.u32()?;
+    let mut entries = Vec::new();
+    for _ in 0..entry_count {
+        let key = CacheKey { path: reader.str()?, size: reader.u64()?, mtime: reader.u64()? };
+        entries.push(CacheEntry {
+            key,
+            media_type: reader.str()?,
+            compress: reader.u8()? != 0,
+            content_crc: reader.u32()?,
+            part: reader.u16()?.max(1),
+            offset: reader.u64()?,
+            packed_len: reader.u64()?,
+            unpacked_len: reader.u64()?,
+            crc: reader.u32()?,
+            flags: reader.u8()?,
+        });
+    }
+
+    Some((total_parts, entries))
+}
+
+struct ByteReader<'a>(&'a [u8]);
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.take(2)?.try_into().ok()?))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn str(&mut self) -> Option<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).ok()
+    }
+}
@@ -0,0 +1,169 @@
+use std::io::{ErrorKind, Read, Write};
+use miniz_oxide::deflate;
+use miniz_oxide::deflate::core::{
+    compress, create_comp_flags_from_zip_params, CompressorOxide, TDEFLFlush, TDEFLStatus,
+};
+use miniz_oxide::inflate::stream::{inflate, InflateState};
+use miniz_oxide::{DataFormat, MZError, MZFlush, MZStatus};
+use crate::codec::{CodecMagic, CompressionLevel, Compressor, Decompressor};
+use crate::defines::COMPRESS_MAGIC_DEFLATE;
+
+// any positive value causes a zlib header and trailer to be emitted
+const ZLIB_WINDOW_BITS: i32 = 1;
+const STREAM_BUF_LEN: usize = 64 * 1024;
+
+pub struct DeflateCodec;
+
+impl Compressor for DeflateCodec {
+    fn magic(&self) -> CodecMagic {
+        COMPRESS_MAGIC_DEFLATE
+    }
+
+    fn compress(&self, data: &[u8], level: CompressionLevel) -> Result<Vec<u8>, String> {
+        Ok(deflate::compress_to_vec_zlib(data, level.get()))
+    }
+
+    fn compress_stream(
+        &self,
+        reader: &mut dyn Read,
+        writer: &mut dyn Write,
+        level: CompressionLevel,
+    ) -> Result<(), String> {
+        let flags = create_comp_flags_from_zip_params(level.get() as i32, ZLIB_WINDOW_BITS, 0);
+        let mut compressor = CompressorOxide::new(flags);
+        let mut in_buf = vec![0u8; STREAM_BUF_LEN];
+        let mut out_buf = vec![0u8; STREAM_BUF_LEN];
+
+        loop {
+            let in_len = match reader.read(&mut in_buf) {
+                Ok(len) => len,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.to_string()),
+            };
+            // an empty read signals the end of the input
+            let flush = if in_len == 0 { TDEFLFlush::Finish } else { TDEFLFlush::None };
+
+            let mut input = &in_buf[..in_len];
+            loop {
+                let (status, bytes_in, bytes_out) =
+                    compress(&mut compressor, input, &mut out_buf, flush);
+                writer.write_all(&out_buf[..bytes_out]).map_err(|e| e.to_string())?;
+                input = &input[bytes_in..];
+
+                match status {
+                    TDEFLStatus::Done => return Ok(()),
+                    TDEFLStatus::Okay => {
+                        if input.is_empty() && flush == TDEFLFlush::None {
+                            break;
+                        }
+                    }
+                    _ => return Err(format!("Failed to compress DEFLATE stream ({:?})", status)),
+                }
+            }
+        }
+    }
+}
+
+impl Decompressor for DeflateCodec {
+    fn magic(&self) -> CodecMagic {
+        COMPRESS_MAGIC_DEFLATE
+    }
+
+    fn decompress(&self, data: &[u8], unpacked_len: u64) -> Result<Vec<u8>, String> {
+        let mut inflate_state = InflateState::new(DataFormat::Zlib);
+        let mut unpacked_data = Vec::with_capacity(unpacked_len as usize);
+        let mut input_off = 0;
+        let mut output_buf = [0u8; 4096];
+        loop {
+            let result = inflate(
+                &mut inflate_state,
+                &data[input_off..],
+                &mut output_buf,
+                MZFlush::None,
+            );
+
+            let status = result.status.map_err(|e| format!("{:?}", e))?;
+            input_off += result.bytes_consumed;
+            if (unpacked_data.len() + result.bytes_written) as u64 > unpacked_len {
+                return Err("Expected end of DEFLATE stream".to_owned());
+            }
+
+            unpacked_data.extend_from_slice(&output_buf[0..result.bytes_written]);
+
+            if status == MZStatus::StreamEnd {
+                break;
+            }
+        }
+
+        if (unpacked_data.len() as u64) < unpacked_len {
+            return Err("Encountered premature end of DEFLATE stream".to_owned());
+        }
+
+        Ok(unpacked_data)
+    }
+
+    fn decompress_stream(
+        &self,
+        reader: &mut dyn Read,
+        writer: &mut dyn Write,
+        unpacked_len: u64,
+    ) -> Result<(), String> {
+        let mut inflate_state = InflateState::new_boxed(DataFormat::Zlib);
+        let mut in_buf = vec![0u8; STREAM_BUF_LEN];
+        let mut out_buf = vec![0u8; STREAM_BUF_LEN];
+        // the unconsumed portion of the input buffer
+        let (mut in_start, mut in_end) = (0, 0);
+        // inflate may still hold output after consuming all of its input if the output buffer
+        // filled up, in which case it has to be called again before more input is read
+        let mut output_pending = false;
+        let mut written = 0u64;
+
+        loop {
+            if in_start == in_end && !output_pending {
+                let in_len = match reader.read(&mut in_buf) {
+                    Ok(len) => len,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e.to_string()),
+                };
+                if in_len == 0 {
+                    return Err("Encountered premature end of DEFLATE stream".to_owned());
+                }
+                (in_start, in_end) = (0, in_len);
+            }
+
+            let result = inflate(
+                &mut inflate_state,
+                &in_buf[in_start..in_end],
+                &mut out_buf,
+                MZFlush::None,
+            );
+            let status = match result.status {
+                Ok(status) => status,
+                // no progress could be made without more input
+                Err(MZError::Buf) if output_pending => {
+                    output_pending = false;
+                    continue;
+                }
+                Err(e) => return Err(format!("{:?}", e)),
+            };
+            in_start += result.bytes_consumed;
+            output_pending = result.bytes_written == out_buf.len();
+            written += result.bytes_written as u64;
+            if written > unpacked_len {
+                return Err("Expected end of DEFLATE stream".to_owned());
+            }
+
+            writer.write_all(&out_buf[..result.bytes_written]).map_err(|e| e.to_string())?;
+
+            if status == MZStatus::StreamEnd {
+                break;
+            }
+        }
+
+        if written < unpacked_len {
+            return Err("Encountered premature end of DEFLATE stream".to_owned());
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+mod deflate;
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::sync::Arc;
+
+pub use deflate::DeflateCodec;
+
+/// The two-byte magic identifying a codec in a package header.
+pub type CodecMagic = [u8; 2];
+
+/// A codec-agnostic compression level, ranging from 0 (fastest) to 9 (smallest output).
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct CompressionLevel(u8);
+
+impl CompressionLevel {
+    pub const FASTEST: CompressionLevel = CompressionLevel(0);
+    pub const BEST: CompressionLevel = CompressionLevel(9);
+    /// The level used unless another is configured. Favors output size, since packages are
+    /// typically written once and read many times.
+    pub const DEFAULT: CompressionLevel = Self::BEST;
+
+    pub fn new(level: u8) -> Result<CompressionLevel, String> {
+        if level > Self::BEST.0 {
+            return Err(format!("Compression level must be between 0 and {}", Self::BEST.0));
+        }
+
+        Ok(CompressionLevel(level))
+    }
+
+    pub fn get(&self) -> u8 {
+        self.0
+    }
+}
+
+impl Default for CompressionLevel {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+pub trait Compressor: Send + Sync {
+    fn magic(&self) -> CodecMagic;
+
+    fn compress(&self, data: &[u8], level: CompressionLevel) -> Result<Vec<u8>, String>;
+
+    /// Compresses the entire contents of `reader` into `writer`.
+    ///
+    /// The default implementation buffers the whole input in memory, so codecs should override
+    /// it if they are able to compress incrementally.
+    fn compress_stream(
+        &self,
+        reader: &mut dyn Read,
+        writer: &mut dyn Write,
+        level: CompressionLevel,
+    ) -> Result<(), String> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).map_err(|e| e.to_string())?;
+        let compressed = self.compress(&data, level)?;
+        writer.write_all(&compressed).map_err(|e| e.to_string())
+    }
+}
+
+pub trait Decompressor: Send + Sync {
+    fn magic(&self) -> CodecMagic;
+
+    /// Decompresses `data`, which is expected to expand to exactly `unpacked_len` bytes.
+    fn decompress(&self, data: &[u8], unpacked_len: u64) -> Result<Vec<u8>, String>;
+
+    /// Decompresses the entire contents of `reader` into `writer`, which is expected to
+    /// receive exactly `unpacked_len` bytes.
+    ///
+    /// The default implementation buffers the whole input and output in memory, so codecs
+    /// should override it if they are able to decompress incrementally.
+    fn decompress_stream(
+        &self,
+        reader: &mut dyn Read,
+        writer: &mut dyn Write,
+        unpacked_len: u64,
+    ) -> Result<(), String> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).map_err(|e| e.to_string())?;
+        let decompressed = self.decompress(&data, unpacked_len)?;
+        writer.write_all(&decompressed).map_err(|e| e.to_string())
+    }
+}
+
+/// A set of compressors and decompressors keyed by their header magic.
+///
+/// The default registry contains the DEFLATE codec.
+#[derive(Clone)]
+pub struct CodecRegistry {
+    compressors: HashMap<CodecMagic, Arc<dyn Compressor>>,
+    decompressors: HashMap<CodecMagic, Arc<dyn Decompressor>>,
+}
+
+impl CodecRegistry {
+    pub fn empty() -> Self {
+        Self {
+            compressors: HashMap::new(),
+            decompressors: HashMap::new(),
+        }
+    }
+
+    pub fn register_compressor(&mut self, compressor: Arc<dyn Compressor>) -> Result<(), String> {
+        let magic = compressor.magic();
+        validate_magic(&magic)?;
+        self.compressors.insert(magic, compressor);
+        Ok(())
+    }
+
+    pub fn register_decompressor(&mut self, decompressor: Arc<dyn Decompressor>)
+        -> Result<(), String> {
+        let magic = decompressor.magic();
+        validate_magic(&magic)?;
+        self.decompressors.insert(magic, decompressor);
+        Ok(())
+    }
+
+    pub fn get_compressor(&self, magic: &CodecMagic) -> Result<Arc<dyn Compressor>, String> {
+        self.compressors.get(magic)
+            .cloned()
+            .ok_or_else(|| format!("Compression codec '{}' is not registered", magic_str(magic)))
+    }
+
+    pub fn get_decompressor(&self, magic: &CodecMagic) -> Result<Arc<dyn Decompressor>, String> {
+        self.decompressors.get(magic)
+            .cloned()
+            .ok_or_else(|| format!("Decompression codec '{}' is not registered", magic_str(magic)))
+    }
+}
+
+impl Default for CodecRegistry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        let deflate = Arc::new(DeflateCodec);
+        registry.compressors.insert(Compressor::magic(deflate.as_ref()), deflate.clone());
+        registry.decompressors.insert(Decompressor::magic(deflate.as_ref()), deflate);
+        registry
+    }
+}
+
+fn validate_magic(magic: &CodecMagic) -> Result<(), String> {
+    // a leading null byte in the header denotes an uncompressed package
+    if magic[0] == 0 {
+        return Err("Codec magic cannot begin with a null byte".to_owned());
+    }
+
+    Ok(())
+}
+
+fn magic_str(magic: &CodecMagic) -> String {
+    String::from_utf8_lossy(magic).into_owned()
+}
@@ -5,12 +5,21 @@ pub(crate) const FORMAT_MAGIC: [u8; 8] = [0x1B, 0x41, 0x52, 0x47, 0x55, 0x53, 0x
 pub(crate) const PART_MAGIC: [u8; 8] = [0x1B, 0x41, 0x52, 0x47, 0x55, 0x53, 0x50, 0x54];
 
 pub const COMPRESS_TYPE_DEFLATE: &str = "deflate";
-pub(crate) const COMPRESS_MAGIC_DEFLATE: &str = "df";
+pub(crate) const COMPRESS_MAGIC_DEFLATE: [u8; 2] = *b"df";
+
+pub(crate) const FORMAT_VERSION_MIN: u16 = 1;
+pub(crate) const FORMAT_VERSION_MAX: u16 = 2;
 
 pub(crate) const PACKAGE_PART_1_SUFFIX: &str = ".part001";
 
 pub(crate) const PACK_NODE_TYPE_RESOURCE: u8 = 0;
 pub(crate) const PACK_NODE_TYPE_DIRECTORY: u8 = 1;
+// only valid in patch packages
+pub(crate) const PACK_NODE_TYPE_TOMBSTONE: u8 = 2;
+
+// package kinds (v2+)
+pub(crate) const PACKAGE_KIND_BASE: u8 = 0;
+pub(crate) const PACKAGE_KIND_PATCH: u8 = 1;
 
 // package header constants
 pub(crate) const PACKAGE_HEADER_LEN: u64 = 0x100;
@@ -27,7 +36,9 @@ pub(crate) const PACK_HEADER_DIR_CNT_LEN: usize = 4;
 pub(crate) const PACK_HEADER_RES_CNT_LEN: usize = 4;
 pub(crate) const PACK_HEADER_BODY_OFF_LEN: usize = 8;
 pub(crate) const PACK_HEADER_BODY_LEN_LEN: usize = 8;
-pub(crate) const PACK_HEADER_RESERVED_1_LEN: usize = 0x96;
+// v2+ only
+pub(crate) const PACK_HEADER_KIND_LEN: usize = 1;
+pub(crate) const PACK_HEADER_RESERVED_1_LEN: usize = 0x95;
 
 pub(crate) const PACK_HEADER_MAGIC_OFF: usize = 0x00;
 pub(crate) const PACK_HEADER_VERSION_OFF: usize = 0x08;
@@ -41,7 +52,8 @@ pub(crate) const PACK_HEADER_DIR_CNT_OFF: usize = 0x52;
 pub(crate) const PACK_HEADER_RES_CNT_OFF: usize = 0x56;
 pub(crate) const PACK_HEADER_BODY_OFF_OFF: usize = 0x5A;
 pub(crate) const PACK_HEADER_BODY_LEN_OFF: usize = 0x62;
-pub(crate) const PACK_HEADER_RESERVED_1_OFF: usize = 0x6A;
+pub(crate) const PACK_HEADER_KIND_OFF: usize = 0x6A;
+pub(crate) const PACK_HEADER_RESERVED_1_OFF: usize = 0x6B;
 
 pub(crate) const PACK_HEADER_MAGIC_END_OFF: usize =
     PACK_HEADER_MAGIC_OFF + PACK_HEADER_MAGIC_LEN;
@@ -67,6 +79,8 @@ pub(crate) const PACK_HEADER_BODY_OFF_END_OFF: usize =
     PACK_HEADER_BODY_OFF_OFF + PACK_HEADER_BODY_OFF_LEN;
 pub(crate) const PACK_HEADER_BODY_LEN_END_OFF: usize =
     PACK_HEADER_BODY_LEN_OFF + PACK_HEADER_BODY_LEN_LEN;
+pub(crate) const PACK_HEADER_KIND_END_OFF: usize =
+    PACK_HEADER_KIND_OFF + PACK_HEADER_KIND_LEN;
 pub(crate) const PACK_HEADER_RESERVED_1_END_OFF: usize =
     PACK_HEADER_RESERVED_1_OFF + PACK_HEADER_RESERVED_1_LEN;
 
@@ -91,6 +105,8 @@ pub(crate) const ND_CRC_LEN: usize = 4;
 pub(crate) const ND_NAME_LEN_LEN: usize = 1;
 pub(crate) const ND_EXT_LEN_LEN: usize = 1;
 pub(crate) const ND_MT_LEN_LEN: usize = 1;
+// v2+ only
+pub(crate) const ND_FLAGS_LEN: usize = 1;
 
 pub(crate) const ND_LEN_OFF: usize = 0x00;
 pub(crate) const ND_TYPE_OFF: usize = 0x02;
@@ -103,14 +119,22 @@ pub(crate) const ND_NAME_LEN_OFF: usize = 0x21;
 pub(crate) const ND_EXT_LEN_OFF: usize = 0x22;
 pub(crate) const ND_MT_LEN_OFF: usize = 0x23;
 pub(crate) const ND_NAME_OFF: usize = 0x24;
+// v2+ descriptors insert a flags field before the name
+pub(crate) const ND_FLAGS_OFF: usize = 0x24;
+pub(crate) const ND_NAME_OFF_V2: usize = 0x25;
 
 pub(crate) const NODE_DESC_BASE_LEN: usize = ND_NAME_OFF;
+pub(crate) const NODE_DESC_BASE_LEN_V2: usize = ND_NAME_OFF_V2;
+
+// node flags (v2+)
+// node data is stored raw even if the package specifies a compression type
+pub(crate) const ND_FLAG_UNCOMPRESSED: u8 = 0x01;
 
 pub(crate) const NODE_NAME_MAX_LEN: usize = 0xFF;
 pub(crate) const NODE_EXT_MAX_LEN: usize = 0xFF;
 pub(crate) const NODE_MT_MAX_LEN: usize = 0xFF;
 pub(crate) const NODE_DESC_MAX_LEN: usize =
-    NODE_DESC_BASE_LEN + NODE_NAME_MAX_LEN + NODE_EXT_MAX_LEN + NODE_MT_MAX_LEN;
+    NODE_DESC_BASE_LEN_V2 + NODE_NAME_MAX_LEN + NODE_EXT_MAX_LEN + NODE_MT_MAX_LEN;
 
 // the length of an index to a node descriptor
 // directory nodes contain an array of node descriptor indices in their body
@@ -0,0 +1,144 @@
+use std::collections::BTreeMap;
+use crate::defines::UID_PATH_SEPARATOR;
+use crate::package::ResourceNode;
+use crate::Package;
+
+/// The differences between two packages, as computed by [diff].
+///
+/// Resources are identified by their path within the package, excluding the namespace, so that
+/// packages with different namespaces can still be compared. All lists are sorted by path.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PackageDiff {
+    pub header: Vec<HeaderChange>,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub modified: Vec<ModifiedResource>,
+}
+
+/// A header field which differs between two packages.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HeaderChange {
+    pub field: &'static str,
+    pub old: String,
+    pub new: String,
+}
+
+/// A resource present in both packages whose contents or metadata differ.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ModifiedResource {
+    pub path: String,
+    pub content_changed: bool,
+    pub old_size: u64,
+    pub new_size: u64,
+    pub old_extension: String,
+    pub new_extension: String,
+    pub old_media_type: String,
+    pub new_media_type: String,
+}
+
+impl PackageDiff {
+    pub fn is_empty(&self) -> bool {
+        self.header.is_empty()
+            && self.added.is_empty()
+            && self.removed.is_empty()
+            && self.modified.is_empty()
+    }
+}
+
+/// Compares two packages.
+///
+/// Resources stored the same way in both packages are compared by their CRC and size. If their
+/// sizes match but their CRCs don't, e.g. because they were compressed differently, their
+/// unpacked contents are compared instead.
+pub fn diff(old: &Package, new: &Package) -> Result<PackageDiff, String> {
+    let mut result = PackageDiff::default();
+
+    let mut push_header = |field, old: String, new: String| {
+        if old != new {
+            result.header.push(HeaderChange { field, old, new });
+        }
+    };
+    push_header("version", old.meta.major_version.to_string(), new.meta.major_version.to_string());
+    push_header("namespace", old.meta.namespace.clone(), new.meta.namespace.clone());
+    push_header("compression", compression_name(old), compression_name(new));
+    push_header("parts", old.meta.total_parts.to_string(), new.meta.total_parts.to_string());
+    push_header("kind", format!("{:?}", old.meta.kind), format!("{:?}", new.meta.kind));
+
+    let old_resources = resources_by_path(old);
+    let new_resources = resources_by_path(new);
+
+    for (path, &old_index) in &old_resources {
+        let Some(&new_index) = new_resources.get(path) else {
+            result.removed.push(path.clone());
+            continue;
+        };
+
+        let old_node = &old.catalogue.resources[&old_index];
+        let new_node = &new.catalogue.resources[&new_index];
+        let content_changed = !same_content(old, old_index, new, new_index)?;
+        if content_changed
+            || old_node.ext != new_node.ext
+            || old_node.media_type != new_node.media_type {
+            result.modified.push(ModifiedResource {
+                path: path.clone(),
+                content_changed,
+                old_size: old_node.data_len_unpacked,
+                new_size: new_node.data_len_unpacked,
+                old_extension: old_node.ext.clone(),
+                new_extension: new_node.ext.clone(),
+                old_media_type: old_node.media_type.clone(),
+                new_media_type: new_node.media_type.clone(),
+            });
+        }
+    }
+
+    result.added = new_resources.into_keys()
+        .filter(|path| !old_resources.contains_key(path))
+        .collect();
+
+    Ok(result)
+}
+
+pub(crate) fn resources_by_path(package: &Package) -> BTreeMap<String, u32> {
+    package.resource_paths().into_iter()
+        .map(|(components, index)| (components.join(&UID_PATH_SEPARATOR.to_string()), index))
+        .collect()
+}
+
+fn compression_name(package: &Package) -> String {
+    match package.meta.compression_type {
+        Some(compression_type) => {
+            String::from_utf8_lossy(&compression_type.get_magic()).into_owned()
+        }
+        None => "none".to_owned(),
+    }
+}
+
+fn same_content(old: &Package, old_index: u32, new: &Package, new_index: u32)
+    -> Result<bool, String> {
+    let old_node = &old.catalogue.resources[&old_index];
+    let new_node = &new.catalogue.resources[&new_index];
+
+    if old_node.data_len_unpacked != new_node.data_len_unpacked {
+        return Ok(false);
+    }
+
+    if same_storage(old, old_node, new, new_node)
+        && old_node.data_len_packed == new_node.data_len_packed
+        && old_node.crc == new_node.crc {
+        return Ok(true);
+    }
+
+    Ok(old.load_resource_data(old_index)? == new.load_resource_data(new_index)?)
+}
+
+// whether identical contents would have been packed into identical bytes, barring differences
+// in compression level
+fn same_storage(old: &Package, old_node: &ResourceNode, new: &Package, new_node: &ResourceNode)
+    -> bool {
+    match (old_node.is_compressed(&old.meta), new_node.is_compressed(&new.meta)) {
+        (false, false) => true,
+        (true, true) => old.meta.compression_type == new.meta.compression_type,
+        _ => false,
+    }
+}
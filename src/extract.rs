@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use crate::util::uid::validate_path_component;
+use crate::{MediaTypeRegistry, ResourceDescriptor, DEFAULT_MEDIA_TYPE};
+
+/// How extraction treats files which already exist.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ExistingFilePolicy {
+    #[default]
+    Overwrite,
+    /// The existing file is left in place and the resource is not written.
+    Skip,
+    /// Extraction fails.
+    Error,
+}
+
+pub struct ExtractOptions {
+    existing: ExistingFilePolicy,
+    flatten: bool,
+    media_types: MediaTypeRegistry,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        Self {
+            existing: ExistingFilePolicy::default(),
+            flatten: false,
+            media_types: MediaTypeRegistry::builtin(),
+        }
+    }
+}
+
+impl ExtractOptions {
+    pub fn with_existing(mut self, existing: ExistingFilePolicy) -> Self {
+        self.existing = existing;
+        self
+    }
+
+    /// Sets whether resources are written directly into the target directory rather than
+    /// being nested according to their UID paths.
+    pub fn with_flatten(mut self, flatten: bool) -> Self {
+        self.flatten = flatten;
+        self
+    }
+
+    /// Sets the registry used to pick an extension for resources packed without one.
+    pub fn with_media_types(mut self, media_types: MediaTypeRegistry) -> Self {
+        self.media_types = media_types;
+        self
+    }
+
+    /// Returns the name of the file a resource is extracted to.
+    pub fn file_name(&self, resource: &ResourceDescriptor) -> String {
+        // resources packed without an extension get the preferred one for their media type
+        let ext = if !resource.extension.is_empty() {
+            Some(resource.extension.as_str())
+        } else if resource.media_type != DEFAULT_MEDIA_TYPE {
+            self.media_types.get_extension(&resource.media_type)
+        } else {
+            None
+        };
+        match ext {
+            Some(ext) => format!("{}.{}", resource.name, ext),
+            None => resource.name.clone(),
+        }
+    }
+}
+
+/// Writes each resource to a file beneath `dir`, creating directories as needed. Returns the
+/// paths of the files which were written.
+///
+/// Every path component is checked before anything is written, so a resource can never be
+/// written outside of `dir`, even if the package it came from was crafted to try. Likewise,
+/// nothing is written if two resources would be extracted to the same file, as can happen
+/// when flattening, or if one would be extracted to a path another needs as a directory.
+pub fn extract_to_dir(
+    resources: &[ResourceDescriptor],
+    dir: impl AsRef<Path>,
+    options: &ExtractOptions,
+) -> Result<Vec<PathBuf>, String> {
+    let dir = dir.as_ref();
+
+    let out_paths = resources.iter()
+        .map(|resource| {
+            let file_name = options.file_name(resource);
+            validate_path_component(&file_name)?;
+
+            let mut out_path = dir.to_owned();
+            if !options.flatten {
+                let components = &resource.identifier.components;
+                for component in &components[..(components.len() - 1)] {
+                    validate_path_component(component)?;
+                    out_path.push(component);
+                }
+            }
+            out_path.push(file_name);
+            Ok(out_path)
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let mut claimed = HashMap::new();
+    for (resource, out_path) in resources.iter().zip(&out_paths) {
+        if let Some(other) = claimed.insert(out_path, resource) {
+            return Err(format!(
+                "Resources {} and {} would both be extracted to '{}'",
+                other.identifier,
+                resource.identifier,
+                out_path.display(),
+            ));
+        }
+    }
+    for (resource, out_path) in resources.iter().zip(&out_paths) {
+        let parent = out_path.ancestors().skip(1)
+            .take_while(|path| *path != dir)
+            .find_map(|path| claimed.get_key_value(&path.to_path_buf()));
+        if let Some((parent_path, other)) = parent {
+            return Err(format!(
+                "Resource {} would be extracted beneath '{}', where resource {} would be extracted",
+                resource.identifier,
+                parent_path.display(),
+                other.identifier,
+            ));
+        }
+    }
+
+    let mut written = Vec::new();
+    for (resource, out_path) in resources.iter().zip(out_paths) {
+        if extract_resource(resource, &out_path, options)? {
+            written.push(out_path);
+        }
+    }
+
+    Ok(written)
+}
+
+/// Writes a resource to the file at `path`, creating its parent directories as needed. Returns
+/// whether the file was written, which it may not be if it already exists.
+///
+/// The data is streamed into the file rather than loaded into memory first. If it turns out to
+/// be corrupt, the partially written file is removed.
+pub fn extract_resource(
+    resource: &ResourceDescriptor,
+    path: impl AsRef<Path>,
+    options: &ExtractOptions,
+) -> Result<bool, String> {
+    let path = path.as_ref();
+
+    if path.exists() {
+        match options.existing {
+            ExistingFilePolicy::Overwrite => {}
+            ExistingFilePolicy::Skip => return Ok(false),
+            ExistingFilePolicy::Error => {
+                return Err(format!("File '{}' already exists", path.display()));
+            }
+        }
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let mut file = BufWriter::new(File::create(path).map_err(|e| e.to_string())?);
+    let res = resource.write_to(&mut file)
+        .and_then(|_| file.flush().map_err(|e| e.to_string()));
+    if res.is_err() {
+        drop(file);
+        let _ = fs::remove_file(path);
+    }
+    res.map(|_| true)
+}
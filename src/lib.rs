@@ -1,15 +1,36 @@
+mod builder;
+mod cache;
+mod codec;
 mod defines;
+mod diff;
+mod extract;
 mod mappings;
+mod merge;
 mod pack;
 mod package;
+mod patch;
+mod repack;
 mod resource;
 mod set;
+mod sniff;
 mod types;
+mod update;
 mod util;
+mod verify;
 
+pub use builder::*;
+pub use codec::*;
+pub use diff::*;
+pub use extract::*;
 pub use mappings::*;
+pub use merge::*;
 pub use pack::*;
 pub use package::*;
+pub use patch::*;
+pub use repack::*;
 pub use resource::*;
 pub use set::*;
+pub use sniff::*;
 pub use types::*;
+pub use update::*;
+pub use verify::*;
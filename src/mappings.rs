@@ -1,19 +1,213 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::{env, fs, io};
+use std::path::{Path, PathBuf};
 
+#[cfg(feature = "builtin-media-types")]
 const MAPPINGS_CONTENT: &str = include_str!(concat!(env!("OUT_DIR"), "/generated/media_types.csv"));
+#[cfg(not(feature = "builtin-media-types"))]
+const MAPPINGS_CONTENT: &str = "";
+
+// where the system MIME database is found on Unix-like systems
+const SYSTEM_MIME_TYPES_PATH: &str = "/etc/mime.types";
+const XDG_DATA_HOME_DEFAULT_REL_PATH: &str = ".local/share";
+const XDG_DATA_DIRS_DEFAULT: &str = "/usr/local/share:/usr/share";
+const GLOBS2_REL_PATH: &str = "mime/globs2";
 
 pub fn load_arp_builtin_media_types() -> HashMap<&'static str, &'static str> {
     load_media_types_from_csv(MAPPINGS_CONTENT)
 }
 
+/// Parses lines of the form `extension,media/type`. Blank lines, lines beginning with `#` and
+/// lines without a comma are skipped.
 pub fn load_media_types_from_csv(content: &str) -> HashMap<&str, &str> {
     content.lines()
         .filter_map(|line| {
             let line = line.trim_ascii();
-            if line.is_empty() {
+            if line.is_empty() || line.starts_with('#') {
                 return None;
             }
             line.split_once(',')
         })
         .collect()
 }
+
+/// Maps file extensions to media types and back.
+///
+/// Mappings are loaded in layers, with each layer taking precedence over those loaded before it.
+/// Extensions are matched case-insensitively. The preferred extension for a media type is the
+/// first one listed for it by the most recent layer to mention it.
+#[derive(Clone, Debug, Default)]
+pub struct MediaTypeRegistry {
+    // keyed by lowercase extension
+    media_types: HashMap<String, String>,
+    // keyed by lowercase media type
+    preferred_exts: HashMap<String, String>,
+}
+
+impl MediaTypeRegistry {
+    /// Creates a registry with no mappings.
+    pub fn empty() -> Self {
+        Self::default()
+    }
+
+    /// Creates a registry containing the mappings built into the library, or no mappings if the
+    /// `builtin-media-types` feature is disabled.
+    pub fn builtin() -> Self {
+        let mut registry = Self::empty();
+        registry.add_layer(MAPPINGS_CONTENT.lines().filter_map(|line| line.split_once(',')));
+        registry
+    }
+
+    /// Adds a layer of mappings from CSV content, where each line takes the form
+    /// `extension,media/type`. Blank lines and lines beginning with `#` are ignored.
+    pub fn load_csv(&mut self, content: &str) -> Result<(), String> {
+        let mut mappings = Vec::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim_ascii();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let Some((ext, media_type)) = line.split_once(',') else {
+                return Err(format!("Malformed media type mapping on line {}", i + 1));
+            };
+            mappings.push((ext.trim_ascii(), media_type.trim_ascii()));
+        }
+
+        self.add_layer(mappings);
+        Ok(())
+    }
+
+    pub fn load_csv_file(&mut self, path: impl AsRef<Path>) -> Result<(), String> {
+        let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        self.load_csv(&content)
+    }
+
+    /// Adds a layer of mappings from content in the format of Apache's `mime.types`, where each
+    /// line lists a media type followed by its extensions. Lines beginning with `#` are ignored.
+    pub fn load_mime_types(&mut self, content: &str) {
+        let mappings = content.lines()
+            .map(|line| line.trim_ascii())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .flat_map(|line| {
+                let mut fields = line.split_ascii_whitespace();
+                let media_type = fields.next().unwrap_or_default();
+                fields.map(move |ext| (ext, media_type))
+            });
+        self.add_layer(mappings);
+    }
+
+    pub fn load_mime_types_file(&mut self, path: impl AsRef<Path>) -> Result<(), String> {
+        let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        self.load_mime_types(&content);
+        Ok(())
+    }
+
+    /// Adds a layer of mappings from content in the format of a shared-mime-info `globs2` file,
+    /// where each line takes the form `weight:media/type:glob`. Only globs matching a simple
+    /// extension, e.g. `*.png`, are used, and those with higher weights take precedence.
+    pub fn load_globs2(&mut self, content: &str) {
+        let mut mappings: Vec<(u32, &str, &str)> = content.lines()
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let mut fields = line.split(':');
+                let weight = fields.next()?.parse().ok()?;
+                let media_type = fields.next()?;
+                let ext = fields.next()?.strip_prefix("*.")?;
+                if ext.is_empty() || ext.contains(['*', '?', '[', '.']) {
+                    return None;
+                }
+                Some((weight, ext, media_type))
+            })
+            .collect();
+        // later mappings win, so apply the heaviest last, keeping file order among equals
+        mappings.sort_by_key(|&(weight, _, _)| weight);
+        self.add_layer(mappings.into_iter().map(|(_, ext, media_type)| (ext, media_type)));
+    }
+
+    pub fn load_globs2_file(&mut self, path: impl AsRef<Path>) -> Result<(), String> {
+        let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        self.load_globs2(&content);
+        Ok(())
+    }
+
+    /// Adds layers of mappings from the system MIME database, if present. The shared-mime-info
+    /// `globs2` files found in the XDG data directories are loaded first, followed by
+    /// `/etc/mime.types`, which takes precedence.
+    pub fn load_system(&mut self) -> Result<(), String> {
+        let mut data_dirs: Vec<PathBuf> = env::var("XDG_DATA_DIRS").ok()
+            .filter(|dirs| !dirs.is_empty())
+            .unwrap_or_else(|| XDG_DATA_DIRS_DEFAULT.to_owned())
+            .split(':')
+            .map(PathBuf::from)
+            .collect();
+        let data_home = env::var_os("XDG_DATA_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| {
+                PathBuf::from(home).join(XDG_DATA_HOME_DEFAULT_REL_PATH)
+            }));
+        // directories listed first are the most important, and the user's data dir above all
+        data_dirs.reverse();
+        data_dirs.extend(data_home);
+
+        for dir in data_dirs {
+            if let Some(content) = read_if_exists(dir.join(GLOBS2_REL_PATH))? {
+                self.load_globs2(&content);
+            }
+        }
+
+        if let Some(content) = read_if_exists(SYSTEM_MIME_TYPES_PATH)? {
+            self.load_mime_types(&content);
+        }
+
+        Ok(())
+    }
+
+    /// Maps a single extension to a media type, taking precedence over existing mappings.
+    pub fn insert(&mut self, ext: &str, media_type: &str) {
+        self.add_layer([(ext, media_type)]);
+    }
+
+    /// Returns the media type mapped to the given extension, if any.
+    pub fn get_media_type(&self, ext: &str) -> Option<&str> {
+        self.media_types.get(&ext.to_ascii_lowercase()).map(|mt| mt.as_str())
+    }
+
+    /// Returns the preferred extension for the given media type, if any extension maps to it.
+    pub fn get_extension(&self, media_type: &str) -> Option<&str> {
+        let media_type = media_type.to_ascii_lowercase();
+        let maps_to_type = |ext: &str| {
+            self.media_types.get(ext).is_some_and(|mt| mt.eq_ignore_ascii_case(&media_type))
+        };
+
+        // the preferred extension may since have been remapped by a later layer
+        match self.preferred_exts.get(&media_type) {
+            Some(ext) if maps_to_type(ext) => Some(ext.as_str()),
+            _ => self.media_types.keys()
+                .filter(|ext| !ext.is_empty() && maps_to_type(ext))
+                .min()
+                .map(|ext| ext.as_str()),
+        }
+    }
+
+    fn add_layer<'a>(&mut self, mappings: impl IntoIterator<Item = (&'a str, &'a str)>) {
+        let mut seen_types = HashSet::new();
+        for (ext, media_type) in mappings {
+            let ext = ext.to_ascii_lowercase();
+            let type_key = media_type.to_ascii_lowercase();
+            if !ext.is_empty() && seen_types.insert(type_key.clone()) {
+                self.preferred_exts.insert(type_key, ext.clone());
+            }
+            self.media_types.insert(ext, media_type.to_owned());
+        }
+    }
+}
+
+fn read_if_exists(path: impl AsRef<Path>) -> Result<Option<String>, String> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.to_string()),
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::Arc;
+use crate::{Package, PackageBuilder, PackingOptions};
+
+/// How [merge] resolves a resource which exists in more than one of the packages being merged,
+/// or whose path is a directory in another of them.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ConflictPolicy {
+    /// The resource from the earliest package in the list is kept.
+    #[default]
+    FirstWins,
+    /// The resource from the latest package in the list is kept.
+    LastWins,
+    /// Merging fails.
+    Error,
+}
+
+/// Prepares a package containing the resources of all the given packages, which must be base
+/// packages sharing a namespace.
+///
+/// Resources whose packed data is compatible with `options`, i.e. which were compressed with
+/// the same codec, are copied without being recompressed.
+pub fn merge(packages: &[Arc<Package>], options: PackingOptions, policy: ConflictPolicy)
+    -> Result<PackageBuilder, String> {
+    let Some(first) = packages.first() else {
+        return Err("At least one package is required".to_owned());
+    };
+    if packages.iter().any(|package| package.is_patch()) {
+        return Err("Patch packages cannot be merged".to_owned());
+    }
+    if packages.iter().any(|package| package.meta.namespace != first.meta.namespace) {
+        return Err("Packages must share a namespace".to_owned());
+    }
+
+    // sorted so that the resources beneath a path are adjacent to it
+    let mut winners: BTreeMap<Vec<String>, (usize, u32)> = BTreeMap::new();
+    for (package_idx, package) in packages.iter().enumerate() {
+        // sorted so that conflicts are reported deterministically
+        let mut resources = package.resource_paths();
+        resources.sort();
+        for (components, index) in resources {
+            let conflicts = conflicting_paths(&winners, &components);
+            if let Some(other) = conflicts.first() {
+                match policy {
+                    ConflictPolicy::FirstWins => continue,
+                    ConflictPolicy::LastWins => {
+                        for path in &conflicts {
+                            winners.remove(path);
+                        }
+                    }
+                    ConflictPolicy::Error => {
+                        let other_package = &packages[winners[other].0];
+                        return Err(format!(
+                            "Resource '{}' in {} conflicts with resource '{}' in {}",
+                            components.join("/"),
+                            describe_package(package, package_idx),
+                            other.join("/"),
+                            describe_package(other_package, winners[other].0),
+                        ));
+                    }
+                }
+            }
+            winners.insert(components, (package_idx, index));
+        }
+    }
+
+    let mut builder = PackageBuilder::new(options)?;
+    for (components, (package_idx, index)) in winners {
+        builder.insert_packed_resource(components, &packages[package_idx], index)?;
+    }
+
+    Ok(builder)
+}
+
+/// Returns the paths of the resources in `winners` which can't coexist with a resource at
+/// `components`: one at the same path, one at a parent of it, or any beneath it.
+fn conflicting_paths(winners: &BTreeMap<Vec<String>, (usize, u32)>, components: &[String])
+    -> Vec<Vec<String>> {
+    let mut conflicts: Vec<_> = (1..=components.len())
+        .map(|len| &components[..len])
+        .filter(|path| winners.contains_key(*path))
+        .map(|path| path.to_vec())
+        .collect();
+    conflicts.extend(winners.range::<[String], _>((Bound::Excluded(components), Bound::Unbounded))
+        .map(|(path, _)| path)
+        .take_while(|path| path.starts_with(components))
+        .cloned());
+    conflicts
+}
+
+fn describe_package(package: &Package, idx: usize) -> String {
+    match package.get_base_file_name() {
+        Some(name) => format!("'{}'", name),
+        None => format!("package {}", idx + 1),
+    }
+}
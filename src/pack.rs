@@ -1,28 +1,85 @@
-use std::collections::{HashMap, VecDeque};
-use std::{env, fs, io};
-use std::fs::{File, FileType};
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::io;
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
-use miniz_oxide::deflate;
-use miniz_oxide::deflate::CompressionLevel;
-use uuid::Uuid;
-use crate::CompressionType;
+use std::sync::{Arc, Mutex};
+use crate::{
+    CodecRegistry, CompressionLevel, CompressionType, Compressor, MediaTypeRegistry, Package,
+    PackageBuilder, PackageKind, SniffMode,
+};
+use crate::cache::{BuildCache, CacheEntry, CacheKey, PrevBuild};
 use crate::defines::*;
 use crate::util::crc32c::crc32c;
+use crate::util::glob::PathPattern;
+use crate::util::io::{CountingReader, CrcWriter};
+use crate::util::parallel::ordered_parallel_map;
 use crate::util::uid::validate_path_component;
 
 pub use crate::defines::COMPRESS_TYPE_DEFLATE;
-use crate::mappings::load_arp_builtin_media_types;
 
 pub const DEFAULT_MEDIA_TYPE: &str = "application/octet-stream";
 
+// resources larger than this are streamed through the compressor by the writer rather than
+// being buffered in memory by a worker thread
+const STREAMING_THRESHOLD: u64 = 4 * 1024 * 1024;
+
+/// Media types which are already compressed and are stored raw by default. A
+/// trailing `/*` matches any subtype.
+pub const DEFAULT_UNCOMPRESSED_MEDIA_TYPES: &[&str] = &[
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "image/avif",
+    "audio/*",
+    "video/*",
+    "font/woff",
+    "font/woff2",
+    "application/zip",
+    "application/gzip",
+    "application/x-7z-compressed",
+    "application/x-bzip2",
+    "application/x-xz",
+    "application/zstd",
+];
+
+/// How symlinks are treated when packing a directory from the filesystem. Other files which are
+/// neither directories nor regular files, such as sockets and FIFOs, are skipped unless the
+/// policy is [Error](SymlinkPolicy::Error).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum SymlinkPolicy {
+    /// Symlinks are left out of the package.
+    #[default]
+    Skip,
+    /// Symlinks are packed as copies of their targets. Links forming a cycle cause packing to
+    /// fail.
+    Follow,
+    /// Packing fails if a symlink or other special file is encountered.
+    Error,
+}
+
 pub struct PackingOptions {
-    version: u16,
-    name: String,
-    namespace: String,
-    max_part_len: Option<u64>,
-    compression_type: Option<CompressionType>,
-    media_types_path: Option<PathBuf>,
+    pub(crate) version: u16,
+    pub(crate) kind: PackageKind,
+    pub(crate) name: String,
+    pub(crate) namespace: String,
+    pub(crate) max_part_len: Option<u64>,
+    pub(crate) compression_type: Option<CompressionType>,
+    pub(crate) media_types_path: Option<PathBuf>,
+    pub(crate) media_types: MediaTypeRegistry,
+    pub(crate) system_media_types: bool,
+    codecs: Arc<CodecRegistry>,
+    uncompressed_media_types: Vec<String>,
+    pub(crate) compression_level: CompressionLevel,
+    threads: usize,
+    include: Vec<PathPattern>,
+    exclude: Vec<PathPattern>,
+    skip_hidden: bool,
+    pub(crate) symlink_policy: SymlinkPolicy,
+    pub(crate) sniff_mode: SniffMode,
+    pub(crate) incremental: bool,
 }
 
 impl PackingOptions {
@@ -32,6 +89,29 @@ impl PackingOptions {
         max_part_len: Option<u64>,
         compression_type: Option<CompressionType>,
         media_types_path: Option<impl AsRef<Path>>,
+    ) -> Result<PackingOptions, String> {
+        Self::new_versioned(1, name, namespace, max_part_len, compression_type, media_types_path)
+    }
+
+    /// Creates options for a v2 package, which supports storing individual resources raw when
+    /// compressing them would not be beneficial.
+    pub fn new_v2(
+        name: impl Into<String>,
+        namespace: impl Into<String>,
+        max_part_len: Option<u64>,
+        compression_type: Option<CompressionType>,
+        media_types_path: Option<impl AsRef<Path>>,
+    ) -> Result<PackingOptions, String> {
+        Self::new_versioned(2, name, namespace, max_part_len, compression_type, media_types_path)
+    }
+
+    pub(crate) fn new_versioned(
+        version: u16,
+        name: impl Into<String>,
+        namespace: impl Into<String>,
+        max_part_len: Option<u64>,
+        compression_type: Option<CompressionType>,
+        media_types_path: Option<impl AsRef<Path>>,
     ) -> Result<PackingOptions, String> {
         let name = name.into();
         let namespace = namespace.into();
@@ -54,12 +134,158 @@ impl PackingOptions {
         }
 
         Ok(Self {
-            version: 1,
+            version,
+            kind: PackageKind::default(),
             name,
             namespace,
             max_part_len,
             compression_type,
             media_types_path,
+            media_types: MediaTypeRegistry::builtin(),
+            system_media_types: false,
+            codecs: Arc::new(CodecRegistry::default()),
+            uncompressed_media_types: DEFAULT_UNCOMPRESSED_MEDIA_TYPES.iter()
+                .map(|mt| mt.to_string())
+                .collect(),
+            compression_level: CompressionLevel::default(),
+            threads: 1,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            skip_hidden: false,
+            symlink_policy: SymlinkPolicy::default(),
+            sniff_mode: SniffMode::default(),
+            incremental: false,
+        })
+    }
+
+    /// Sets the kind of package to write. Only v2 packages may be patches.
+    pub fn with_kind(mut self, kind: PackageKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// Sets the registry used to look up the compressor for the configured compression type.
+    pub fn with_codecs(mut self, codecs: Arc<CodecRegistry>) -> Self {
+        self.codecs = codecs;
+        self
+    }
+
+    /// Sets the registry used to determine media types from file extensions, replacing the
+    /// built-in mappings. Mappings from the file passed at construction are layered on top.
+    pub fn with_media_types(mut self, media_types: MediaTypeRegistry) -> Self {
+        self.media_types = media_types;
+        self
+    }
+
+    /// Sets whether mappings from the system MIME database are layered over the registry when
+    /// packing. See [MediaTypeRegistry::load_system].
+    pub fn with_system_media_types(mut self, enabled: bool) -> Self {
+        self.system_media_types = enabled;
+        self
+    }
+
+    /// Sets the media types which are never compressed, replacing the defaults. Only honored
+    /// by v2 packages.
+    pub fn with_uncompressed_media_types(
+        mut self,
+        media_types: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.uncompressed_media_types = media_types.into_iter().map(|mt| mt.into()).collect();
+        self
+    }
+
+    /// Sets the level at which resources are compressed. Defaults to
+    /// [CompressionLevel::DEFAULT].
+    pub fn with_compression_level(mut self, level: CompressionLevel) -> Self {
+        self.compression_level = level;
+        self
+    }
+
+    /// Sets the number of worker threads used to compress resources. The output is identical
+    /// regardless of the thread count.
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Restricts files packed from the filesystem to those matching at least one of the given
+    /// gitignore-style patterns, relative to the source directory. A pattern matching a
+    /// directory, e.g. `assets` or `assets/`, includes everything beneath it.
+    pub fn with_include(mut self, patterns: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+        self.include = patterns.into_iter().map(|p| PathPattern::new(p.as_ref())).collect();
+        self
+    }
+
+    /// Skips files and directories matching any of the given gitignore-style patterns, relative
+    /// to the source directory, when packing from the filesystem. Takes precedence over
+    /// [with_include](Self::with_include).
+    pub fn with_exclude(mut self, patterns: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+        self.exclude = patterns.into_iter().map(|p| PathPattern::new(p.as_ref())).collect();
+        self
+    }
+
+    /// Skips files and directories whose names begin with a dot when packing from the
+    /// filesystem.
+    pub fn with_skip_hidden(mut self, skip_hidden: bool) -> Self {
+        self.skip_hidden = skip_hidden;
+        self
+    }
+
+    pub fn with_symlink_policy(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlink_policy = policy;
+        self
+    }
+
+    /// Sets whether resource contents are sniffed to determine media types which aren't given
+    /// explicitly.
+    pub fn with_sniff_mode(mut self, mode: SniffMode) -> Self {
+        self.sniff_mode = mode;
+        self
+    }
+
+    /// Sets whether packing to a directory reuses packed data from the previous build for files
+    /// which haven't changed. The data is located through a `.arpcache` file written next to
+    /// the package.
+    pub fn with_incremental(mut self, incremental: bool) -> Self {
+        self.incremental = incremental;
+        self
+    }
+
+    pub(crate) fn should_pack_path(&self, rel_path: &str, is_dir: bool) -> bool {
+        let file_name = rel_path.rsplit('/').next().unwrap_or(rel_path);
+        if self.skip_hidden && file_name.starts_with('.') {
+            return false;
+        }
+
+        if self.exclude.iter().any(|pattern| pattern.matches(rel_path, is_dir)) {
+            return false;
+        }
+
+        // directories are always traversed so that files beneath them can be included
+        is_dir || self.is_included(rel_path, false)
+    }
+
+    /// Returns whether the path, or a directory containing it, matches an include pattern.
+    /// Every path is included if there are no include patterns.
+    pub(crate) fn is_included(&self, rel_path: &str, is_dir: bool) -> bool {
+        self.include.is_empty() || self.include.iter().any(|pattern| {
+            pattern.matches(rel_path, is_dir)
+                || rel_path.match_indices('/').any(|(i, _)| pattern.matches(&rel_path[..i], true))
+        })
+    }
+
+    fn should_compress(&self, media_type: &str) -> bool {
+        // v1 packages have no way of marking individual nodes as uncompressed
+        self.version < 2 || self.should_compress_media_type(media_type)
+    }
+
+    fn should_compress_media_type(&self, media_type: &str) -> bool {
+        !self.uncompressed_media_types.iter().any(|pattern| {
+            match pattern.strip_suffix("/*") {
+                Some(prefix) => media_type.split_once('/')
+                    .is_some_and(|(ty, _)| ty.eq_ignore_ascii_case(prefix)),
+                None => media_type.eq_ignore_ascii_case(pattern),
+            }
         })
     }
 }
@@ -69,320 +295,371 @@ pub fn create_arp_from_fs(
     target_dir: impl AsRef<Path>,
     options: PackingOptions,
 ) -> Result<(), String> {
-    let media_types_builtin = load_arp_builtin_media_types();
-
-    let mut user_mappings_content = String::new();
-    let media_types_user = if let Some(mt_path) = &options.media_types_path {
-        let mut mt_file = File::open(mt_path).map_err(|e| e.to_string())?;
-        mt_file.read_to_string(&mut user_mappings_content).map_err(|e| e.to_string())?;
-        load_media_types(user_mappings_content.as_str())?
-    } else {
-        Default::default()
-    };
-
-    let mut all_media_types = media_types_builtin;
-    all_media_types.extend(media_types_user);
-
-    let nodes = traverse_fs(src_path.as_ref(), &all_media_types)?;
+    let mut builder = PackageBuilder::new(options)?;
+    builder.add_directory("", src_path)?;
+    builder.write_to_dir(target_dir)
+}
 
-    write_package_to_disk(nodes, target_dir.as_ref(), &options)
+/// Packs the contents of `src_path` into a single-part package held entirely in memory. The
+/// result can be loaded with [Package::load_from_vec](crate::Package::load_from_vec).
+pub fn pack_to_vec(
+    src_path: impl AsRef<Path>,
+    options: PackingOptions,
+) -> Result<Vec<u8>, String> {
+    let mut builder = PackageBuilder::new(options)?;
+    builder.add_directory("", src_path)?;
+    builder.write_to_vec()
 }
 
-struct FsNode {
-    ty: FileType,
-    target_path: PathBuf,
-    media_type: String,
-    size: u64,
-    index: u32,
-    child_dir_indices: Vec<u32>,
-    child_dir_paths: Vec<PathBuf>,
-    child_file_indices: Vec<u32>,
-    child_file_paths: Vec<PathBuf>,
+pub(crate) struct PackNode {
+    pub(crate) name: String,
+    pub(crate) ext: String,
+    pub(crate) media_type: String,
+    pub(crate) source: NodeSource,
 }
 
-struct ProcessedNodeData {
-    data: Vec<u8>,
-    crc: u32,
+pub(crate) enum NodeSource {
+    // indices of the directory's children
+    Directory(Vec<u32>),
+    Bytes(Vec<u8>),
+    // taken by the writer when the node's data is streamed
+    Reader(Mutex<Option<Box<dyn Read + Send>>>),
+    File { path: PathBuf, size: u64 },
+    // a resource in an existing package, whose packed data is copied as-is if it's compatible
+    // with the new package
+    Packed { package: Arc<Package>, index: u32 },
+    // marks the resource as deleted in a patch package, and has no data
+    Tombstone,
 }
 
-fn traverse_fs(
-    root_path: impl AsRef<Path>,
-    media_types: &HashMap<&str, &str>
-) -> Result<Vec<FsNode>, String> {
+impl PackNode {
+    fn is_dir(&self) -> bool {
+        matches!(self.source, NodeSource::Directory(_))
+    }
 
-    let mut dir_queue: VecDeque<PathBuf> = VecDeque::from([root_path.as_ref().to_owned()]);
-    let mut file_queue: VecDeque<PathBuf> = VecDeque::new();
+    fn is_tombstone(&self) -> bool {
+        matches!(self.source, NodeSource::Tombstone)
+    }
+}
 
-    let mut dir_nodes: Vec<FsNode> = Vec::new();
-    let mut file_nodes: Vec<FsNode> = Vec::new();
+impl NodeSource {
+    /// Returns up to `len` bytes from the start of the source without consuming them.
+    pub(crate) fn peek(&mut self, len: usize) -> Result<Vec<u8>, String> {
+        match self {
+            NodeSource::Directory(_) | NodeSource::Tombstone => Ok(Vec::new()),
+            NodeSource::Bytes(data) => Ok(data[..data.len().min(len)].to_vec()),
+            NodeSource::File { path, .. } => {
+                let mut prefix = Vec::with_capacity(len);
+                File::open(path)
+                    .and_then(|file| file.take(len as u64).read_to_end(&mut prefix))
+                    .map_err(|e| e.to_string())?;
+                Ok(prefix)
+            }
+            NodeSource::Reader(reader) => {
+                let slot = reader.get_mut().map_err(|e| e.to_string())?;
+                let Some(mut inner) = slot.take() else {
+                    return Ok(Vec::new());
+                };
+
+                let mut prefix = Vec::with_capacity(len);
+                inner.by_ref().take(len as u64).read_to_end(&mut prefix)
+                    .map_err(|e| e.to_string())?;
+                // put the consumed bytes back in front of the rest of the stream
+                *slot = Some(Box::new(Cursor::new(prefix.clone()).chain(inner)));
+                Ok(prefix)
+            }
+            NodeSource::Packed { package, index } => {
+                let mut data = package.load_resource_data(*index)?;
+                data.truncate(len);
+                Ok(data)
+            }
+        }
+    }
+}
 
-    let mut dir_index_map: HashMap<PathBuf, u32> = HashMap::new();
-    let mut file_index_map: HashMap<PathBuf, u32> = HashMap::new();
+/// Returns the flags to store with the packed data of an existing resource if the data can be
+/// copied into a package with the given options as-is, without being recompressed.
+fn copyable_packed_flags(package: &Package, index: u32, options: &PackingOptions) -> Option<u8> {
+    if package.meta.compression_type != options.compression_type {
+        return None;
+    }
 
-    let mut cur_index = 0;
+    let resource = &package.catalogue.resources[&index];
+    if options.compression_type.is_some() && !resource.is_compressed(&package.meta) {
+        // v1 packages have no way of marking individual nodes as uncompressed
+        return (options.version >= 2).then_some(ND_FLAG_UNCOMPRESSED);
+    }
 
-    while let Some(dir_path) = dir_queue.pop_front() {
-        let meta = dir_path.metadata().map_err(|err| err.to_string())?;
+    Some(0)
+}
 
-        let ty = meta.file_type();
-        if !ty.is_dir() && !ty.is_file() {
-            return Err("Only regular files and directories are supported".to_owned());
-        }
+/// A destination for the contents of a single package part.
+pub(crate) trait PartSink: Read + Write + Seek {
+    /// Discards everything past `len`. Sinks which are unable to shrink may leave stale bytes
+    /// in place, as they fall outside the package body and are never read.
+    fn truncate(&mut self, len: u64) -> io::Result<()>;
 
-        let media_type = if meta.is_file() {
-            dir_path.extension()
-                .and_then(|ext|
-                    media_types.get(ext.to_string_lossy().as_ref()).copied()
-                )
-                .unwrap_or(DEFAULT_MEDIA_TYPE)
-        } else {
-            ""
-        };
+    /// Returns whether data written to the sink can be read back.
+    fn is_readable(&self) -> bool {
+        true
+    }
+}
 
-        let size = meta.len();
-
-        let mut child_dir_paths = vec![];
-        let mut child_file_paths = vec![];
-
-        if meta.is_dir() {
-            let dir = dir_path.read_dir().map_err(|err| err.to_string())?;
-            for child in dir {
-                let child = child.map_err(|err| err.to_string())?;
-                let child_meta = child.metadata().map_err(|err| err.to_string())?;
-                if child_meta.is_dir() {
-                    dir_queue.push_back(child.path());
-                    child_dir_paths.push(child.path());
-                } else if child_meta.is_file() {
-                    file_queue.push_back(child.path());
-                    child_file_paths.push(child.path());
-                } else {
-                    println!(
-                        "Warning: Ignoring child '{}' as it is not a directory or regular file",
-                        child.path().display(),
-                    );
-                }
-            }
-        }
+impl PartSink for File {
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        self.set_len(len)
+    }
+}
 
-        let node = FsNode {
-            ty,
-            target_path: dir_path.clone(),
-            media_type: media_type.to_owned(),
-            size,
-            index: cur_index,
-            child_dir_indices: Vec::with_capacity(child_dir_paths.len()),
-            child_dir_paths,
-            child_file_indices: Vec::with_capacity(child_file_paths.len()),
-            child_file_paths,
-        };
-        dir_nodes.push(node);
-        dir_index_map.insert(dir_path, cur_index);
-        cur_index += 1;
+impl<S: PartSink> PartSink for &mut S {
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        (**self).truncate(len)
     }
 
-    while let Some(file_path) = file_queue.pop_front() {
-        let meta = file_path.metadata().map_err(|err| err.to_string())?;
+    fn is_readable(&self) -> bool {
+        (**self).is_readable()
+    }
+}
 
-        let ty = meta.file_type();
-        if !ty.is_dir() && !ty.is_file() {
-            return Err("Only regular files and directories are supported".to_owned());
-        }
+impl PartSink for Cursor<Vec<u8>> {
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        self.get_mut().truncate(len as usize);
+        Ok(())
+    }
+}
 
-        let media_type = file_path.extension()
-            .and_then(|ext|
-                media_types.get(ext.to_string_lossy().as_ref()).copied()
-            )
-            .unwrap_or(DEFAULT_MEDIA_TYPE);
-
-        let size = meta.len();
-
-        let node = FsNode {
-            ty,
-            target_path: file_path.clone(),
-            media_type: media_type.to_owned(),
-            size,
-            index: cur_index,
-            child_dir_indices: vec![],
-            child_dir_paths: vec![],
-            child_file_indices: vec![],
-            child_file_paths: vec![],
-        };
-        file_nodes.push(node);
-        file_index_map.insert(file_path, cur_index);
-        cur_index += 1;
+/// Adapts a write-only sink for single-part packages, which never need to read back
+/// their own output.
+pub(crate) struct WriteSeekSink<W: Write + Seek>(pub(crate) W);
+
+impl<W: Write + Seek> Read for WriteSeekSink<W> {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "Sink is not readable"))
     }
+}
 
-    for node in &mut dir_nodes {
-        for child_path in &mut node.child_dir_paths {
-            node.child_dir_indices.push(dir_index_map[child_path]);
-        }
-        for child_path in &mut node.child_file_paths {
-            node.child_file_indices.push(file_index_map[child_path]);
-        }
+impl<W: Write + Seek> Write for WriteSeekSink<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
 
-        node.child_dir_paths.clear();
-        node.child_file_paths.clear();
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
     }
+}
 
-    let final_nodes = dir_nodes.into_iter().chain(file_nodes.into_iter()).collect();
-    Ok(final_nodes)
+impl<W: Write + Seek> Seek for WriteSeekSink<W> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
 }
 
-fn load_media_types(csv_contents: &str) -> Result<HashMap<&str, &str>, String> {
-    let mappings = csv_contents.lines()
-        .filter_map(|line| {
-            let spl = line.split_once(",")?;
-            Some((spl.0, spl.1))
-        })
-        .collect::<HashMap<&str, &str>>();
+impl<W: Write + Seek> PartSink for WriteSeekSink<W> {
+    fn truncate(&mut self, _len: u64) -> io::Result<()> {
+        Ok(())
+    }
 
-    Ok(mappings)
+    fn is_readable(&self) -> bool {
+        false
+    }
 }
 
-fn write_package_to_disk(
-    nodes: Vec<FsNode>,
-    target_dir: impl AsRef<Path>,
-    options: &PackingOptions
-) -> Result<(), String> {
-    let node_count = nodes.len();
-    let dir_count = nodes.iter().filter(|n| n.ty.is_dir()).count();
-    let resource_count = nodes.iter().filter(|n| n.ty.is_file()).count();
+enum NodeData<'a> {
+    Buffered(ProcessedNodeData<'a>),
+    // too large to buffer, so the writer must stream it from the source
+    Deferred,
+    // too large to buffer, so the writer must copy it from the previous build
+    Cached(&'a CacheEntry),
+}
 
-    let catalogue_len = compute_catalogue_len(&nodes);
+struct ProcessedNodeData<'a> {
+    data: Cow<'a, [u8]>,
+    unpacked_len: u64,
+    crc: u32,
+    flags: u8,
+    // CRC of the unpacked data, only computed for incremental builds
+    content_crc: u32,
+}
 
-    let catalogue_path = env::temp_dir().join(Uuid::new_v4().to_string());
-    let mut catalogue_file = File::create_new(catalogue_path).map_err(|e| e.to_string())?;
+#[derive(Default)]
+pub(crate) struct WrittenNodeData {
+    pub(crate) packed_len: u64,
+    pub(crate) unpacked_len: u64,
+    pub(crate) crc: u32,
+    pub(crate) flags: u8,
+    content_crc: u32,
+}
 
-    // generate temp file for new part
-    let part_1_path = env::temp_dir().join(Uuid::new_v4().to_string());
-    // open new part file
-    let mut part_1_file = File::create_new(&part_1_path).map_err(|e| e.to_string())?;
+/// Writes the package described by `nodes` to `part_1`, calling `create_part` with the index
+/// of each subsequent part as it becomes needed. Returns the total number of parts.
+///
+/// If a build cache is given, packed data is reused from the previous build for unchanged
+/// files, and entries describing the new build are recorded in it.
+pub(crate) fn write_package<S: PartSink>(
+    nodes: &[PackNode],
+    options: &PackingOptions,
+    part_1: S,
+    create_part: impl FnMut(u16) -> Result<S, String>,
+    cache: Option<&mut BuildCache>,
+) -> Result<u16, String> {
+    let node_count = nodes.len();
+    let dir_count = nodes.iter().filter(|n| n.is_dir()).count();
+    let tombstone_count = nodes.iter().filter(|n| n.is_tombstone()).count();
+    let resource_count = node_count - dir_count - tombstone_count;
+
+    let catalogue_len = compute_catalogue_len(nodes, options.version);
+
+    let compressor = options.compression_type.as_ref()
+        .map(|c| options.codecs.get_compressor(&c.get_magic()))
+        .transpose()?;
+
+    // the catalogue is bounded by the node count rather than the size of the data, so it's
+    // built in memory and copied into the first part once all nodes have been written
+    let mut catalogue: Vec<u8> = Vec::with_capacity(catalogue_len as usize);
+
+    let mut parts = PartWriter {
+        create_part,
+        part_1,
+        cur_part: None,
+        body_lens: Vec::new(),
+        cur_index: 1,
+        cur_body_len: 0,
+    };
     // reserve bytes at start so we can populate the header later
-    part_1_file.seek(SeekFrom::Start(PACKAGE_HEADER_LEN + catalogue_len))
+    parts.part_1.seek(SeekFrom::Start(PACKAGE_HEADER_LEN + catalogue_len))
         .map_err(|e| e.to_string())?;
 
-    let mut part_paths: Vec<PathBuf> = vec![part_1_path.clone()];
-    let mut part_body_lens: Vec<u64> = Vec::new();
-    let mut cur_part_body_len: u64 = 0;
+    let (prev_build, mut new_cache_entries) = match cache {
+        Some(cache) => (Some(&cache.prev), Some(&mut cache.new_entries)),
+        None => (None, None),
+    };
 
-    let mut cur_part_file = part_1_file;
+    let load_data = |node| {
+        let Some(prev_build) = prev_build else {
+            return Ok((load_node_data(node, compressor.as_deref(), options, false)?, None));
+        };
+        let Some(cache_key) = CacheKey::for_node(node)? else {
+            return Ok((load_node_data(node, compressor.as_deref(), options, false)?, None));
+        };
 
-    let mut cur_part = 1;
-    for node in nodes {
-        let processed_data = load_node_data(&node, &options)?;
+        let compress = options.should_compress(&node.media_type);
+        if let Some(entry) = prev_build.lookup(&cache_key, &node.media_type, compress)? {
+            if entry.packed_len > STREAMING_THRESHOLD {
+                return Ok((NodeData::Cached(entry), Some(cache_key)));
+            }
+            // if the previous build's data has gone missing, fall back to packing it again
+            if let Some(data) = prev_build.read_packed(entry) {
+                let processed_data = ProcessedNodeData {
+                    data: Cow::Owned(data),
+                    unpacked_len: entry.unpacked_len,
+                    crc: entry.crc,
+                    flags: entry.flags,
+                    content_crc: entry.content_crc,
+                };
+                return Ok((NodeData::Buffered(processed_data), Some(cache_key)));
+            }
+        }
 
-        let node_len = processed_data.data.len() as u64;
+        let node_data = load_node_data(node, compressor.as_deref(), options, true)?;
+        Ok((node_data, Some(cache_key)))
+    };
+    ordered_parallel_map(nodes, options.threads, load_data, |node, (node_data, cache_key)| {
+        let desc_off = catalogue.len();
+        push_node_desc(&mut catalogue, node, options.version);
+
+        let written = match node_data {
+            NodeData::Buffered(processed_data) => {
+                let node_len = processed_data.data.len() as u64;
+                if parts.would_overflow(node_len, options) {
+                    if parts.cur_body_len == 0 {
+                        return Err("Max part size is smaller than largest resource".to_owned());
+                    }
+                    parts.start_new_part()?;
+                }
 
-        let new_part_body_len = cur_part_body_len + node_len;
-        let new_part_len = new_part_body_len + PACKAGE_PART_HEADER_LEN;
-        if options.max_part_len.is_some_and(|max_len| new_part_len > max_len) {
-            if cur_part_body_len == 0 {
-                return Err("Max part size is smaller than largest resource".to_owned());
-            }
+                // write node contents to part file
+                parts.cur_sink().write_all(&processed_data.data).map_err(|e| e.to_string())?;
 
-            // part is finalized - push its length
-            part_body_lens.push(cur_part_body_len);
-            // advance to the next part
-            cur_part += 1;
-            // reset body length for new part
-            cur_part_body_len = 0;
-
-            // generate temp file for new part
-            let cur_part_path = env::temp_dir().join(Uuid::new_v4().to_string());
-            part_paths.push(cur_part_path.clone());
-            // open new part file
-            cur_part_file = File::create_new(cur_part_path).map_err(|e| e.to_string())?;
-
-            // populate part header
-            let mut part_header_buf: Vec<u8> = Vec::with_capacity(PACKAGE_PART_HEADER_LEN as usize);
-            // part format magic
-            part_header_buf.extend_from_slice(&PART_MAGIC);
-            // part index
-            push_u16_le(&mut part_header_buf, cur_part);
-            // extend to full part header length (last section is reserved)
-            part_header_buf.resize(0x10, 0u8);
-            // write header to file
-            cur_part_file.write_all(part_header_buf.as_slice()).map_err(|e| e.to_string())?;
-
-            if cur_part > PARTS_MAX {
-                return Err("Part count would exceed maximum".to_owned());
+                WrittenNodeData {
+                    packed_len: node_len,
+                    unpacked_len: processed_data.unpacked_len,
+                    crc: processed_data.crc,
+                    flags: processed_data.flags,
+                    content_crc: processed_data.content_crc,
+                }
             }
-        }
+            NodeData::Deferred | NodeData::Cached(_) => {
+                let node_start = parts.cur_sink().stream_position().map_err(|e| e.to_string())?;
+                let copied = match (&node_data, prev_build) {
+                    (NodeData::Cached(entry), Some(prev_build)) => {
+                        copy_cached_node_data(entry, prev_build, parts.cur_sink())?
+                    }
+                    _ => None,
+                };
+                let written = match copied {
+                    Some(written) => written,
+                    None => stream_node_data(
+                        node,
+                        parts.cur_sink(),
+                        compressor.as_deref(),
+                        options,
+                    )?,
+                };
+
+                // the packed length isn't known until the data has been streamed, so if it
+                // turns out not to fit we need to move it to a fresh part after the fact
+                if parts.would_overflow(written.packed_len, options) {
+                    if parts.cur_body_len == 0 {
+                        return Err("Max part size is smaller than largest resource".to_owned());
+                    }
+                    parts.move_to_new_part(node_start, written.packed_len)?;
+
+                    if parts.would_overflow(written.packed_len, options) {
+                        return Err("Max part size is smaller than largest resource".to_owned());
+                    }
+                }
 
-        let type_ordinal = if node.ty.is_file() {
-            PACK_NODE_TYPE_RESOURCE
-        } else if node.ty.is_dir() {
-            PACK_NODE_TYPE_DIRECTORY
-        } else {
-            panic!("Unhandled FS node type");
+                written
+            }
         };
 
-        let name = if node.index == 0 {
-            &[]
-        } else {
-            node.target_path.file_stem().unwrap().as_encoded_bytes()
-        };
-        let ext = if node.ty.is_file() {
-            node.target_path.extension().map(|ext| ext.as_encoded_bytes()).unwrap_or(&[])
-        } else {
-            &[]
-        };
-        let name_len = name.len();
-        let ext_len = ext.len();
-        let media_type_len = node.media_type.len();
-        assert!(name_len <= u8::MAX as usize);
-        assert!(ext_len <= u8::MAX as usize);
-        assert!(media_type_len <= u8::MAX as usize);
-
-        // write node contents to part file
-        cur_part_file.write_all(&processed_data.data).map_err(|e| e.to_string())?;
-
-        // build node descriptor in memory
-        let node_desc_len = compute_node_desc_len(&node);
-        let mut node_desc: Vec<u8> = Vec::with_capacity(node_desc_len as usize);
-        push_u16_le(&mut node_desc, node_desc_len);
-        node_desc.push(type_ordinal);
-        push_u16_le(&mut node_desc, cur_part);
-        push_u64_le(&mut node_desc, cur_part_body_len);
-        push_u64_le(&mut node_desc, processed_data.data.len() as u64);
-        push_u64_le(&mut node_desc, node.size);
-        push_u32_le(&mut node_desc, processed_data.crc);
-        node_desc.push(name_len as u8);
-        node_desc.push(ext_len as u8);
-        node_desc.push(media_type_len as u8);
-        node_desc.extend_from_slice(name);
-        node_desc.extend_from_slice(ext);
-        node_desc.extend_from_slice(node.media_type.as_bytes());
-        assert_eq!(node_desc.len(), node_desc_len as usize);
-
-        // write node descriptor to disk
-        catalogue_file.write_all(&node_desc).map_err(|e| e.to_string())?;
-
-        cur_part_body_len += processed_data.data.len() as u64;
-    }
+        // back-patch the node descriptor now that the data has been written
+        patch_node_desc(
+            &mut catalogue[desc_off..],
+            parts.cur_index,
+            parts.cur_body_len,
+            &written,
+        );
+
+        if let (Some(new_cache_entries), Some(cache_key)) = (&mut new_cache_entries, cache_key) {
+            let body_off = if parts.cur_index == 1 {
+                PACKAGE_HEADER_LEN + catalogue_len
+            } else {
+                PACKAGE_PART_HEADER_LEN
+            };
+            new_cache_entries.push(CacheEntry {
+                key: cache_key,
+                media_type: node.media_type.clone(),
+                compress: options.should_compress(&node.media_type),
+                content_crc: written.content_crc,
+                part: parts.cur_index,
+                offset: body_off + parts.cur_body_len,
+                packed_len: written.packed_len,
+                unpacked_len: written.unpacked_len,
+                crc: written.crc,
+                flags: written.flags,
+            });
+        }
 
-    // store body length of final part
-    part_body_lens.push(cur_part_body_len);
+        parts.cur_body_len += written.packed_len;
 
-    // flush and rewind completed catalogue file
-    catalogue_file.flush().map_err(|e| e.to_string())?;
-    catalogue_file.rewind().map_err(|e| e.to_string())?;
+        Ok(())
+    })?;
 
-    let total_parts = cur_part;
+    // store body length of final part
+    parts.body_lens.push(parts.cur_body_len);
 
-    let mut part_1_file = if total_parts == 1 {
-        // still on part 1, reuse the handle
-        cur_part_file
-    } else {
-        // close current part
-        _ = cur_part_file;
-        // open the first part again
-        File::options()
-            .write(true)
-            .truncate(false)
-            .open(part_1_path)
-            .map_err(|e| e.to_string())?
-    };
+    let total_parts = parts.cur_index;
 
     let mut header_buf: Vec<u8> = Vec::with_capacity(PACKAGE_HEADER_LEN as usize);
     // format magic
@@ -392,8 +669,8 @@ fn write_package_to_disk(
     // compression type
     let compress_magic = options.compression_type.as_ref()
         .map(|c| c.get_magic())
-        .unwrap_or(&[0u8; 2]);
-    header_buf.extend_from_slice(compress_magic);
+        .unwrap_or([0u8; 2]);
+    header_buf.extend_from_slice(&compress_magic);
     // namespace
     let mut namespace_buf = Vec::with_capacity(PACK_HEADER_NAMESPACE_LEN);
     namespace_buf.extend_from_slice(options.namespace.as_bytes());
@@ -414,113 +691,577 @@ fn write_package_to_disk(
     // body offset
     push_u64_le(&mut header_buf, PACKAGE_HEADER_LEN + catalogue_len);
     // body length
-    push_u64_le(&mut header_buf, part_body_lens[0]);
+    push_u64_le(&mut header_buf, parts.body_lens[0]);
 
-    assert_eq!(header_buf.len(), PACK_HEADER_BODY_LEN_END_OFF);
+    if options.version >= 2 {
+        // package kind
+        header_buf.push(options.kind.get_ordinal());
+        assert_eq!(header_buf.len(), PACK_HEADER_KIND_END_OFF);
+    } else {
+        assert_eq!(header_buf.len(), PACK_HEADER_BODY_LEN_END_OFF);
+    }
     // extend to full header length (last section is reserved)
     header_buf.resize(0x100, 0u8);
 
+    assert_eq!(catalogue.len() as u64, catalogue_len);
+
+    // close the last part if it isn't the first
+    if let Some(mut cur_part) = parts.cur_part.take() {
+        cur_part.flush().map_err(|e| e.to_string())?;
+    }
+
+    let part_1 = &mut parts.part_1;
     // write package header
-    part_1_file.rewind().map_err(|e| e.to_string())?;
-    part_1_file.write_all(header_buf.as_slice()).map_err(|e| e.to_string())?;
-    // copy catalogue contents
-    io::copy(&mut catalogue_file, &mut part_1_file).map_err(|e| e.to_string())?;
-    part_1_file.flush().map_err(|e| e.to_string())?;
+    part_1.rewind().map_err(|e| e.to_string())?;
+    part_1.write_all(header_buf.as_slice()).map_err(|e| e.to_string())?;
+    // write catalogue contents
+    part_1.write_all(&catalogue).map_err(|e| e.to_string())?;
+    part_1.flush().map_err(|e| e.to_string())?;
 
-    // release part 1 file handle
-    _ = part_1_file;
+    Ok(total_parts)
+}
 
-    let target_dir_ref = target_dir.as_ref();
+struct PartWriter<S: PartSink, F: FnMut(u16) -> Result<S, String>> {
+    create_part: F,
+    part_1: S,
+    // None while still writing the first part
+    cur_part: Option<S>,
+    body_lens: Vec<u64>,
+    cur_index: u16,
+    cur_body_len: u64,
+}
 
-    if !target_dir_ref.exists() {
-        fs::create_dir(target_dir_ref).map_err(|e| e.to_string())?;
+impl<S: PartSink, F: FnMut(u16) -> Result<S, String>> PartWriter<S, F> {
+    fn cur_sink(&mut self) -> &mut S {
+        self.cur_part.as_mut().unwrap_or(&mut self.part_1)
     }
 
-    // copy temp files to final paths
-    for i in 0..total_parts {
-        let src = &part_paths[i as usize];
-        let dest = if i == 0 && total_parts == 1 {
-            target_dir_ref.join(format!("{}.arp", options.name))
-        } else {
-            target_dir_ref.join(format!("{}.part{:0>3}.arp", options.name, i + 1))
-        };
-        fs::copy(src, dest).map_err(|e| e.to_string())?;
-        fs::remove_file(src).map_err(|e| e.to_string())?;
+    fn would_overflow(&self, node_len: u64, options: &PackingOptions) -> bool {
+        let new_part_body_len = self.cur_body_len + node_len;
+        let new_part_len = new_part_body_len + PACKAGE_PART_HEADER_LEN;
+        options.max_part_len.is_some_and(|max_len| new_part_len > max_len)
+    }
+
+    // finalizes the current part and opens the next one, returning the previous part if it
+    // wasn't the first
+    fn start_new_part(&mut self) -> Result<Option<S>, String> {
+        // part is finalized - push its length
+        self.body_lens.push(self.cur_body_len);
+        // advance to the next part
+        self.cur_index += 1;
+        // reset body length for new part
+        self.cur_body_len = 0;
+
+        if self.cur_index > PARTS_MAX {
+            return Err("Part count would exceed maximum".to_owned());
+        }
+
+        let mut new_part = (self.create_part)(self.cur_index)?;
+
+        // populate part header
+        let mut part_header_buf: Vec<u8> = Vec::with_capacity(PACKAGE_PART_HEADER_LEN as usize);
+        // part format magic
+        part_header_buf.extend_from_slice(&PART_MAGIC);
+        // part index
+        push_u16_le(&mut part_header_buf, self.cur_index);
+        // extend to full part header length (last section is reserved)
+        part_header_buf.resize(0x10, 0u8);
+        // write header to file
+        new_part.write_all(part_header_buf.as_slice()).map_err(|e| e.to_string())?;
+
+        Ok(self.cur_part.replace(new_part))
+    }
+
+    // moves the trailing `len` bytes of the current part, starting at `node_start`, into a
+    // newly started part
+    fn move_to_new_part(&mut self, node_start: u64, len: u64) -> Result<(), String> {
+        let mut prev_part = self.start_new_part()?;
+        let prev_sink = prev_part.as_mut().unwrap_or(&mut self.part_1);
+        let new_sink = self.cur_part.as_mut().unwrap();
+
+        prev_sink.seek(SeekFrom::Start(node_start)).map_err(|e| e.to_string())?;
+        io::copy(&mut Read::by_ref(prev_sink).take(len), new_sink).map_err(|e| e.to_string())?;
+        prev_sink.truncate(node_start).map_err(|e| e.to_string())?;
+        prev_sink.flush().map_err(|e| e.to_string())?;
+
+        Ok(())
     }
+}
 
-    Ok(())
+/// Writes the data of a single node at the sink's current position.
+pub(crate) fn write_node_data<S: PartSink>(
+    node: &PackNode,
+    sink: &mut S,
+    options: &PackingOptions,
+) -> Result<WrittenNodeData, String> {
+    let compressor = options.compression_type.as_ref()
+        .map(|c| options.codecs.get_compressor(&c.get_magic()))
+        .transpose()?;
+
+    match load_node_data(node, compressor.as_deref(), options, false)? {
+        NodeData::Buffered(processed_data) => {
+            sink.write_all(&processed_data.data).map_err(|e| e.to_string())?;
+            Ok(WrittenNodeData {
+                packed_len: processed_data.data.len() as u64,
+                unpacked_len: processed_data.unpacked_len,
+                crc: processed_data.crc,
+                flags: processed_data.flags,
+                content_crc: processed_data.content_crc,
+            })
+        }
+        NodeData::Deferred => stream_node_data(node, sink, compressor.as_deref(), options),
+        NodeData::Cached(_) => unreachable!("Cached data requires a build cache"),
+    }
 }
 
-fn load_node_data(node: &FsNode, options: &PackingOptions)
-                  -> Result<ProcessedNodeData, String> {
-    let data: Vec<u8> = if node.ty.is_file() {
-        let mut data = Vec::new();
+fn load_node_data<'a>(
+    node: &'a PackNode,
+    compressor: Option<&dyn Compressor>,
+    options: &PackingOptions,
+    hash_content: bool,
+) -> Result<NodeData<'a>, String> {
+    let mut flags = 0u8;
+    let mut content_crc = 0;
+
+    let (data, unpacked_len) = match &node.source {
+        NodeSource::Directory(child_indices) => {
+            let data: Vec<u8> = child_indices.iter()
+                .flat_map(|idx| idx.to_le_bytes())
+                .collect();
+            let len = data.len() as u64;
+            (Cow::Owned(data), len)
+        }
+        NodeSource::Tombstone => (Cow::Borrowed(&[][..]), 0),
+        NodeSource::Packed { package, index } => {
+            if let Some(packed_flags) = copyable_packed_flags(package, *index, options) {
+                let resource = &package.catalogue.resources[index];
+                if resource.data_len_packed > STREAMING_THRESHOLD {
+                    return Ok(NodeData::Deferred);
+                }
 
-        let mut file = File::open(&node.target_path).map_err(|e| e.to_string())?;
-        file.read_to_end(&mut data).map_err(|e| e.to_string())?;
+                return Ok(NodeData::Buffered(ProcessedNodeData {
+                    data: Cow::Owned(package.load_packed_data(*index)?),
+                    unpacked_len: resource.data_len_unpacked,
+                    crc: resource.crc,
+                    flags: packed_flags,
+                    content_crc,
+                }));
+            }
 
-        if let Some(compression) = options.compression_type.as_ref() {
-            match compression {
-                CompressionType::Deflate =>
-                    deflate::compress_to_vec_zlib(&data, CompressionLevel::BestCompression as u8),
+            // the data has to be decompressed before it can be packed differently
+            let raw_data = package.load_resource_data(*index)?;
+            compress_node_data(node, Cow::Owned(raw_data), compressor, options, &mut flags)?
+        }
+        NodeSource::Reader(_) => {
+            return Ok(NodeData::Deferred);
+        }
+        // large files are streamed by the writer instead of being read into memory here
+        NodeSource::File { size, .. } if *size > STREAMING_THRESHOLD => {
+            return Ok(NodeData::Deferred);
+        }
+        NodeSource::File { .. } | NodeSource::Bytes(_) => {
+            let raw_data: Cow<[u8]> = match &node.source {
+                NodeSource::File { path, .. } => {
+                    let mut data = Vec::new();
+                    let mut file = File::open(path).map_err(|e| e.to_string())?;
+                    file.read_to_end(&mut data).map_err(|e| e.to_string())?;
+                    Cow::Owned(data)
+                }
+                NodeSource::Bytes(data) => Cow::Borrowed(data),
+                _ => unreachable!(),
+            };
+            if hash_content {
+                content_crc = crc32c(&raw_data);
             }
-        } else {
-            data
+
+            compress_node_data(node, raw_data, compressor, options, &mut flags)?
         }
-    } else if node.ty.is_dir() {
-        node.child_dir_indices.iter()
-            .chain(node.child_file_indices.iter())
-            .map(|idx| idx.to_le_bytes())
-            .flatten()
-            .collect()
-    } else {
-        panic!("Unhandled node type {:?}", node.ty);
     };
 
     let crc = crc32c(&data);
 
-    Ok(ProcessedNodeData {
+    Ok(NodeData::Buffered(ProcessedNodeData {
         data,
+        unpacked_len,
         crc,
+        flags,
+        content_crc,
+    }))
+}
+
+// compresses a node's raw data if appropriate, returning the data to store and its unpacked
+// length
+fn compress_node_data<'a>(
+    node: &PackNode,
+    raw_data: Cow<'a, [u8]>,
+    compressor: Option<&dyn Compressor>,
+    options: &PackingOptions,
+    flags: &mut u8,
+) -> Result<(Cow<'a, [u8]>, u64), String> {
+    let unpacked_len = raw_data.len() as u64;
+
+    let data = match compressor {
+        Some(compressor) if options.should_compress(&node.media_type) => {
+            let compressed = compressor.compress(&raw_data, options.compression_level)?;
+            // v1 packages have no way of marking individual nodes as uncompressed
+            if options.version < 2 || compressed.len() < raw_data.len() {
+                Cow::Owned(compressed)
+            } else {
+                *flags |= ND_FLAG_UNCOMPRESSED;
+                raw_data
+            }
+        }
+        Some(_) => {
+            *flags |= ND_FLAG_UNCOMPRESSED;
+            raw_data
+        }
+        None => raw_data,
+    };
+
+    Ok((data, unpacked_len))
+}
+
+// copies a node's packed data from the previous build, or returns None without writing
+// anything if it's no longer intact
+fn copy_cached_node_data<S: PartSink>(
+    entry: &CacheEntry,
+    prev_build: &PrevBuild,
+    sink: &mut S,
+) -> Result<Option<WrittenNodeData>, String> {
+    let Some(src) = prev_build.open_packed(entry) else {
+        return Ok(None);
+    };
+
+    let node_start = sink.stream_position().map_err(|e| e.to_string())?;
+    let mut writer = CrcWriter::new(&mut *sink);
+    let copied = io::copy(&mut src.take(entry.packed_len), &mut writer);
+    if copied.is_ok_and(|len| len == entry.packed_len) && writer.crc() == entry.crc {
+        return Ok(Some(WrittenNodeData {
+            packed_len: entry.packed_len,
+            unpacked_len: entry.unpacked_len,
+            crc: entry.crc,
+            flags: entry.flags,
+            content_crc: entry.content_crc,
+        }));
+    }
+
+    sink.truncate(node_start).map_err(|e| e.to_string())?;
+    sink.seek(SeekFrom::Start(node_start)).map_err(|e| e.to_string())?;
+    Ok(None)
+}
+
+fn stream_node_data<S: PartSink>(
+    node: &PackNode,
+    sink: &mut S,
+    compressor: Option<&dyn Compressor>,
+    options: &PackingOptions,
+) -> Result<WrittenNodeData, String> {
+    let node_start = sink.stream_position().map_err(|e| e.to_string())?;
+
+    if let NodeSource::Packed { package, index } = &node.source {
+        return copy_packed_node_data(package, *index, sink, options);
+    }
+
+    let (mut src, rewindable): (Box<dyn ReadSeek>, bool) = match &node.source {
+        NodeSource::File { path, .. } =>
+            (Box::new(File::open(path).map_err(|e| e.to_string())?), true),
+        NodeSource::Reader(reader) => {
+            let reader = reader.lock().unwrap().take()
+                .ok_or_else(|| "Resource reader was already consumed".to_owned())?;
+            (Box::new(UnseekableReader(reader)), false)
+        }
+        _ => panic!("Node data cannot be streamed"),
+    };
+
+    let mut flags = 0u8;
+
+    match compressor {
+        // a reader can only be read once, so if the part can be read back the raw data is
+        // written first, allowing it to be kept if compression doesn't help
+        Some(compressor) if options.should_compress(&node.media_type)
+            && !rewindable && options.version >= 2 && sink.is_readable() => {
+            return stream_once_node_data(&mut src, sink, compressor, options);
+        }
+        Some(compressor) if options.should_compress(&node.media_type) => {
+            let mut reader = CountingReader::new(&mut src);
+            let mut writer = CrcWriter::new(&mut *sink);
+            compressor.compress_stream(&mut reader, &mut writer, options.compression_level)?;
+
+            let unpacked_len = reader.len();
+            let packed_len = writer.len();
+            // v1 packages have no way of marking individual nodes as uncompressed, and
+            // readers can't be rewound to be written again if the part can't be read back
+            if options.version < 2 || packed_len < unpacked_len || !rewindable {
+                return Ok(WrittenNodeData {
+                    packed_len,
+                    unpacked_len,
+                    crc: writer.crc(),
+                    flags,
+                    content_crc: reader.crc(),
+                });
+            }
+
+            // compression didn't help, so go back and overwrite it with the raw data
+            sink.seek(SeekFrom::Start(node_start)).map_err(|e| e.to_string())?;
+            src.rewind().map_err(|e| e.to_string())?;
+            flags |= ND_FLAG_UNCOMPRESSED;
+        }
+        Some(_) => {
+            flags |= ND_FLAG_UNCOMPRESSED;
+        }
+        None => {}
+    }
+
+    let mut writer = CrcWriter::new(&mut *sink);
+    let unpacked_len = io::copy(&mut src, &mut writer).map_err(|e| e.to_string())?;
+    let crc = writer.crc();
+    // discard whatever is left of an abandoned compressed stream
+    sink.truncate(node_start + unpacked_len).map_err(|e| e.to_string())?;
+
+    Ok(WrittenNodeData {
+        packed_len: unpacked_len,
+        unpacked_len,
+        crc,
+        flags,
+        content_crc: crc,
     })
 }
 
-fn compute_node_desc_len(node: &FsNode) -> u16 {
-    let stem_len = if node.index == 0 {
-        0
-    } else {
-        node.target_path.file_stem().unwrap().as_encoded_bytes().len()
+// writes data which can only be read once, raw at first, then compresses it from the part into
+// the space following it and moves the result down over the raw data if it's smaller
+fn stream_once_node_data<S: PartSink>(
+    src: &mut dyn Read,
+    sink: &mut S,
+    compressor: &dyn Compressor,
+    options: &PackingOptions,
+) -> Result<WrittenNodeData, String> {
+    let node_start = sink.stream_position().map_err(|e| e.to_string())?;
+
+    let mut writer = CrcWriter::new(&mut *sink);
+    let unpacked_len = io::copy(src, &mut writer).map_err(|e| e.to_string())?;
+    let raw_crc = writer.crc();
+    let raw_end = node_start + unpacked_len;
+
+    let shared = RefCell::new(&mut *sink);
+    let mut reader = SinkCursor { sink: &shared, pos: node_start, end: raw_end };
+    let mut writer = CrcWriter::new(SinkCursor { sink: &shared, pos: raw_end, end: u64::MAX });
+    compressor.compress_stream(&mut reader, &mut writer, options.compression_level)?;
+    let (packed_len, crc) = (writer.len(), writer.crc());
+
+    if packed_len < unpacked_len {
+        let mut buf = vec![0u8; 64 * 1024];
+        let mut copied = 0;
+        while copied < packed_len {
+            let len = buf.len().min((packed_len - copied) as usize);
+            sink.seek(SeekFrom::Start(raw_end + copied)).map_err(|e| e.to_string())?;
+            sink.read_exact(&mut buf[..len]).map_err(|e| e.to_string())?;
+            sink.seek(SeekFrom::Start(node_start + copied)).map_err(|e| e.to_string())?;
+            sink.write_all(&buf[..len]).map_err(|e| e.to_string())?;
+            copied += len as u64;
+        }
+        sink.truncate(node_start + packed_len).map_err(|e| e.to_string())?;
+
+        return Ok(WrittenNodeData {
+            packed_len,
+            unpacked_len,
+            crc,
+            flags: 0,
+            content_crc: raw_crc,
+        });
+    }
+
+    // compression didn't help, so discard it and keep the raw data
+    sink.truncate(raw_end).map_err(|e| e.to_string())?;
+    sink.seek(SeekFrom::Start(raw_end)).map_err(|e| e.to_string())?;
+
+    Ok(WrittenNodeData {
+        packed_len: unpacked_len,
+        unpacked_len,
+        crc: raw_crc,
+        flags: ND_FLAG_UNCOMPRESSED,
+        content_crc: raw_crc,
+    })
+}
+
+// copies a resource's packed data from an existing package without buffering it
+fn copy_packed_node_data<S: PartSink>(
+    package: &Package,
+    index: u32,
+    sink: &mut S,
+    options: &PackingOptions,
+) -> Result<WrittenNodeData, String> {
+    let resource = &package.catalogue.resources[&index];
+    let flags = copyable_packed_flags(package, index, options)
+        .expect("Only compatible packed data can be streamed");
+
+    let mut writer = CrcWriter::new(&mut *sink);
+    let packed_len = io::copy(&mut package.open_packed_data(index)?, &mut writer)
+        .map_err(|e| e.to_string())?;
+    if packed_len != resource.data_len_packed || writer.crc() != resource.crc {
+        return Err("CRC mismatch".to_owned());
+    }
+
+    Ok(WrittenNodeData {
+        packed_len,
+        unpacked_len: resource.data_len_unpacked,
+        crc: resource.crc,
+        flags,
+        content_crc: 0,
+    })
+}
+
+trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// Reads from or writes to a sink at its own position, so that a reader and a writer can share
+/// the same sink.
+struct SinkCursor<'a, S: PartSink> {
+    sink: &'a RefCell<&'a mut S>,
+    pos: u64,
+    // reads stop here
+    end: u64,
+}
+
+impl<S: PartSink> Read for SinkCursor<'_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.end.saturating_sub(self.pos) as usize);
+        let mut sink = self.sink.borrow_mut();
+        sink.seek(SeekFrom::Start(self.pos))?;
+        let read = sink.read(&mut buf[..len])?;
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl<S: PartSink> Write for SinkCursor<'_, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut sink = self.sink.borrow_mut();
+        sink.seek(SeekFrom::Start(self.pos))?;
+        let written = sink.write(buf)?;
+        self.pos += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.borrow_mut().flush()
+    }
+}
+
+struct UnseekableReader(Box<dyn Read + Send>);
+
+impl Read for UnseekableReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Seek for UnseekableReader {
+    fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "Reader is not seekable"))
+    }
+}
+
+// pushes a descriptor for the node with its data fields zeroed out, to be filled in by
+// patch_node_desc once the node's data has been written
+pub(crate) fn push_node_desc(catalogue: &mut Vec<u8>, node: &PackNode, version: u16) {
+    let type_ordinal = match node.source {
+        NodeSource::Directory(_) => PACK_NODE_TYPE_DIRECTORY,
+        NodeSource::Tombstone => PACK_NODE_TYPE_TOMBSTONE,
+        _ => PACK_NODE_TYPE_RESOURCE,
     };
-    let ext_len = node.target_path.extension()
-        .map(|ext| ext.as_encoded_bytes().len())
-        .unwrap_or(0);
+
+    let name_len = node.name.len();
+    let ext_len = node.ext.len();
     let media_type_len = node.media_type.len();
+    assert!(name_len <= u8::MAX as usize);
+    assert!(ext_len <= u8::MAX as usize);
+    assert!(media_type_len <= u8::MAX as usize);
+
+    let node_desc_len = compute_node_desc_len(node, version);
+    let start_len = catalogue.len();
+    push_u16_le(catalogue, node_desc_len);
+    catalogue.push(type_ordinal);
+    // part, data offset, packed length, unpacked length, CRC
+    catalogue.resize(catalogue.len() + ND_NAME_LEN_OFF - ND_PART_OFF, 0u8);
+    catalogue.push(name_len as u8);
+    catalogue.push(ext_len as u8);
+    catalogue.push(media_type_len as u8);
+    if version >= 2 {
+        // flags
+        catalogue.push(0u8);
+    }
+    catalogue.extend_from_slice(node.name.as_bytes());
+    catalogue.extend_from_slice(node.ext.as_bytes());
+    catalogue.extend_from_slice(node.media_type.as_bytes());
+    assert_eq!(catalogue.len() - start_len, node_desc_len as usize);
+}
 
-    (NODE_DESC_BASE_LEN + stem_len + ext_len + media_type_len) as u16
+pub(crate) fn patch_node_desc(desc: &mut [u8], part: u16, data_off: u64, written: &WrittenNodeData) {
+    write_u16_le(desc, ND_PART_OFF, part);
+    write_u64_le(desc, ND_DATA_OFF_OFF, data_off);
+    write_u64_le(desc, ND_PACKED_DATA_LEN_OFF, written.packed_len);
+    write_u64_le(desc, ND_UNPACKED_DATA_LEN_OFF, written.unpacked_len);
+    write_u32_le(desc, ND_CRC_OFF, written.crc);
+    // the flags field only exists in v2+ descriptors, which are at least as long as this
+    if written.flags != 0 {
+        desc[ND_FLAGS_OFF] = written.flags;
+    }
 }
 
-fn compute_catalogue_len(nodes: &Vec<FsNode>) -> u64 {
-    let catalogue_base_len: u64 = (nodes.len() * NODE_DESC_BASE_LEN) as u64;
-    let mut catalogue_len: u64 = catalogue_base_len;
+fn compute_node_desc_len(node: &PackNode, version: u16) -> u16 {
+    (node_desc_base_len(version) + node.name.len() + node.ext.len() + node.media_type.len())
+        as u16
+}
 
-    for node in nodes {
-        catalogue_len += compute_node_desc_len(node) as u64;
+fn node_desc_base_len(version: u16) -> usize {
+    if version >= 2 {
+        NODE_DESC_BASE_LEN_V2
+    } else {
+        NODE_DESC_BASE_LEN
     }
+}
+
+fn compute_catalogue_len(nodes: &[PackNode], version: u16) -> u64 {
+    nodes.iter()
+        .map(|node| compute_node_desc_len(node, version) as u64)
+        .sum()
+}
 
-    catalogue_len
+#[inline(always)]
+pub(crate) fn part_file_name(name: &str, index: usize, total_parts: usize) -> String {
+    if total_parts == 1 {
+        format!("{}.arp", name)
+    } else {
+        format!("{}.part{:0>3}.arp", name, index)
+    }
+}
+
+fn write_u16_le(buf: &mut [u8], off: usize, val: u16) {
+    buf[off..(off + size_of::<u16>())].copy_from_slice(&val.to_le_bytes());
+}
+
+#[inline(always)]
+pub(crate) fn write_u32_le(buf: &mut [u8], off: usize, val: u32) {
+    buf[off..(off + size_of::<u32>())].copy_from_slice(&val.to_le_bytes());
+}
+
+#[inline(always)]
+pub(crate) fn write_u64_le(buf: &mut [u8], off: usize, val: u64) {
+    buf[off..(off + size_of::<u64>())].copy_from_slice(&val.to_le_bytes());
 }
 
 #[inline(always)]
-fn push_u16_le(buf: &mut Vec<u8>, val: u16) {
+pub(crate) fn push_u16_le(buf: &mut Vec<u8>, val: u16) {
     buf.extend_from_slice(&val.to_le_bytes());
 }
 
 #[inline(always)]
-fn push_u32_le(buf: &mut Vec<u8>, val: u32) {
+pub(crate) fn push_u32_le(buf: &mut Vec<u8>, val: u32) {
     buf.extend_from_slice(&val.to_le_bytes());
 }
 
 #[inline(always)]
-fn push_u64_le(buf: &mut Vec<u8>, val: u64) {
+pub(crate) fn push_u64_le(buf: &mut Vec<u8>, val: u64) {
     buf.extend_from_slice(&val.to_le_bytes());
 }
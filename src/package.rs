@@ -1,34 +1,43 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::io::{Cursor, Read, Seek};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
 use std::path::Path;
 use std::sync::{Arc, RwLock};
 use crate::defines::*;
-use crate::{CompressionType, ResourceDescriptor, ResourceIdentifier, DEFAULT_MEDIA_TYPE};
+use crate::util::crc32c::crc32c;
+use crate::util::glob::PathPattern;
+use crate::util::io::CountingReader;
+use crate::util::uid::validate_node_name;
+use crate::{
+    CodecRegistry, CompressionType, PackageKind, ResourceDescriptor, ResourceIdentifier,
+    DEFAULT_MEDIA_TYPE,
+};
 
 pub struct Package {
     pub(crate) meta: PackageMeta,
     pub(crate) catalogue: LoadedCatalogue,
     pub(crate) base_file_name: Option<String>,
     pub(crate) part_files: Option<Arc<RwLock<Vec<File>>>>,
-    pub(crate) mem_buffer: Option<&'static [u8]>,
+    pub(crate) mem_buffer: Option<Cow<'static, [u8]>>,
+    pub(crate) codecs: Arc<CodecRegistry>,
 }
 
 pub(crate) struct LoadedCatalogue {
     pub(crate) dirs: HashMap<u32, DirectoryNode>,
     pub(crate) resources: HashMap<u32, ResourceNode>,
+    // names of tombstone nodes, which only occur in patch packages
+    pub(crate) tombstones: HashMap<u32, String>,
 }
 
 pub(crate) struct DirectoryNode {
-    index: u32,
-    name: String,
+    pub(crate) name: String,
     data_off: u64,
     data_len: u64,
-    children: HashMap<String, u32>,
+    pub(crate) children: HashMap<String, u32>,
 }
 
 pub(crate) struct ResourceNode {
-    pub(crate) index: u32,
     pub(crate) name: String,
     pub(crate) ext: String,
     pub(crate) media_type: String,
@@ -37,6 +46,13 @@ pub(crate) struct ResourceNode {
     pub(crate) data_len_packed: u64,
     pub(crate) data_len_unpacked: u64,
     pub(crate) crc: u32,
+    pub(crate) flags: u8,
+}
+
+impl ResourceNode {
+    pub(crate) fn is_compressed(&self, meta: &PackageMeta) -> bool {
+        meta.compression_type.is_some() && (self.flags & ND_FLAG_UNCOMPRESSED) == 0
+    }
 }
 
 pub struct PackageMeta {
@@ -51,10 +67,16 @@ pub struct PackageMeta {
     pub resource_count: u32,
     pub body_off: u64,
     pub body_len: u64,
+    pub kind: PackageKind,
 }
 
 impl Package {
     pub fn load_from_file(path: impl AsRef<Path>) -> Result<Arc<Self>, String> {
+        Self::load_from_file_with_codecs(path, Arc::new(CodecRegistry::default()))
+    }
+
+    pub fn load_from_file_with_codecs(path: impl AsRef<Path>, codecs: Arc<CodecRegistry>)
+        -> Result<Arc<Self>, String> {
         let path_ref = path.as_ref();
 
         if !path_ref.is_file() {
@@ -66,6 +88,7 @@ impl Package {
         let package_meta = load_header_from(&mut main_file).map_err(|e| e.to_string())?;
 
         validate_package_meta(&package_meta).map_err(|e| e.to_string())?;
+        validate_codec(&package_meta, &codecs)?;
 
         let catalogue = load_catalogue_from(&mut main_file, &package_meta).map_err(|e| e.to_string())?;
 
@@ -83,10 +106,10 @@ impl Package {
         part_files.push(main_file);
         for i in 1..package_meta.total_parts {
             let part_file_name = format!(
-                "{:?}.part{:0>3}{:?}",
-                path_ref.file_stem().unwrap(),
+                "{}.part{:0>3}.{}",
+                base_file_name,
                 i + 1,
-                path_ref.extension().unwrap(),
+                path_ref.extension().unwrap().to_string_lossy(),
             );
             let part_file_path = path_ref.with_file_name(&part_file_name);
             if !part_file_path.is_file() {
@@ -102,6 +125,7 @@ impl Package {
             base_file_name: Some(base_file_name),
             part_files: Some(Arc::new(RwLock::new(part_files))),
             mem_buffer: None,
+            codecs,
         }))
     }
 
@@ -136,13 +160,35 @@ impl Package {
     }
 
     pub fn load_from_memory(data: &'static [u8]) -> Result<Arc<Self>, String> {
-        let mut cursor = Cursor::new(data);
+        Self::load_from_memory_with_codecs(data, Arc::new(CodecRegistry::default()))
+    }
+
+    pub fn load_from_memory_with_codecs(data: &'static [u8], codecs: Arc<CodecRegistry>)
+        -> Result<Arc<Self>, String> {
+        Self::load_from_buffer(Cow::Borrowed(data), codecs)
+    }
+
+    /// Loads a single-part package from an owned buffer, such as one produced by
+    /// [pack_to_vec](crate::pack_to_vec).
+    pub fn load_from_vec(data: Vec<u8>) -> Result<Arc<Self>, String> {
+        Self::load_from_vec_with_codecs(data, Arc::new(CodecRegistry::default()))
+    }
+
+    pub fn load_from_vec_with_codecs(data: Vec<u8>, codecs: Arc<CodecRegistry>)
+        -> Result<Arc<Self>, String> {
+        Self::load_from_buffer(Cow::Owned(data), codecs)
+    }
+
+    fn load_from_buffer(data: Cow<'static, [u8]>, codecs: Arc<CodecRegistry>)
+        -> Result<Arc<Self>, String> {
+        let mut cursor = Cursor::new(data.as_ref());
         let package_meta = load_header_from(&mut cursor).map_err(|e| e.to_string())?;
 
         if package_meta.total_parts > 1 {
             return Err("In-memory packages cannot contain multiple parts".to_owned());
         }
         validate_package_meta(&package_meta).map_err(|e| e.to_string())?;
+        validate_codec(&package_meta, &codecs)?;
 
         let catalogue = load_catalogue_from(&mut cursor, &package_meta).map_err(|e| e.to_string())?;
 
@@ -152,21 +198,75 @@ impl Package {
             base_file_name: None,
             part_files: None,
             mem_buffer: Some(data),
+            codecs,
         }))
     }
 
+    pub fn get_meta(&self) -> &PackageMeta {
+        &self.meta
+    }
+
     pub fn get_namespace(&self) -> &str {
         self.meta.namespace.as_str()
     }
 
     pub fn get_base_file_name(&self) -> Option<&str> {
-        self.base_file_name.as_ref().map(|s| s.as_str())
+        self.base_file_name.as_deref()
     }
 
     pub fn is_in_memory(&self) -> bool {
         self.mem_buffer.is_some()
     }
 
+    pub fn get_kind(&self) -> PackageKind {
+        self.meta.kind
+    }
+
+    pub fn is_patch(&self) -> bool {
+        self.meta.kind == PackageKind::Patch
+    }
+
+    /// Returns the identifiers marked as deleted by this package. Each one hides the resource
+    /// with the same identifier, along with any resources beneath it, in packages which the
+    /// patch is layered over. Always empty for base packages.
+    pub fn get_tombstones(&self) -> Vec<ResourceIdentifier> {
+        let mut tombstones = self.tombstone_paths().into_iter()
+            .map(|components| ResourceIdentifier::new(self.meta.namespace.clone(), components))
+            .collect::<Vec<_>>();
+        tombstones.sort();
+        tombstones
+    }
+
+    /// Returns whether this package hides the given identifier in packages it's layered over.
+    ///
+    /// Only patches hide identifiers, either with a tombstone covering them or by replacing a
+    /// node on their path with one of a different type, i.e. a directory in place of the
+    /// resource or a resource in place of one of its parent directories.
+    pub(crate) fn hides(&self, uid: &ResourceIdentifier) -> bool {
+        if !self.is_patch() || self.meta.namespace != uid.namespace {
+            return false;
+        }
+
+        let mut cur_dir = self.catalogue.dirs.get(&0)
+            .expect("Failed to get root directory of package");
+        for (i, component) in uid.components.iter().enumerate() {
+            let Some(&child_index) = cur_dir.children.get(component) else {
+                return false;
+            };
+            if self.catalogue.tombstones.contains_key(&child_index) {
+                return true;
+            }
+            let Some(next_dir) = self.catalogue.dirs.get(&child_index) else {
+                // a resource only replaces the identifier if it's one of its parents
+                return i + 1 < uid.components.len();
+            };
+            cur_dir = next_dir;
+        }
+
+        // a directory exists at the identifier's path
+        true
+    }
+
     pub fn find_resource(self: &Arc<Self>, uid: &ResourceIdentifier)
                          -> Result<ResourceDescriptor, String> {
         if self.meta.namespace != uid.namespace {
@@ -202,46 +302,250 @@ impl Package {
             extension: resource_node.ext.clone(),
             media_type: resource_node.media_type.clone(),
             size: resource_node.data_len_unpacked,
+            packed_size: resource_node.data_len_packed,
             index: resource_node_index,
         })
     }
 
     pub fn get_all_resource_descriptors(self: &Arc<Package>) -> Vec<ResourceDescriptor> {
+        self.resource_paths().into_iter()
+            .map(|(components, index)| {
+                let child_res = &self.catalogue.resources[&index];
+                ResourceDescriptor {
+                    package: Arc::clone(self),
+                    identifier: ResourceIdentifier::new(self.meta.namespace.clone(), components),
+                    name: child_res.name.clone(),
+                    extension: child_res.ext.clone(),
+                    media_type: child_res.media_type.clone(),
+                    size: child_res.data_len_unpacked,
+                    packed_size: child_res.data_len_packed,
+                    index,
+                }
+            })
+            .collect()
+    }
+
+    /// Returns descriptors for the resources whose UID paths, excluding the namespace, match the
+    /// given gitignore-style glob, e.g. `textures/**/*_n`. A pattern matching a directory matches
+    /// every resource beneath it. The descriptors are sorted by identifier.
+    pub fn find_resources_matching(self: &Arc<Package>, pattern: &str) -> Vec<ResourceDescriptor> {
+        let pattern = PathPattern::new(pattern);
+        let mut resources = self.get_all_resource_descriptors().into_iter()
+            .filter(|desc| {
+                let components = &desc.identifier.components;
+                (1..=components.len()).any(|len| {
+                    let path = components[..len].join(&UID_PATH_SEPARATOR.to_string());
+                    pattern.matches(&path, len < components.len())
+                })
+            })
+            .collect::<Vec<_>>();
+        resources.sort_by(|a, b| a.identifier.cmp(&b.identifier));
+        resources
+    }
+
+    /// Returns the UID path components and node index of every resource in the package.
+    pub(crate) fn resource_paths(&self) -> Vec<(Vec<String>, u32)> {
+        self.node_paths().into_iter()
+            .filter(|(_, index)| self.catalogue.resources.contains_key(index))
+            .collect()
+    }
+
+    /// Returns the UID path components of every tombstone in the package.
+    pub(crate) fn tombstone_paths(&self) -> Vec<Vec<String>> {
+        if self.catalogue.tombstones.is_empty() {
+            return Vec::new();
+        }
+
+        self.node_paths().into_iter()
+            .filter(|(_, index)| self.catalogue.tombstones.contains_key(index))
+            .map(|(components, _)| components)
+            .collect()
+    }
+
+    /// Returns the UID path components of every directory in the package besides the root.
+    pub(crate) fn directory_paths(&self) -> Vec<Vec<String>> {
+        self.node_paths().into_iter()
+            .filter(|(_, index)| self.catalogue.dirs.contains_key(index))
+            .map(|(components, _)| components)
+            .collect()
+    }
+
+    // returns the path components and index of every node beneath the root directory
+    pub(crate) fn node_paths(&self) -> Vec<(Vec<String>, u32)> {
         let mut dir_queue = Vec::new();
-        let mut resources = Vec::new();
+        let mut nodes = Vec::new();
 
         let root_dir = self.catalogue.dirs.get(&0)
             .expect("Failed to get root directory for package");
-        dir_queue.push((root_dir, ResourceIdentifier::new(self.meta.namespace.clone(), vec![])));
-        while let Some((cur_dir, cur_uid)) = dir_queue.pop() {
+        dir_queue.push((root_dir, Vec::new()));
+        while let Some((cur_dir, cur_path)) = dir_queue.pop() {
             for (child_name, child_index) in &cur_dir.children {
-                let child_uid = ResourceIdentifier::new(
-                    cur_uid.namespace.clone(),
-                    {
-                        let mut c = cur_uid.components.clone();
-                        c.push(child_name.clone());
-                        c
-                    },
-                );
-                if let Some(child_dir) = self.catalogue.dirs.get(&child_index) {
-                    dir_queue.push((child_dir, child_uid));
-                } else if let Some(child_res) = self.catalogue.resources.get(&child_index) {
-                    resources.push(ResourceDescriptor {
-                        package: Arc::clone(self),
-                        identifier: child_uid,
-                        name: child_res.name.clone(),
-                        extension: child_res.ext.clone(),
-                        media_type: child_res.media_type.clone(),
-                        size: child_res.data_len_unpacked,
-                        index: *child_index,
-                    });
-                } else {
-                    //TODO: shouldn't happen
+                let mut child_path = cur_path.clone();
+                child_path.push(child_name.clone());
+                if let Some(child_dir) = self.catalogue.dirs.get(child_index) {
+                    dir_queue.push((child_dir, child_path.clone()));
                 }
+                nodes.push((child_path, *child_index));
             }
         }
 
-        resources
+        nodes
+    }
+
+    /// Reads the packed data of the resource with the given node index, verifying its CRC.
+    pub(crate) fn load_packed_data(&self, index: u32) -> Result<Vec<u8>, String> {
+        let resource = self.catalogue.resources.get(&index)
+            .ok_or_else(|| "No resource exists with the given index".to_owned())?;
+        let data_off = if resource.data_part == 1 {
+            self.meta.body_off + resource.data_off
+        } else {
+            PACKAGE_PART_HEADER_LEN + resource.data_off
+        };
+        let data_len_packed = resource.data_len_packed;
+
+        let resource_data = if let Some(mem_buffer) = self.mem_buffer.as_deref() {
+            assert!(self.part_files.is_none());
+            assert_eq!(resource.data_part, 1);
+
+            let data_end = data_off.checked_add(data_len_packed)
+                .filter(|end| *end <= mem_buffer.len() as u64)
+                .ok_or_else(|| "Resource data lies outside of package".to_owned())?;
+            Vec::from(&mem_buffer[(data_off as usize)..(data_end as usize)])
+        } else if let Some(part_files) = self.part_files.as_ref() {
+            let mut part_files_borrowed = part_files.write().unwrap();
+            let part_file = &mut part_files_borrowed[resource.data_part as usize - 1];
+
+            let mut buf = vec![0u8; data_len_packed as usize];
+            part_file.seek(SeekFrom::Start(data_off)).map_err(|e| e.to_string())?;
+            part_file.read_exact(&mut buf).map_err(|e| e.to_string())?;
+
+            buf
+        } else {
+            panic!("Memory buffer or part file list must be populated");
+        };
+
+        let actual_crc = crc32c(&resource_data);
+        if actual_crc != resource.crc {
+            return Err("CRC mismatch".to_owned());
+        }
+
+        Ok(resource_data)
+    }
+
+    /// Opens a reader over the packed data of the resource with the given node index, for
+    /// data too large to be loaded at once. Unlike [load_packed_data](Self::load_packed_data),
+    /// the CRC is not verified, so callers must check it themselves.
+    pub(crate) fn open_packed_data(&self, index: u32) -> Result<Box<dyn Read + '_>, String> {
+        let resource = self.catalogue.resources.get(&index)
+            .ok_or_else(|| "No resource exists with the given index".to_owned())?;
+        let data_off = if resource.data_part == 1 {
+            self.meta.body_off + resource.data_off
+        } else {
+            PACKAGE_PART_HEADER_LEN + resource.data_off
+        };
+
+        if let Some(mem_buffer) = self.mem_buffer.as_deref() {
+            let data_end = data_off.checked_add(resource.data_len_packed)
+                .filter(|end| *end <= mem_buffer.len() as u64)
+                .ok_or_else(|| "Resource data lies outside of package".to_owned())?;
+            Ok(Box::new(&mem_buffer[(data_off as usize)..(data_end as usize)]))
+        } else if let Some(part_files) = self.part_files.as_ref() {
+            Ok(Box::new(PartDataReader {
+                part_files,
+                part: resource.data_part as usize - 1,
+                pos: data_off,
+                remaining: resource.data_len_packed,
+            }))
+        } else {
+            panic!("Memory buffer or part file list must be populated");
+        }
+    }
+
+    /// Reads and decompresses the data of the resource with the given node index.
+    pub(crate) fn load_resource_data(&self, index: u32) -> Result<Vec<u8>, String> {
+        let resource_data = self.load_packed_data(index)?;
+        let resource = &self.catalogue.resources[&index];
+
+        let compression_type = if resource.is_compressed(&self.meta) {
+            self.meta.compression_type.as_ref()
+        } else {
+            None
+        };
+        let resource_data = match compression_type {
+            Some(compression_type) => {
+                let decompressor = self.codecs
+                    .get_decompressor(&compression_type.get_magic())?;
+                decompressor.decompress(&resource_data, resource.data_len_unpacked)?
+            }
+            None => resource_data,
+        };
+
+        Ok(resource_data)
+    }
+
+    /// Writes the data of the resource with the given node index to `writer` without loading
+    /// it all into memory, decompressing it unless `raw` is set. The CRC can only be verified
+    /// once all of the data has been read, so `writer` may already have received corrupt data
+    /// by the time an error is returned.
+    pub(crate) fn write_resource_data(&self, index: u32, writer: &mut dyn Write, raw: bool)
+        -> Result<(), String> {
+        let resource = self.catalogue.resources.get(&index)
+            .ok_or_else(|| "No resource exists with the given index".to_owned())?;
+        let mut reader = CountingReader::new(self.open_packed_data(index)?);
+
+        let compression_type = if !raw && resource.is_compressed(&self.meta) {
+            self.meta.compression_type.as_ref()
+        } else {
+            None
+        };
+        match compression_type {
+            Some(compression_type) => {
+                let decompressor = self.codecs
+                    .get_decompressor(&compression_type.get_magic())?;
+                decompressor.decompress_stream(&mut reader, writer, resource.data_len_unpacked)?;
+                // the CRC covers the whole of the packed data, including anything trailing
+                io::copy(&mut reader, &mut io::sink()).map_err(|e| e.to_string())?;
+            }
+            None => {
+                io::copy(&mut reader, writer).map_err(|e| e.to_string())?;
+            }
+        }
+
+        if reader.crc() != resource.crc {
+            return Err("CRC mismatch".to_owned());
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads a range of a part file. The part files are shared by all readers of the package, so
+/// the position is restored on every read.
+struct PartDataReader<'a> {
+    part_files: &'a RwLock<Vec<File>>,
+    part: usize,
+    pos: u64,
+    remaining: u64,
+}
+
+impl Read for PartDataReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.remaining == 0 {
+            return Ok(0);
+        }
+
+        let len = buf.len().min(self.remaining as usize);
+        let mut part_files = self.part_files.write().unwrap();
+        let part_file = &mut part_files[self.part];
+        part_file.seek(SeekFrom::Start(self.pos))?;
+        let read = part_file.read(&mut buf[..len])?;
+        if read == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+
+        self.pos += read as u64;
+        self.remaining -= read as u64;
+        Ok(read)
     }
 }
 
@@ -260,8 +564,7 @@ fn load_magic_from<R: Read + Seek>(reader: &mut R) -> Result<[u8; PACK_HEADER_MA
 
 fn load_catalogue_from<R: Read + Seek>(reader: &mut R, package_meta: &PackageMeta)
     -> Result<LoadedCatalogue, String> {
-    let mut catalogue_buf = Vec::with_capacity(package_meta.cat_len as usize);
-    catalogue_buf.resize(package_meta.cat_len as usize, 0u8);
+    let mut catalogue_buf = vec![0u8; package_meta.cat_len as usize];
     reader.seek(std::io::SeekFrom::Start(package_meta.cat_off)).map_err(|e| e.to_string())?;
     reader.read_exact(
         catalogue_buf.as_mut_slice(),
@@ -269,6 +572,8 @@ fn load_catalogue_from<R: Read + Seek>(reader: &mut R, package_meta: &PackageMet
         .map_err(|e| e.to_string())?;
     let mut catalogue = parse_catalogue(
         &catalogue_buf,
+        package_meta.major_version,
+        package_meta.kind,
         package_meta.node_count,
         package_meta.directory_count,
         package_meta.resource_count,
@@ -280,12 +585,24 @@ fn load_catalogue_from<R: Read + Seek>(reader: &mut R, package_meta: &PackageMet
             catalogue.resources.iter()
                 .map(|(i, n)| (*i, n.name.clone()))
         )
+        .chain(catalogue.tombstones.iter().map(|(i, name)| (*i, name.clone())))
         .collect::<HashMap<u32, String>>();
 
-    for (_, dir_node) in &mut catalogue.dirs {
-        assert_eq!(dir_node.data_len % 4, 0);
-        let mut child_indices_buf: Vec<u8> = Vec::with_capacity(dir_node.data_len as usize);
-        child_indices_buf.resize(dir_node.data_len as usize, 0);
+    if !catalogue.dirs.contains_key(&0) {
+        return Err("Root node is not a directory".to_owned());
+    }
+    if catalogue.resources.values()
+        .any(|res| res.data_part == 0 || res.data_part > package_meta.total_parts) {
+        return Err("Resource data lies in a nonexistent part".to_owned());
+    }
+
+    // every node may have only one parent, and the root none, so that the tree can't loop
+    let mut parented = HashSet::from([0]);
+    for dir_node in catalogue.dirs.values_mut() {
+        if dir_node.data_len % size_of::<u32>() as u64 != 0 {
+            return Err("Directory data length is not a multiple of 4".to_owned());
+        }
+        let mut child_indices_buf: Vec<u8> = vec![0u8; dir_node.data_len as usize];
         reader.seek(std::io::SeekFrom::Start(package_meta.body_off + dir_node.data_off))
             .map_err(|e| e.to_string())?;
         reader.read_exact(&mut child_indices_buf)
@@ -298,7 +615,14 @@ fn load_catalogue_from<R: Read + Seek>(reader: &mut R, package_meta: &PackageMet
 
         dir_node.children.reserve(child_count);
         for child_index in child_indices {
-            dir_node.children.insert(node_names.get(&child_index).unwrap().clone(), child_index);
+            let child_name = node_names.get(&child_index)
+                .ok_or_else(|| format!("Directory refers to nonexistent node {}", child_index))?;
+            if !parented.insert(child_index) {
+                return Err(format!("Node {} has more than one parent", child_index));
+            }
+            if dir_node.children.insert(child_name.clone(), child_index).is_some() {
+                return Err(format!("Directory contains duplicate name '{}'", child_name));
+            }
         }
     }
 
@@ -327,11 +651,15 @@ fn parse_header(header: &[u8]) -> Result<PackageMeta, String> {
         return Err("Format magic is incorrect".to_owned());
     }
 
+    // the kind field falls within the reserved section of v1 headers
+    let kind = if version >= 2 {
+        PackageKind::from_ordinal(header[PACK_HEADER_KIND_OFF])?
+    } else {
+        PackageKind::Base
+    };
+
     let compression_type = if compress_magic[0] != 0 {
-        Some(match CompressionType::from_magic(compress_magic.try_into().unwrap()) {
-            Some(c) => c,
-            None => { return Err("Compression magic not recognized".to_owned()) }
-        })
+        Some(CompressionType::from_magic(compress_magic.try_into().unwrap()))
     } else {
         None
     };
@@ -348,23 +676,39 @@ fn parse_header(header: &[u8]) -> Result<PackageMeta, String> {
         resource_count: res_count,
         body_off,
         body_len,
+        kind,
     })
 }
 
-fn parse_catalogue(buf: &[u8], node_count: u32, dir_count: u32, resource_count: u32)
-    -> Result<LoadedCatalogue, String> {
+fn parse_catalogue(
+    buf: &[u8],
+    version: u16,
+    kind: PackageKind,
+    node_count: u32,
+    dir_count: u32,
+    resource_count: u32,
+) -> Result<LoadedCatalogue, String> {
     let mut cursor = Cursor::new(buf);
 
+    let (desc_base_len, name_off) = if version >= 2 {
+        (NODE_DESC_BASE_LEN_V2, ND_NAME_OFF_V2)
+    } else {
+        (NODE_DESC_BASE_LEN, ND_NAME_OFF)
+    };
+
     let mut dir_nodes = HashMap::with_capacity(dir_count as usize);
     let mut res_nodes = HashMap::with_capacity(resource_count as usize);
+    let mut tombstone_nodes = HashMap::new();
 
     for index in 0..node_count {
         let mut len_buf = [0u8; 2];
         cursor.read_exact(&mut len_buf).map_err(|e| e.to_string())?;
         let len = u16::from_le_bytes(len_buf);
+        if (len as usize) < desc_base_len {
+            return Err("Node descriptor is too short".to_owned());
+        }
 
-        let mut desc_buf = Vec::with_capacity(len as usize);
-        desc_buf.resize(len as usize, 0u8);
+        let mut desc_buf = vec![0u8; len as usize];
         cursor.read_exact(&mut desc_buf[2..]).map_err(|e| e.to_string())?;
 
         let ty = desc_buf[ND_TYPE_OFF];
@@ -376,6 +720,7 @@ fn parse_catalogue(buf: &[u8], node_count: u32, dir_count: u32, resource_count:
         let name_len = desc_buf[ND_NAME_LEN_OFF];
         let ext_len = desc_buf[ND_EXT_LEN_OFF];
         let mt_len = desc_buf[ND_MT_LEN_OFF];
+        let flags = if version >= 2 { desc_buf[ND_FLAGS_OFF] } else { 0 };
 
         let mut name_buf = Vec::with_capacity(name_len as usize);
         let mut ext_buf = Vec::with_capacity(ext_len as usize);
@@ -385,13 +730,15 @@ fn parse_catalogue(buf: &[u8], node_count: u32, dir_count: u32, resource_count:
         ext_buf.resize(ext_len as usize, 0);
         mt_buf.resize(mt_len as usize, 0);
 
-        let mut subcursor = Cursor::new(&desc_buf[ND_NAME_OFF..]);
+        let mut subcursor = Cursor::new(&desc_buf[name_off..]);
         subcursor.read_exact(&mut name_buf).map_err(|e| e.to_string())?;
         subcursor.read_exact(&mut ext_buf).map_err(|e| e.to_string())?;
         subcursor.read_exact(&mut mt_buf).map_err(|e| e.to_string())?;
 
         let name = String::from_utf8(name_buf).map_err(|e| e.to_string())?;
         let ext = String::from_utf8(ext_buf).map_err(|e| e.to_string())?;
+        // names are untrusted, and must not be able to escape the directory they're extracted to
+        validate_node_name(&name, &ext, index == 0 && ty == PACK_NODE_TYPE_DIRECTORY)?;
         let media_type = if mt_len > 0 {
             String::from_utf8(mt_buf).map_err(|e| e.to_string())?
         } else {
@@ -401,7 +748,6 @@ fn parse_catalogue(buf: &[u8], node_count: u32, dir_count: u32, resource_count:
         match ty {
             PACK_NODE_TYPE_RESOURCE => {
                 res_nodes.insert(index, ResourceNode {
-                    index,
                     name,
                     ext,
                     media_type,
@@ -410,17 +756,20 @@ fn parse_catalogue(buf: &[u8], node_count: u32, dir_count: u32, resource_count:
                     data_len_packed: packed_len,
                     data_len_unpacked: unpacked_len,
                     crc,
+                    flags,
                 });
             }
             PACK_NODE_TYPE_DIRECTORY => {
                 dir_nodes.insert(index, DirectoryNode {
-                    index,
                     name,
                     data_off,
                     data_len: packed_len,
                     children: HashMap::new(),
                 });
             }
+            PACK_NODE_TYPE_TOMBSTONE if kind == PackageKind::Patch => {
+                tombstone_nodes.insert(index, name);
+            }
             _ => {
                 return Err("Encountered unrecognized node type".to_owned());
             }
@@ -430,11 +779,13 @@ fn parse_catalogue(buf: &[u8], node_count: u32, dir_count: u32, resource_count:
     Ok(LoadedCatalogue {
         dirs: dir_nodes,
         resources: res_nodes,
+        tombstones: tombstone_nodes,
     })
 }
 
 fn validate_package_meta(package_meta: &PackageMeta) -> Result<(), String> {
-    if package_meta.major_version != 1 {
+    if package_meta.major_version < FORMAT_VERSION_MIN ||
+        package_meta.major_version > FORMAT_VERSION_MAX {
         return Err("Unsupported format version".to_owned());
     }
 
@@ -445,6 +796,14 @@ fn validate_package_meta(package_meta: &PackageMeta) -> Result<(), String> {
     Ok(())
 }
 
+fn validate_codec(package_meta: &PackageMeta, codecs: &CodecRegistry) -> Result<(), String> {
+    if let Some(compression_type) = package_meta.compression_type.as_ref() {
+        codecs.get_decompressor(&compression_type.get_magic())?;
+    }
+
+    Ok(())
+}
+
 fn read_u16_le(buf: &[u8], off: usize) -> u16 {
     u16::from_le_bytes(buf[off..(off + size_of::<u16>())].try_into().unwrap())
 }
@@ -0,0 +1,57 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use crate::defines::UID_PATH_SEPARATOR;
+use crate::diff::resources_by_path;
+use crate::{diff, Package, PackageBuilder, PackageKind, PackingOptions};
+
+/// Prepares a patch package which, when layered over `old` in a
+/// [PackageSet](crate::PackageSet), presents the same resources as `new`.
+///
+/// The patch contains every resource which was added or modified in `new`, and a tombstone for
+/// every resource which was removed. No tombstone is needed where `new` replaces a removed
+/// resource with a directory, or one of its parent directories with a resource, since the patch
+/// hides the old resource by replacing it. Both packages must be base packages sharing the namespace
+/// given in `options`, which are written as a patch regardless of their configured kind.
+///
+/// Resources are copied from `new` without being recompressed if their packed data is
+/// compatible with `options`.
+pub fn make_patch(old: &Package, new: &Arc<Package>, options: PackingOptions)
+    -> Result<PackageBuilder, String> {
+    if old.is_patch() || new.is_patch() {
+        return Err("Patches can only be made from base packages".to_owned());
+    }
+    if old.meta.namespace != new.meta.namespace {
+        return Err("Packages must share a namespace".to_owned());
+    }
+    if options.namespace != new.meta.namespace {
+        return Err("Patch namespace must match that of the packages".to_owned());
+    }
+
+    let package_diff = diff(old, new)?;
+
+    let mut builder = PackageBuilder::new(options.with_kind(PackageKind::Patch))?;
+
+    let new_resources = resources_by_path(new);
+    let changed_paths = package_diff.added.iter()
+        .chain(package_diff.modified.iter().map(|res| &res.path));
+    for path in changed_paths {
+        let components = path.split(UID_PATH_SEPARATOR)
+            .map(|s| s.to_owned())
+            .collect::<Vec<_>>();
+        builder.insert_packed_resource(components, new, new_resources[path])?;
+    }
+
+    let new_dirs = new.directory_paths().into_iter()
+        .map(|components| components.join(&UID_PATH_SEPARATOR.to_string()))
+        .collect::<HashSet<_>>();
+    for path in &package_diff.removed {
+        let replaced = new_dirs.contains(path)
+            || path.match_indices(UID_PATH_SEPARATOR)
+                .any(|(i, _)| new_resources.contains_key(&path[..i]));
+        if !replaced {
+            builder.add_tombstone(path)?;
+        }
+    }
+
+    Ok(builder)
+}
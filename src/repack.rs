@@ -0,0 +1,30 @@
+use std::sync::Arc;
+use crate::defines::UID_PATH_SEPARATOR;
+use crate::{Package, PackageBuilder, PackageKind, PackingOptions};
+
+/// Prepares a copy of `package` to be written with different options, e.g. to change its part
+/// size, compression type or namespace, without extracting it first.
+///
+/// Packed data is copied as-is when the compression type is unchanged, and is otherwise
+/// decompressed and compressed again. Patch packages remain patches and keep their tombstones.
+pub fn repack(package: &Arc<Package>, options: PackingOptions) -> Result<PackageBuilder, String> {
+    let options = if package.is_patch() {
+        options.with_kind(PackageKind::Patch)
+    } else {
+        options
+    };
+    let mut builder = PackageBuilder::new(options)?;
+
+    // directories are added explicitly so that empty ones survive
+    for components in package.directory_paths() {
+        builder.insert_directory(&components)?;
+    }
+    for (components, index) in package.resource_paths() {
+        builder.insert_packed_resource(components, package, index)?;
+    }
+    for components in package.tombstone_paths() {
+        builder.add_tombstone(components.join(&UID_PATH_SEPARATOR.to_string()))?;
+    }
+
+    Ok(builder)
+}
@@ -1,11 +1,8 @@
 use std::fmt::{Display, Formatter};
-use std::io::{Read, Seek, SeekFrom};
+use std::io::Write;
 use std::sync::Arc;
-use miniz_oxide::inflate::stream::{inflate, InflateState};
-use miniz_oxide::{DataFormat, MZFlush, MZStatus};
-use crate::defines::{PACKAGE_PART_HEADER_LEN, UID_NAMESPACE_SEPARATOR, UID_PATH_SEPARATOR};
-use crate::{CompressionType, Package};
-use crate::util::crc32c::crc32c;
+use crate::defines::{UID_NAMESPACE_SEPARATOR, UID_PATH_SEPARATOR};
+use crate::Package;
 
 pub struct Resource {
     pub descriptor: ResourceDescriptor,
@@ -20,76 +17,37 @@ pub struct ResourceDescriptor {
     pub extension: String,
     pub media_type: String,
     pub size: u64,
+    /// The size of the resource's data as stored in the package.
+    pub packed_size: u64,
     pub(crate) index: u32,
 }
 
 impl ResourceDescriptor {
     pub fn load(&self) -> Result<Vec<u8>, String> {
-        let resource = self.package.catalogue.resources.get(&self.index).unwrap();
-        let data_off = if resource.data_part == 1 {
-            self.package.meta.body_off + resource.data_off
-        } else {
-            PACKAGE_PART_HEADER_LEN + resource.data_off
-        };
-        let data_len_packed = resource.data_len_packed;
-
-        let resource_data = if let Some(mem_buffer) = self.package.mem_buffer {
-            assert!(self.package.part_files.is_none());
-            assert_eq!(resource.data_part, 1);
-
-            Vec::from(
-                &mem_buffer[(data_off as usize)..((data_off + data_len_packed) as usize)]
-            )
-        } else if let Some(part_files) = self.package.part_files.as_ref() {
-            let mut part_files_borrowed = part_files.write().unwrap();
-            let part_file = &mut part_files_borrowed[resource.data_part as usize - 1];
-
-            let mut buf = Vec::with_capacity(data_len_packed as usize);
-            buf.resize(data_len_packed as usize, 0);
-            part_file.seek(SeekFrom::Start(data_off)).unwrap();
-            part_file.read_exact(&mut buf).map_err(|e| e.to_string())?;
-
-            buf
-        } else {
-            panic!("Memory buffer or part file list must be populated");
-        };
+        self.package.load_resource_data(self.index)
+    }
 
-        let actual_crc = crc32c(&resource_data);
-        if actual_crc != resource.crc {
-            return Err("CRC mismatch".to_owned());
-        }
+    /// Writes the resource's data to `writer` as it is read, rather than loading it all into
+    /// memory first. Its CRC is only checked at the end, so `writer` may have received corrupt
+    /// data if this fails.
+    pub fn write_to(&self, writer: &mut dyn Write) -> Result<(), String> {
+        self.package.write_resource_data(self.index, writer, false)
+    }
 
-        let resource_data = match self.package.meta.compression_type.as_ref() {
-            Some(CompressionType::Deflate) => {
-                let mut inflate_state = InflateState::new(DataFormat::Zlib);
-                let mut unpacked_data = Vec::with_capacity(resource.data_len_unpacked as usize);
-                let mut remaining_bytes = resource.data_len_unpacked as usize;
-                let mut output_buf = [0u8; 4096];
-                while remaining_bytes > 0 {
-                    let result = inflate(
-                        &mut inflate_state,
-                        &resource_data,
-                        &mut output_buf,
-                        MZFlush::None,
-                    );
-
-                    let status = result.status.map_err(|e| format!("{:?}", e))?;
-                    remaining_bytes -= result.bytes_written;
-                    if remaining_bytes == 0 && status != MZStatus::StreamEnd {
-                        return Err("Expected end of DEFLATE stream".to_owned());
-                    } else if remaining_bytes > 0 && status == MZStatus::StreamEnd {
-                        return Err("Encountered premature end of DEFLATE stream".to_owned());
-                    }
-
-                    unpacked_data.extend_from_slice(&output_buf[0..result.bytes_written]);
-                }
-
-                unpacked_data
-            }
-            None => resource_data,
-        };
+    /// Like [write_to](Self::write_to), but writes the data as it is stored in the package,
+    /// without decompressing it.
+    pub fn write_packed_to(&self, writer: &mut dyn Write) -> Result<(), String> {
+        self.package.write_resource_data(self.index, writer, true)
+    }
+
+    /// Returns the CRC-32C of the resource's packed data, as recorded in the catalogue.
+    pub fn get_crc(&self) -> u32 {
+        self.package.catalogue.resources[&self.index].crc
+    }
 
-        Ok(resource_data)
+    /// Returns the 1-based index of the package part containing the resource's data.
+    pub fn get_part(&self) -> u16 {
+        self.package.catalogue.resources[&self.index].data_part
     }
 }
 
@@ -146,9 +104,12 @@ impl ResourceIdentifier {
             components: new_cmpts,
         })
     }
+}
 
-    pub fn to_string(&self) -> String {
-        format!(
+impl Display for ResourceIdentifier {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
             "{}{}{}",
             self.namespace,
             UID_NAMESPACE_SEPARATOR,
@@ -156,9 +117,3 @@ impl ResourceIdentifier {
         )
     }
 }
-
-impl Display for ResourceIdentifier {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.to_string())
-    }
-}
@@ -1,6 +1,12 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
 use std::sync::Arc;
 use crate::{Package, ResourceDescriptor, ResourceIdentifier};
 
+/// An ordered collection of packages which are searched as one. Packages earlier in the set
+/// take precedence over later ones, so patches should be placed before the packages they
+/// apply to.
 #[derive(Default)]
 pub struct PackageSet {
     packages: Vec<Arc<Package>>,
@@ -11,14 +17,43 @@ impl PackageSet {
         Self { packages }
     }
 
+    /// Loads every package in a directory. Other parts of multi-part packages, and files
+    /// without the package extension, are ignored.
+    ///
+    /// Patches take precedence over base packages. Within each group, packages are ordered by
+    /// file name, with later names taking precedence, so patches named by version or date
+    /// override earlier ones.
+    pub fn load_from_dir(path: impl AsRef<Path>) -> Result<PackageSet, String> {
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(path).map_err(|e| e.to_string())? {
+            let entry_path = entry.map_err(|e| e.to_string())?.path();
+            if entry_path.is_file()
+                && entry_path.extension().is_some_and(|ext| ext == "arp")
+                && Package::is_base_archive(&entry_path)? {
+                paths.push(entry_path);
+            }
+        }
+        paths.sort();
+
+        let mut packages = paths.iter().rev()
+            .map(Package::load_from_file)
+            .collect::<Result<Vec<_>, String>>()?;
+        // stable, so the order of the file names is kept within each group
+        packages.sort_by_key(|package| !package.is_patch());
+
+        Ok(Self { packages })
+    }
+
     pub fn get_packages(&self) -> &Vec<Arc<Package>> {
         &self.packages
     }
-    
+
     pub fn add_package(&mut self, package: Arc<Package>) {
         self.packages.push(package);
     }
 
+    /// Finds a resource in the highest-priority package containing it. Lookups stop at the
+    /// first package which hides the identifier, e.g. with a tombstone covering it.
     pub fn find_resource(&self, uid: &ResourceIdentifier) -> Result<ResourceDescriptor, String> {
         for package in &self.packages {
             if package.meta.namespace != uid.namespace {
@@ -28,14 +63,43 @@ impl PackageSet {
             if let Ok(desc) = package.find_resource(uid) {
                 return Ok(desc);
             }
+
+            if package.hides(uid) {
+                break;
+            }
         }
 
         Err("Resource not found in package set".to_owned())
     }
+
+    /// Returns descriptors for every resource visible through the set, i.e. those which are
+    /// neither overridden nor hidden by a higher-priority package.
+    pub fn get_all_resource_descriptors(&self) -> Vec<ResourceDescriptor> {
+        let mut seen = HashSet::new();
+        let mut resources = Vec::new();
+
+        for (i, package) in self.packages.iter().enumerate() {
+            let higher_patches = self.packages[..i].iter()
+                .filter(|other| other.is_patch() && other.meta.namespace == package.meta.namespace)
+                .collect::<Vec<_>>();
+
+            for desc in package.get_all_resource_descriptors() {
+                if seen.contains(&desc.identifier)
+                    || higher_patches.iter().any(|patch| patch.hides(&desc.identifier)) {
+                    continue;
+                }
+
+                seen.insert(desc.identifier.clone());
+                resources.push(desc);
+            }
+        }
+
+        resources
+    }
 }
 
-impl Into<Vec<Arc<Package>>> for PackageSet {
-    fn into(self) -> Vec<Arc<Package>> {
-        self.packages
+impl From<PackageSet> for Vec<Arc<Package>> {
+    fn from(set: PackageSet) -> Self {
+        set.packages
     }
 }
@@ -0,0 +1,155 @@
+/// Number of leading bytes examined when sniffing a resource's media type.
+pub const SNIFF_LEN: usize = 1024;
+
+/// How the packer uses a resource's contents to determine its media type.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum SniffMode {
+    /// Media types are determined from file extensions alone.
+    #[default]
+    Disabled,
+    /// Contents are sniffed only if the file extension has no known media type.
+    Fallback,
+    /// Contents are always sniffed, and a recognized format takes precedence over the file
+    /// extension. Plain text and ZIP archives are too generic to override an extension.
+    Override,
+}
+
+/// Identifies a common file format from the leading bytes of some data, as returned by
+/// [sniff_media_type].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SniffedType {
+    pub media_type: &'static str,
+    /// Whether the format is commonly used as the basis of more specific formats, e.g. ZIP
+    /// archives or plain text.
+    pub generic: bool,
+}
+
+/// Attempts to identify the media type of `data` from its leading bytes. Only the first
+/// [SNIFF_LEN] bytes are examined.
+pub fn sniff_media_type(data: &[u8]) -> Option<SniffedType> {
+    let data = &data[..data.len().min(SNIFF_LEN)];
+
+    let specific = |media_type| Some(SniffedType { media_type, generic: false });
+    let generic = |media_type| Some(SniffedType { media_type, generic: true });
+
+    if data.starts_with(b"\x89PNG\r\n\x1A\n") {
+        specific("image/png")
+    } else if data.starts_with(b"\xFF\xD8\xFF") {
+        specific("image/jpeg")
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        specific("image/gif")
+    } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") {
+        specific("image/webp")
+    } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WAVE") {
+        specific("audio/x-wav")
+    } else if data.starts_with(b"OggS") {
+        specific("audio/ogg")
+    } else if data.starts_with(b"glTF") {
+        specific("model/gltf-binary")
+    } else if data.starts_with(b"%PDF-") {
+        specific("application/pdf")
+    } else if data.starts_with(b"\x1F\x8B") {
+        specific("application/gzip")
+    } else if data.starts_with(b"PK\x03\x04") || data.starts_with(b"PK\x05\x06") {
+        generic("application/zip")
+    } else if is_text(data) {
+        if is_gltf_json(data) {
+            specific("model/gltf+json")
+        } else {
+            generic("text/plain")
+        }
+    } else {
+        None
+    }
+}
+
+fn is_text(data: &[u8]) -> bool {
+    if data.is_empty() {
+        return false;
+    }
+
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text,
+        // the sample may end partway through a multi-byte character
+        Err(e) if e.error_len().is_none() && data.len() - e.valid_up_to() < 4 => {
+            std::str::from_utf8(&data[..e.valid_up_to()]).unwrap()
+        }
+        Err(_) => return false,
+    };
+
+    !text.chars().any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0C' | '\x1B'))
+}
+
+fn is_gltf_json(data: &[u8]) -> bool {
+    let data = data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(data);
+    data.trim_ascii_start().starts_with(b"{")
+        && data.windows(b"\"asset\"".len()).any(|w| w == b"\"asset\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sniff_media_type, SNIFF_LEN};
+
+    fn sniff(data: &[u8]) -> Option<(&'static str, bool)> {
+        sniff_media_type(data).map(|sniffed| (sniffed.media_type, sniffed.generic))
+    }
+
+    #[test]
+    fn images() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1A\n\0\0\0\x0DIHDR"), Some(("image/png", false)));
+        assert_eq!(sniff(b"\xFF\xD8\xFF\xE0\0\x10JFIF"), Some(("image/jpeg", false)));
+        assert_eq!(sniff(b"GIF87a\x01\0\x01\0"), Some(("image/gif", false)));
+        assert_eq!(sniff(b"GIF89a\x01\0\x01\0"), Some(("image/gif", false)));
+        assert_eq!(sniff(b"RIFF\x24\0\0\0WEBPVP8 "), Some(("image/webp", false)));
+    }
+
+    #[test]
+    fn audio() {
+        assert_eq!(sniff(b"RIFF\x24\0\0\0WAVEfmt "), Some(("audio/x-wav", false)));
+        assert_eq!(sniff(b"OggS\0\x02\0\0"), Some(("audio/ogg", false)));
+        // other RIFF formats aren't recognized
+        assert_eq!(sniff(b"RIFF\x24\0\0\0AVI LIST"), None);
+        assert_eq!(sniff(b"RIFF\x24\0"), None);
+    }
+
+    #[test]
+    fn binary_formats() {
+        assert_eq!(sniff(b"glTF\x02\0\0\0"), Some(("model/gltf-binary", false)));
+        assert_eq!(sniff(b"%PDF-1.7\n"), Some(("application/pdf", false)));
+        assert_eq!(sniff(b"\x1F\x8B\x08\0"), Some(("application/gzip", false)));
+        assert_eq!(sniff(b"PK\x03\x04\x14\0"), Some(("application/zip", true)));
+        assert_eq!(sniff(b"PK\x05\x06\0\0"), Some(("application/zip", true)));
+        assert_eq!(sniff(b"\0\x01\x02\x03"), None);
+        assert_eq!(sniff(b""), None);
+    }
+
+    #[test]
+    fn text() {
+        assert_eq!(sniff(b"hello\tworld\r\n"), Some(("text/plain", true)));
+        assert_eq!(sniff("caf\u{E9}".as_bytes()), Some(("text/plain", true)));
+        assert_eq!(sniff(b"a\0b"), None);
+        assert_eq!(sniff(b"\xC3\x28"), None);
+
+        // the sample may end partway through a multi-byte character
+        let mut data = vec![b'a'; SNIFF_LEN - 1];
+        data.extend_from_slice("\u{E9}".as_bytes());
+        assert_eq!(sniff(&data), Some(("text/plain", true)));
+    }
+
+    #[test]
+    fn gltf_json() {
+        let gltf = br#"{ "asset": { "version": "2.0" } }"#;
+        assert_eq!(sniff(gltf), Some(("model/gltf+json", false)));
+        let with_bom = [b"\xEF\xBB\xBF\n  ".as_slice(), gltf].concat();
+        assert_eq!(sniff(&with_bom), Some(("model/gltf+json", false)));
+        assert_eq!(sniff(br#"{ "name": "stone" }"#), Some(("text/plain", true)));
+        assert_eq!(sniff(br#"["asset"]"#), Some(("text/plain", true)));
+    }
+
+    #[test]
+    fn only_leading_bytes_are_examined() {
+        let mut data = vec![b'a'; SNIFF_LEN];
+        data.push(0);
+        assert_eq!(sniff(&data), Some(("text/plain", true)));
+    }
+}
@@ -1,24 +1,53 @@
-const MAGIC_DEFLATE: &str = "df";
+use crate::codec::CodecMagic;
+use crate::defines::{COMPRESS_MAGIC_DEFLATE, PACKAGE_KIND_BASE, PACKAGE_KIND_PATCH};
 
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub enum CompressionType {
     Deflate,
+    /// A codec provided through a [CodecRegistry](crate::CodecRegistry), identified by its magic.
+    Custom(CodecMagic),
 }
 
 impl CompressionType {
-    pub(crate) fn from_magic(magic: &[u8; 2]) -> Option<CompressionType> {
-        let magic_str = String::from_utf8_lossy(magic);
-        match magic_str.as_ref() {
-            MAGIC_DEFLATE => Some(CompressionType::Deflate),
-            _ => None,
+    pub fn from_magic(magic: &CodecMagic) -> CompressionType {
+        match *magic {
+            COMPRESS_MAGIC_DEFLATE => CompressionType::Deflate,
+            _ => CompressionType::Custom(*magic),
         }
     }
-    
-    pub(crate) fn get_magic(&self) -> &[u8; 2] {
-        let s = match self {
-            CompressionType::Deflate => MAGIC_DEFLATE,
-        };
-        assert_eq!(s.len(), 2);
-        assert!(s.is_ascii());
-        s.as_bytes().try_into().unwrap()
+
+    pub fn get_magic(&self) -> CodecMagic {
+        match self {
+            CompressionType::Deflate => COMPRESS_MAGIC_DEFLATE,
+            CompressionType::Custom(magic) => *magic,
+        }
+    }
+}
+
+/// What a package represents. Only v2+ packages may be patches.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum PackageKind {
+    /// A self-contained set of resources.
+    #[default]
+    Base,
+    /// An update to be layered over other packages in a [PackageSet](crate::PackageSet). Its
+    /// catalogue may contain tombstones marking resources which the patch deletes.
+    Patch,
+}
+
+impl PackageKind {
+    pub(crate) fn from_ordinal(ordinal: u8) -> Result<PackageKind, String> {
+        match ordinal {
+            PACKAGE_KIND_BASE => Ok(PackageKind::Base),
+            PACKAGE_KIND_PATCH => Ok(PackageKind::Patch),
+            _ => Err("Unrecognized package kind".to_owned()),
+        }
+    }
+
+    pub(crate) fn get_ordinal(&self) -> u8 {
+        match self {
+            PackageKind::Base => PACKAGE_KIND_BASE,
+            PackageKind::Patch => PACKAGE_KIND_PATCH,
+        }
     }
 }
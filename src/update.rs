@@ -0,0 +1,240 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use crate::defines::*;
+use crate::pack::{
+    part_file_name, patch_node_desc, push_node_desc, write_node_data, write_u32_le, write_u64_le,
+    NodeSource, PackNode, WrittenNodeData,
+};
+use crate::{repack, Package, PackingOptions};
+
+/// A directory in the package being updated, keyed by node index.
+struct UpdatedDir {
+    name: String,
+    children: BTreeMap<String, u32>,
+}
+
+/// Adds the nodes built by a [PackageBuilder](crate::PackageBuilder) to the package at `path`,
+/// replacing any existing nodes with the same paths.
+///
+/// New data is appended to the last part, and the changed directories and a new catalogue are
+/// appended to the first. Nothing the current header refers to is modified, so the package
+/// remains valid until the header is rewritten to point at the new catalogue.
+pub(crate) fn update_package(
+    nodes: &[PackNode],
+    options: &PackingOptions,
+    path: &Path,
+) -> Result<(), String> {
+    let package = Package::load_from_file(path)?;
+    let meta = &package.meta;
+    if options.version != meta.major_version
+        || options.compression_type != meta.compression_type
+        || options.kind != meta.kind {
+        return Err(
+            "Update must use the package's format version, compression type and kind".to_owned()
+        );
+    }
+    if options.namespace != meta.namespace {
+        return Err("Update namespace must match that of the package".to_owned());
+    }
+
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let base_name = package.get_base_file_name().unwrap();
+    let total_parts = meta.total_parts as usize;
+    let open_part = |index| {
+        File::options()
+            .read(true)
+            .write(true)
+            .open(dir.join(part_file_name(base_name, index, total_parts)))
+            .map_err(|e| e.to_string())
+    };
+    let mut part_1 = open_part(1)?;
+    // None if the first part is also the last
+    let mut last_part = if total_parts > 1 { Some(open_part(total_parts)?) } else { None };
+
+    // existing descriptors are carried over as-is unless their node is replaced
+    let mut catalogue_buf = vec![0u8; meta.cat_len as usize];
+    part_1.seek(SeekFrom::Start(meta.cat_off)).map_err(|e| e.to_string())?;
+    part_1.read_exact(&mut catalogue_buf).map_err(|e| e.to_string())?;
+    let mut descs = split_descriptors(&catalogue_buf, meta.node_count)?;
+
+    let mut dirs: HashMap<u32, UpdatedDir> = package.catalogue.dirs.iter()
+        .map(|(index, dir)| (*index, UpdatedDir {
+            name: dir.name.clone(),
+            children: dir.children.iter().map(|(name, index)| (name.clone(), *index)).collect(),
+        }))
+        .collect();
+    let mut changed_dirs = BTreeSet::new();
+
+    // data is appended to the last part, and offsets within the first part are relative to
+    // the start of the body rather than the start of the file
+    let data_part_base = if total_parts > 1 { PACKAGE_PART_HEADER_LEN } else { meta.body_off };
+
+    // pairs of builder node indices and the indices of the corresponding package directories
+    let mut dir_queue = vec![(0usize, 0u32, String::new())];
+    while let Some((node_index, dir_index, dir_path)) = dir_queue.pop() {
+        let NodeSource::Directory(child_indices) = &nodes[node_index].source else {
+            unreachable!("Queued node is not a directory");
+        };
+
+        for &child_index in child_indices {
+            let child = &nodes[child_index as usize];
+            let child_path = if dir_path.is_empty() {
+                child.name.clone()
+            } else {
+                format!("{}/{}", dir_path, child.name)
+            };
+            let existing = dirs[&dir_index].children.get(&child.name).copied();
+
+            if let NodeSource::Directory(_) = child.source {
+                let target_index = match existing {
+                    Some(index) if dirs.contains_key(&index) => index,
+                    Some(_) => {
+                        return Err(format!("Resource path '{}' is already in use", child_path));
+                    }
+                    None => {
+                        // filled in along with the other changed directories
+                        let index = descs.len() as u32;
+                        descs.push(Vec::new());
+                        dirs.insert(index, UpdatedDir {
+                            name: child.name.clone(),
+                            children: BTreeMap::new(),
+                        });
+                        dirs.get_mut(&dir_index).unwrap().children.insert(child.name.clone(), index);
+                        changed_dirs.insert(dir_index);
+                        changed_dirs.insert(index);
+                        index
+                    }
+                };
+                dir_queue.push((child_index as usize, target_index, child_path));
+                continue;
+            }
+
+            if existing.is_some_and(|index| dirs.contains_key(&index)) {
+                return Err(format!("Resource path '{}' is already in use", child_path));
+            }
+
+            let mut desc = Vec::new();
+            push_node_desc(&mut desc, child, options.version);
+            if let NodeSource::Tombstone = child.source {
+                patch_node_desc(&mut desc, 1, 0, &WrittenNodeData::default());
+            } else {
+                let sink = last_part.as_mut().unwrap_or(&mut part_1);
+                let data_start = sink.seek(SeekFrom::End(0)).map_err(|e| e.to_string())?;
+                let written = write_node_data(child, sink, options)?;
+                patch_node_desc(
+                    &mut desc,
+                    total_parts as u16,
+                    data_start - data_part_base,
+                    &written,
+                );
+            }
+
+            match existing {
+                Some(index) => descs[index as usize] = desc,
+                None => {
+                    let index = descs.len() as u32;
+                    descs.push(desc);
+                    dirs.get_mut(&dir_index).unwrap().children.insert(child.name.clone(), index);
+                    changed_dirs.insert(dir_index);
+                }
+            }
+        }
+    }
+
+    if let Some(last_part) = last_part.as_mut() {
+        last_part.sync_all().map_err(|e| e.to_string())?;
+    }
+
+    // directory contents are always read from the first part
+    for dir_index in changed_dirs {
+        let dir = &dirs[&dir_index];
+        let dir_node = PackNode {
+            name: dir.name.clone(),
+            ext: String::new(),
+            media_type: String::new(),
+            source: NodeSource::Directory(dir.children.values().copied().collect()),
+        };
+
+        let data_start = part_1.seek(SeekFrom::End(0)).map_err(|e| e.to_string())?;
+        let written = write_node_data(&dir_node, &mut part_1, options)?;
+        let mut desc = Vec::new();
+        push_node_desc(&mut desc, &dir_node, options.version);
+        patch_node_desc(&mut desc, 1, data_start - meta.body_off, &written);
+        descs[dir_index as usize] = desc;
+    }
+
+    let catalogue = descs.concat();
+    let cat_off = part_1.seek(SeekFrom::End(0)).map_err(|e| e.to_string())?;
+    part_1.write_all(&catalogue).map_err(|e| e.to_string())?;
+    // the new catalogue must be on disk before the header refers to it
+    part_1.sync_all().map_err(|e| e.to_string())?;
+
+    let count_type = |ty| descs.iter().filter(|desc| desc[ND_TYPE_OFF] == ty).count() as u32;
+
+    let mut header_buf = [0u8; PACKAGE_HEADER_LEN as usize];
+    part_1.rewind().map_err(|e| e.to_string())?;
+    part_1.read_exact(&mut header_buf).map_err(|e| e.to_string())?;
+    write_u64_le(&mut header_buf, PACK_HEADER_CAT_OFF_OFF, cat_off);
+    write_u64_le(&mut header_buf, PACK_HEADER_CAT_LEN_OFF, catalogue.len() as u64);
+    write_u32_le(&mut header_buf, PACK_HEADER_NODE_CNT_OFF, descs.len() as u32);
+    write_u32_le(&mut header_buf, PACK_HEADER_DIR_CNT_OFF, count_type(PACK_NODE_TYPE_DIRECTORY));
+    write_u32_le(&mut header_buf, PACK_HEADER_RES_CNT_OFF, count_type(PACK_NODE_TYPE_RESOURCE));
+    // the body now spans everything up to the new catalogue, including any dead space
+    write_u64_le(&mut header_buf, PACK_HEADER_BODY_LEN_OFF, cat_off - meta.body_off);
+
+    part_1.rewind().map_err(|e| e.to_string())?;
+    part_1.write_all(&header_buf).map_err(|e| e.to_string())?;
+    part_1.sync_all().map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+/// Rewrites the package at `path` without the dead space left behind by in-place updates.
+///
+/// Packed data is copied as-is. The new package is staged alongside the old one and renamed
+/// into place, so the old package remains intact if compaction fails.
+///
+/// Packages don't record the part length they were written with, so `max_part_len` must be
+/// given for a multi-part package. Since the number of parts may change, e.g. if the package
+/// now fits in a single part, the path of the compacted package's first part is returned.
+pub fn compact(path: impl AsRef<Path>, max_part_len: Option<u64>) -> Result<PathBuf, String> {
+    let path = path.as_ref();
+    let package = Package::load_from_file(path)?;
+    let meta = &package.meta;
+
+    if meta.total_parts > 1 && max_part_len.is_none() {
+        return Err("A maximum part length is required to compact a multi-part package".to_owned());
+    }
+
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let base_name = package.get_base_file_name().unwrap().to_owned();
+
+    let options = PackingOptions::new_versioned(
+        meta.major_version,
+        base_name.clone(),
+        meta.namespace.clone(),
+        max_part_len,
+        meta.compression_type,
+        None::<&Path>,
+    )?;
+    let total_parts = repack(&package, options)?.write_parts_to_dir(dir)?;
+    Ok(dir.join(part_file_name(&base_name, 1, total_parts as usize)))
+}
+
+fn split_descriptors(catalogue: &[u8], node_count: u32) -> Result<Vec<Vec<u8>>, String> {
+    let mut descs = Vec::with_capacity(node_count as usize);
+    let mut off = 0;
+    for _ in 0..node_count {
+        let len = catalogue.get(off..(off + ND_LEN_LEN))
+            .map(|buf| u16::from_le_bytes(buf.try_into().unwrap()) as usize)
+            .ok_or_else(|| "Catalogue is truncated".to_owned())?;
+        let desc = catalogue.get(off..(off + len))
+            .ok_or_else(|| "Catalogue is truncated".to_owned())?;
+        descs.push(desc.to_vec());
+        off += len;
+    }
+
+    Ok(descs)
+}
@@ -33,13 +33,12 @@ pub fn crc32c_continue(initial: u32, buf: &[u8]) -> u32 {
 
 fn crc32c_sw(initial: u32, buf: &[u8]) -> u32 {
     let mut crc: u32 = initial;
-    for i in 0..buf.len() {
-        let lookup_index = ((crc & BIT_MASK_8 as u32) as u8 ^ buf[i]) as usize;
+    for byte in buf {
+        let lookup_index = ((crc & BIT_MASK_8 as u32) as u8 ^ byte) as usize;
         crc = (crc >> BITS_PER_BYTE) ^ LOOKUP_TABLE[lookup_index];
     }
 
-    let crc_final = !crc;
-    crc_final
+    !crc
 }
 
 #[cfg(target_arch = "x86_64")]
@@ -51,7 +50,7 @@ fn crc32c_x86_64_sse42(initial: u32, buf: &[u8]) -> u32 {
         unsafe {
             crc = arch::x86_64::_mm_crc32_u64(
                 crc as u64,
-                *(buf[(i * 8)..((i + 1) * 8)].as_ptr() as *const u64)
+                u64::from_le_bytes(buf[(i * 8)..((i + 1) * 8)].try_into().unwrap())
             ) as u32;
         }
     }
@@ -61,8 +60,7 @@ fn crc32c_x86_64_sse42(initial: u32, buf: &[u8]) -> u32 {
         }
     }
 
-    let crc_final = !crc;
-    crc_final
+    !crc
 }
 
 const fn compute_lookup_table() -> [u32; LOOKUP_TABLE_SIZE] {
@@ -0,0 +1,256 @@
+/// A gitignore-style path pattern, matched against `/`-separated paths relative to some base
+/// directory.
+///
+/// `*` and `?` match within a single path component, `[...]` matches a character class and `**`
+/// matches across components. A pattern containing a `/` other than a trailing one is anchored to
+/// the base directory; otherwise it may match at any depth. A trailing `/` restricts the pattern
+/// to directories.
+#[derive(Clone, Debug)]
+pub(crate) struct PathPattern {
+    tokens: Vec<Token>,
+    dir_only: bool,
+}
+
+#[derive(Clone, Debug)]
+enum Token {
+    Literal(char),
+    // ?
+    AnyChar,
+    // *
+    AnyComponentChars,
+    // ** at the end of a pattern
+    AnyChars,
+    // **/, matching zero or more whole components
+    AnyComponents,
+    Class { negated: bool, ranges: Vec<(char, char)> },
+}
+
+impl PathPattern {
+    pub(crate) fn new(pattern: &str) -> Self {
+        let (pattern, dir_only) = match pattern.strip_suffix('/') {
+            Some(stripped) => (stripped, true),
+            None => (pattern, false),
+        };
+
+        let anchored = pattern.contains('/');
+        let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+
+        let mut tokens = Vec::new();
+        if !anchored {
+            tokens.push(Token::AnyComponents);
+        }
+        parse_tokens(pattern, &mut tokens);
+
+        Self { tokens, dir_only }
+    }
+
+    pub(crate) fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+
+        let chars: Vec<char> = path.chars().collect();
+        match_tokens(&self.tokens, &chars)
+    }
+}
+
+fn parse_tokens(pattern: &str, tokens: &mut Vec<Token>) {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                let at_component_start = i == 0 || chars[i - 1] == '/';
+                if at_component_start && chars.get(i + 2) == Some(&'/') {
+                    tokens.push(Token::AnyComponents);
+                    i += 3;
+                } else if at_component_start && i + 2 == chars.len() {
+                    tokens.push(Token::AnyChars);
+                    i += 2;
+                } else {
+                    // ** within a component behaves like *
+                    tokens.push(Token::AnyComponentChars);
+                    i += 2;
+                }
+                continue;
+            }
+            '*' => tokens.push(Token::AnyComponentChars),
+            '?' => tokens.push(Token::AnyChar),
+            '[' => {
+                if let Some((class, len)) = parse_class(&chars[i..]) {
+                    tokens.push(class);
+                    i += len;
+                    continue;
+                }
+                // an unterminated class is matched literally
+                tokens.push(Token::Literal('['));
+            }
+            '\\' if i + 1 < chars.len() => {
+                tokens.push(Token::Literal(chars[i + 1]));
+                i += 2;
+                continue;
+            }
+            c => tokens.push(Token::Literal(c)),
+        }
+        i += 1;
+    }
+}
+
+/// Parses a character class at the start of `chars`, returning it along with the number of
+/// characters it spans.
+fn parse_class(chars: &[char]) -> Option<(Token, usize)> {
+    let mut i = 1;
+    let negated = matches!(chars.get(i), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut ranges = Vec::new();
+    let mut first = true;
+    loop {
+        let c = *chars.get(i)?;
+        if c == ']' && !first {
+            return Some((Token::Class { negated, ranges }, i + 1));
+        }
+        first = false;
+
+        if chars.get(i + 1) == Some(&'-') && chars.get(i + 2).is_some_and(|&end| end != ']') {
+            ranges.push((c, chars[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((c, c));
+            i += 1;
+        }
+    }
+}
+
+fn match_tokens(tokens: &[Token], chars: &[char]) -> bool {
+    // matched[j] is true if the tokens processed so far can consume exactly chars[..j]
+    let mut matched = vec![false; chars.len() + 1];
+    matched[0] = true;
+
+    for token in tokens {
+        let mut next = vec![false; chars.len() + 1];
+        for start in 0..=chars.len() {
+            if !matched[start] {
+                continue;
+            }
+
+            match token {
+                Token::Literal(c) => {
+                    if chars.get(start) == Some(c) {
+                        next[start + 1] = true;
+                    }
+                }
+                Token::AnyChar => {
+                    if chars.get(start).is_some_and(|&c| c != '/') {
+                        next[start + 1] = true;
+                    }
+                }
+                Token::AnyComponentChars => {
+                    next[start] = true;
+                    for (end, &c) in chars.iter().enumerate().skip(start) {
+                        if c == '/' {
+                            break;
+                        }
+                        next[end + 1] = true;
+                    }
+                }
+                Token::AnyChars => {
+                    next[start..].fill(true);
+                }
+                Token::AnyComponents => {
+                    next[start] = true;
+                    for (end, &c) in chars.iter().enumerate().skip(start) {
+                        if c == '/' {
+                            next[end + 1] = true;
+                        }
+                    }
+                }
+                Token::Class { negated, ranges } => {
+                    if let Some(&c) = chars.get(start) {
+                        let in_class = ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi);
+                        if c != '/' && in_class != *negated {
+                            next[start + 1] = true;
+                        }
+                    }
+                }
+            }
+        }
+
+        if !next.contains(&true) {
+            return false;
+        }
+        matched = next;
+    }
+
+    matched[chars.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PathPattern;
+
+    fn matches(pattern: &str, path: &str) -> bool {
+        PathPattern::new(pattern).matches(path, false)
+    }
+
+    #[test]
+    fn unanchored_patterns_match_at_any_depth() {
+        assert!(matches("*.txt", "a.txt"));
+        assert!(matches("*.txt", "dir/sub/a.txt"));
+        assert!(matches("a.txt", "dir/a.txt"));
+        assert!(!matches("a.txt", "dir/ba.txt"));
+    }
+
+    #[test]
+    fn patterns_containing_slash_are_anchored() {
+        assert!(matches("dir/*.txt", "dir/a.txt"));
+        assert!(!matches("dir/*.txt", "other/dir/a.txt"));
+        assert!(matches("/a.txt", "a.txt"));
+        assert!(!matches("/a.txt", "dir/a.txt"));
+    }
+
+    #[test]
+    fn single_star_stays_within_component() {
+        assert!(matches("dir/*", "dir/a"));
+        assert!(!matches("dir/*", "dir/a/b"));
+        assert!(matches("a?c", "abc"));
+        assert!(!matches("a?c", "a/c"));
+    }
+
+    #[test]
+    fn double_star_matches_across_components() {
+        assert!(matches("dir/**", "dir/a"));
+        assert!(matches("dir/**", "dir/a/b"));
+        assert!(matches("**/a.txt", "a.txt"));
+        assert!(matches("**/a.txt", "x/y/a.txt"));
+        assert!(matches("x/**/a.txt", "x/a.txt"));
+        assert!(matches("x/**/a.txt", "x/y/z/a.txt"));
+        assert!(!matches("x/**/a.txt", "y/a.txt"));
+        // ** within a component behaves like *
+        assert!(matches("/a**b", "axxb"));
+        assert!(!matches("/a**b", "a/b"));
+    }
+
+    #[test]
+    fn trailing_slash_only_matches_directories() {
+        let pattern = PathPattern::new("build/");
+        assert!(pattern.matches("build", true));
+        assert!(pattern.matches("src/build", true));
+        assert!(!pattern.matches("build", false));
+    }
+
+    #[test]
+    fn character_classes() {
+        assert!(matches("[ab].txt", "a.txt"));
+        assert!(!matches("[ab].txt", "c.txt"));
+        assert!(matches("[a-c].txt", "b.txt"));
+        assert!(matches("[!a-c].txt", "d.txt"));
+        assert!(!matches("[!a-c].txt", "a.txt"));
+        // an unterminated class is matched literally
+        assert!(matches("[a", "[a"));
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "a"));
+    }
+}
@@ -0,0 +1,72 @@
+use crate::util::glob::PathPattern;
+
+/// Name of the file listing paths to leave out when packing a directory.
+pub(crate) const IGNORE_FILE_NAME: &str = ".arpignore";
+
+/// Rules parsed from a gitignore-style ignore file, matched against paths relative to the
+/// directory containing it.
+pub(crate) struct IgnoreRules {
+    rules: Vec<(PathPattern, bool)>,
+}
+
+impl IgnoreRules {
+    pub(crate) fn parse(contents: &str) -> Self {
+        let rules = contents.lines()
+            .map(|line| line.trim_end())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| match line.strip_prefix('!') {
+                Some(pattern) => (PathPattern::new(pattern), false),
+                None => (PathPattern::new(line.strip_prefix('\\').unwrap_or(line)), true),
+            })
+            .collect();
+        Self { rules }
+    }
+
+    /// Returns whether the path is ignored according to the last rule matching it, or `None`
+    /// if no rule matches.
+    pub(crate) fn check(&self, path: &str, is_dir: bool) -> Option<bool> {
+        self.rules.iter().rev()
+            .find(|(pattern, _)| pattern.matches(path, is_dir))
+            .map(|(_, ignored)| *ignored)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IgnoreRules;
+
+    #[test]
+    fn comments_and_blank_lines_are_skipped() {
+        let rules = IgnoreRules::parse("# a comment\n\n*.log\n");
+        assert_eq!(rules.check("a.log", false), Some(true));
+        assert_eq!(rules.check("# a comment", false), None);
+        assert_eq!(rules.check("a.txt", false), None);
+    }
+
+    #[test]
+    fn last_matching_rule_wins() {
+        let rules = IgnoreRules::parse("*.log\n!keep.log\n");
+        assert_eq!(rules.check("a.log", false), Some(true));
+        assert_eq!(rules.check("keep.log", false), Some(false));
+        assert_eq!(rules.check("dir/keep.log", false), Some(false));
+
+        let rules = IgnoreRules::parse("!keep.log\n*.log\n");
+        assert_eq!(rules.check("keep.log", false), Some(true));
+    }
+
+    #[test]
+    fn escaped_prefixes_are_literal() {
+        let rules = IgnoreRules::parse("\\!important\n\\#notes\n");
+        assert_eq!(rules.check("!important", false), Some(true));
+        assert_eq!(rules.check("#notes", false), Some(true));
+    }
+
+    #[test]
+    fn anchoring_and_directories() {
+        let rules = IgnoreRules::parse("/out\ncache/\n");
+        assert_eq!(rules.check("out", false), Some(true));
+        assert_eq!(rules.check("dir/out", false), None);
+        assert_eq!(rules.check("dir/cache", true), Some(true));
+        assert_eq!(rules.check("cache", false), None);
+    }
+}
@@ -0,0 +1,67 @@
+use std::io;
+use std::io::{Read, Write};
+use crate::util::crc32c::crc32c_continue;
+
+/// Passes writes through to an inner writer while tracking their length and CRC.
+pub(crate) struct CrcWriter<W: Write> {
+    inner: W,
+    len: u64,
+    crc: u32,
+}
+
+impl<W: Write> CrcWriter<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        Self { inner, len: 0, crc: 0 }
+    }
+
+    pub(crate) fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub(crate) fn crc(&self) -> u32 {
+        self.crc
+    }
+}
+
+impl<W: Write> Write for CrcWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.crc = crc32c_continue(self.crc, &buf[..written]);
+        self.len += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Passes reads through to an inner reader while tracking their length and CRC.
+pub(crate) struct CountingReader<R: Read> {
+    inner: R,
+    len: u64,
+    crc: u32,
+}
+
+impl<R: Read> CountingReader<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        Self { inner, len: 0, crc: 0 }
+    }
+
+    pub(crate) fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub(crate) fn crc(&self) -> u32 {
+        self.crc
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.crc = crc32c_continue(self.crc, &buf[..read]);
+        self.len += read as u64;
+        Ok(read)
+    }
+}
@@ -1,2 +1,6 @@
 pub mod crc32c;
+pub mod glob;
+pub mod ignore;
+pub mod io;
+pub mod parallel;
 pub mod uid;
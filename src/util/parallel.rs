@@ -0,0 +1,106 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Condvar, Mutex};
+use std::thread;
+
+// how many items each worker may process ahead of the consumer
+const ITEMS_AHEAD_PER_THREAD: usize = 2;
+
+/// Maps each item on a pool of worker threads and passes the results to the
+/// consumer on the calling thread in the original item order.
+///
+/// At most a small, fixed number of results per thread are held at once
+/// regardless of how far the workers get ahead of the consumer.
+pub(crate) fn ordered_parallel_map<'a, T, R, F, C>(
+    items: &'a [T],
+    threads: usize,
+    map: F,
+    mut consume: C,
+) -> Result<(), String>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&'a T) -> Result<R, String> + Sync,
+    C: FnMut(&'a T, R) -> Result<(), String>,
+{
+    if threads <= 1 || items.len() <= 1 {
+        for item in items {
+            let result = map(item)?;
+            consume(item, result)?;
+        }
+        return Ok(());
+    }
+
+    let window = threads * ITEMS_AHEAD_PER_THREAD;
+
+    let next_index = AtomicUsize::new(0);
+    let aborted = AtomicBool::new(false);
+    let consumed_count = Mutex::new(0usize);
+    let consumed_cvar = Condvar::new();
+
+    let (tx, rx) = mpsc::channel::<(usize, Result<R, String>)>();
+
+    thread::scope(|scope| {
+        for _ in 0..threads.min(items.len()) {
+            let tx = tx.clone();
+            let (map, next_index, aborted, consumed_count, consumed_cvar) =
+                (&map, &next_index, &aborted, &consumed_count, &consumed_cvar);
+            scope.spawn(move || {
+                loop {
+                    let index = next_index.fetch_add(1, Ordering::SeqCst);
+                    if index >= items.len() {
+                        break;
+                    }
+
+                    // wait until the consumer has caught up enough to make room for this item
+                    let mut consumed = consumed_count.lock().unwrap();
+                    while index >= *consumed + window && !aborted.load(Ordering::SeqCst) {
+                        consumed = consumed_cvar.wait(consumed).unwrap();
+                    }
+                    drop(consumed);
+
+                    if aborted.load(Ordering::SeqCst) {
+                        break;
+                    }
+
+                    let result = map(&items[index]);
+                    if tx.send((index, result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+
+        let abort = |err: String| {
+            aborted.store(true, Ordering::SeqCst);
+            // take the lock so no worker can miss the notification
+            let _guard = consumed_count.lock().unwrap();
+            consumed_cvar.notify_all();
+            Err(err)
+        };
+
+        let mut pending: BTreeMap<usize, Result<R, String>> = BTreeMap::new();
+        let mut next_out = 0;
+        for (index, result) in rx {
+            pending.insert(index, result);
+
+            while let Some(result) = pending.remove(&next_out) {
+                let res = result.and_then(|r| consume(&items[next_out], r));
+                if let Err(e) = res {
+                    return abort(e);
+                }
+
+                next_out += 1;
+                *consumed_count.lock().unwrap() = next_out;
+                consumed_cvar.notify_all();
+            }
+        }
+
+        if next_out != items.len() {
+            return abort("Worker thread terminated unexpectedly".to_owned());
+        }
+
+        Ok(())
+    })
+}
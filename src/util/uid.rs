@@ -1,6 +1,11 @@
 pub(crate) fn validate_path_component(component: impl AsRef<str>) -> Result<(), String> {
     let component_str = component.as_ref();
 
+    // these would refer to other directories if the component were used as a file name
+    if component_str == "." || component_str == ".." {
+        return Err(format!("Path component cannot be '{}'", component_str));
+    }
+
     for c in component_str.chars() {
         if c.is_control() {
             return Err("Path component cannot contain control characters".to_owned());
@@ -13,3 +18,19 @@ pub(crate) fn validate_path_component(component: impl AsRef<str>) -> Result<(),
 
     Ok(())
 }
+
+/// Validates a node name and extension read from a catalogue, which may be used to build paths
+/// when the package is extracted.
+pub(crate) fn validate_node_name(name: &str, ext: &str, is_root: bool) -> Result<(), String> {
+    if name.is_empty() && !is_root {
+        return Err("Node name cannot be empty".to_owned());
+    }
+    if !name.is_empty() {
+        validate_path_component(name).map_err(|e| format!("Invalid node name: {}", e))?;
+    }
+    if !ext.is_empty() {
+        validate_path_component(ext).map_err(|e| format!("Invalid node extension: {}", e))?;
+    }
+
+    Ok(())
+}
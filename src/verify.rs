@@ -0,0 +1,111 @@
+use std::collections::HashSet;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::Arc;
+use crate::defines::*;
+use crate::util::io::CrcWriter;
+use crate::{Package, ResourceIdentifier};
+
+/// The result of checking a single resource.
+pub struct ResourceCheck {
+    pub identifier: ResourceIdentifier,
+    pub size: u64,
+    /// Why the resource is invalid, or `None` if it is intact.
+    pub error: Option<String>,
+}
+
+/// The result of [verify].
+pub struct VerifyReport {
+    /// Problems with the structure of the package as a whole, such as its part headers or
+    /// catalogue.
+    pub errors: Vec<String>,
+    pub resources: Vec<ResourceCheck>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty() && self.resources.iter().all(|res| res.error.is_none())
+    }
+}
+
+/// Checks a loaded package for corruption.
+///
+/// Loading a package already validates its header and the structure of its directory tree.
+/// This additionally checks the headers of any further parts, that the catalogue agrees with
+/// the header and contains no unreachable nodes, and the CRC and unpacked length of every
+/// resource.
+pub fn verify(package: &Arc<Package>) -> VerifyReport {
+    let mut errors = Vec::new();
+    let meta = &package.meta;
+    let catalogue = &package.catalogue;
+
+    if let Some(part_files) = package.part_files.as_ref() {
+        let mut part_files = part_files.write().unwrap();
+        // the first part begins with the package header rather than a part header
+        for (i, part_file) in part_files.iter_mut().enumerate().skip(1) {
+            if let Err(err) = check_part_header(part_file, i + 1) {
+                errors.push(format!("Part {}: {}", i + 1, err));
+            }
+        }
+    }
+
+    if catalogue.dirs.len() != meta.directory_count as usize {
+        errors.push(format!(
+            "Header declares {} directories, but catalogue contains {}",
+            meta.directory_count,
+            catalogue.dirs.len(),
+        ));
+    }
+    if catalogue.resources.len() != meta.resource_count as usize {
+        errors.push(format!(
+            "Header declares {} resources, but catalogue contains {}",
+            meta.resource_count,
+            catalogue.resources.len(),
+        ));
+    }
+
+    let reachable: HashSet<u32> = package.node_paths().into_iter()
+        .map(|(_, index)| index)
+        .collect();
+    for index in (1..meta.node_count).filter(|index| !reachable.contains(index)) {
+        errors.push(format!("Node {} is not reachable from the root directory", index));
+    }
+
+    let mut resources: Vec<_> = package.get_all_resource_descriptors().into_iter()
+        .map(|desc| {
+            let resource = &catalogue.resources[&desc.index];
+            // the data is only counted, so resources of any size can be checked
+            let mut counter = CrcWriter::new(io::sink());
+            let error = match package.write_resource_data(desc.index, &mut counter, false) {
+                Ok(()) if counter.len() != resource.data_len_unpacked => Some(format!(
+                    "Unpacked length is {} bytes, expected {}",
+                    counter.len(),
+                    resource.data_len_unpacked,
+                )),
+                Ok(()) => None,
+                Err(err) => Some(err),
+            };
+            ResourceCheck { identifier: desc.identifier, size: desc.size, error }
+        })
+        .collect();
+    resources.sort_by(|a, b| a.identifier.cmp(&b.identifier));
+
+    VerifyReport { errors, resources }
+}
+
+fn check_part_header<R: Read + Seek>(reader: &mut R, part_index: usize) -> Result<(), String> {
+    let mut header_buf = [0u8; PACKAGE_PART_HEADER_LEN as usize];
+    reader.seek(SeekFrom::Start(0)).map_err(|e| e.to_string())?;
+    reader.read_exact(&mut header_buf).map_err(|e| e.to_string())?;
+
+    if header_buf[PART_MAGIC_OFF..(PART_MAGIC_OFF + PART_MAGIC.len())] != PART_MAGIC {
+        return Err("Part magic is incorrect".to_owned());
+    }
+    let index_buf = &header_buf[PART_INDEX_OFF..(PART_INDEX_OFF + PART_INDEX_LEN)];
+    let index = u16::from_le_bytes(index_buf.try_into().unwrap());
+    if index as usize != part_index {
+        return Err(format!("Part header declares index {}", index));
+    }
+
+    Ok(())
+}
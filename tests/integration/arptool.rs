@@ -0,0 +1,37 @@
+use std::path::Path;
+use std::process::Command;
+
+use crate::common::{builder, options, TestDir};
+
+/// Runs arptool with the given arguments, returning its stdout if it succeeds.
+fn arptool(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_arptool")).args(args).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn path_arg(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+#[test]
+fn diff_json_output() {
+    let dir = TestDir::new();
+    builder(options("old"), &[("a.txt", "a"), ("b.txt", "b"), ("c.txt", "c")])
+        .write_to_dir(dir.path()).unwrap();
+    builder(options("new"), &[("a.txt", "a"), ("b.json", "bb"), ("d.txt", "d")])
+        .write_to_dir(dir.path()).unwrap();
+
+    let old_path = dir.path().join("old.arp");
+    let new_path = dir.path().join("new.arp");
+    let json = arptool(&["diff", "--json", path_arg(&old_path), path_arg(&new_path)]);
+    assert_eq!(json.trim_end(), concat!(
+        r#"{"header":[],"added":["d"],"removed":["c"],"modified":[{"path":"b","#,
+        r#""content_changed":true,"old_size":1,"new_size":2,"old_extension":"txt","#,
+        r#""new_extension":"json","old_media_type":"text/plain","#,
+        r#""new_media_type":"application/json"}]}"#,
+    ));
+
+    let json = arptool(&["diff", "--json", path_arg(&old_path), path_arg(&old_path)]);
+    assert_eq!(json.trim_end(), r#"{"header":[],"added":[],"removed":[],"modified":[]}"#);
+}
@@ -0,0 +1,164 @@
+use std::fs;
+use std::io::Cursor;
+
+use arp::{Package, PackageBuilder, SniffMode, DEFAULT_MEDIA_TYPE};
+
+use crate::common::{deflate_options, incompressible, load, options, uid, TestDir};
+
+#[test]
+fn resources_from_bytes_readers_and_files_round_trip() {
+    let dir = TestDir::new();
+    let file_path = dir.path().join("source.txt");
+    fs::write(&file_path, "from a file").unwrap();
+
+    let mut builder = PackageBuilder::new(options("builder")).unwrap();
+    builder.add_bytes("bytes.txt", b"from bytes".to_vec(), None).unwrap();
+    builder.add_reader("dir/reader.txt", Cursor::new(b"from a reader".to_vec()), None).unwrap();
+    builder.add_file("dir/file.txt", &file_path, None).unwrap();
+    let package = Package::load_from_vec(builder.write_to_vec().unwrap()).unwrap();
+
+    assert_eq!(load(&package, "bytes"), b"from bytes");
+    assert_eq!(load(&package, "dir/reader"), b"from a reader");
+    assert_eq!(load(&package, "dir/file"), b"from a file");
+    assert_eq!(package.find_resource(&uid("dir/file")).unwrap().media_type, "text/plain");
+}
+
+#[test]
+fn missing_files_are_rejected() {
+    let dir = TestDir::new();
+    let mut builder = PackageBuilder::new(options("builder")).unwrap();
+    assert!(builder.add_file("missing.txt", dir.path().join("missing.txt"), None).is_err());
+    let err = builder.add_file("dir.txt", dir.path(), None).unwrap_err();
+    assert!(err.contains("is not a regular file"), "{}", err);
+}
+
+#[test]
+fn reader_data_is_stored_raw_if_compression_does_not_help() {
+    let noise = incompressible(64 * 1024, 1);
+    let text = b"compressible ".repeat(10_000);
+
+    let mut builder = PackageBuilder::new(deflate_options("builder")).unwrap();
+    builder.add_reader("noise.bin", Cursor::new(noise.clone()), None).unwrap();
+    builder.add_reader("text.txt", Cursor::new(text.clone()), None).unwrap();
+    builder.add_bytes("after.txt", b"after".to_vec(), None).unwrap();
+    let package = Package::load_from_vec(builder.write_to_vec().unwrap()).unwrap();
+
+    let noise_res = package.find_resource(&uid("noise")).unwrap();
+    assert_eq!(noise_res.packed_size, noise_res.size);
+    assert_eq!(noise_res.load().unwrap(), noise);
+
+    let text_res = package.find_resource(&uid("text")).unwrap();
+    assert!(text_res.packed_size < text_res.size);
+    assert_eq!(text_res.load().unwrap(), text);
+
+    // resources written afterwards aren't affected by the discarded data
+    assert_eq!(load(&package, "after"), b"after");
+    assert!(arp::verify(&package).is_ok());
+}
+
+#[test]
+fn reader_data_is_stored_raw_in_files() {
+    let dir = TestDir::new();
+    let noise = incompressible(64 * 1024, 2);
+    let text = b"compressible ".repeat(10_000);
+
+    let mut builder = PackageBuilder::new(deflate_options("builder")).unwrap();
+    builder.add_reader("noise.bin", Cursor::new(noise.clone()), None).unwrap();
+    builder.add_reader("text.txt", Cursor::new(text.clone()), None).unwrap();
+    builder.write_to_dir(dir.path()).unwrap();
+
+    let package = Package::load_from_file(dir.path().join("builder.arp")).unwrap();
+    let noise_res = package.find_resource(&uid("noise")).unwrap();
+    assert_eq!(noise_res.packed_size, noise_res.size);
+    assert_eq!(noise_res.load().unwrap(), noise);
+    assert_eq!(load(&package, "text"), text);
+    assert!(arp::verify(&package).is_ok());
+}
+
+#[test]
+fn duplicate_paths_are_rejected() {
+    let mut builder = PackageBuilder::new(options("builder")).unwrap();
+    builder.add_bytes("a.txt", b"a".to_vec(), None).unwrap();
+    builder.add_bytes("dir/b.txt", b"b".to_vec(), None).unwrap();
+
+    // the extension isn't part of the UID, so it doesn't tell resources apart
+    let err = builder.add_bytes("a.json", b"a".to_vec(), None).unwrap_err();
+    assert!(err.contains("'a' is already in use"), "{}", err);
+    let err = builder.add_reader("a.txt", Cursor::new(Vec::new()), None).unwrap_err();
+    assert!(err.contains("already in use"), "{}", err);
+
+    // resources and directories can't share a path either
+    let err = builder.add_bytes("dir.txt", b"dir".to_vec(), None).unwrap_err();
+    assert!(err.contains("'dir' is already in use"), "{}", err);
+    let err = builder.add_bytes("a/c.txt", b"c".to_vec(), None).unwrap_err();
+    assert!(err.contains("'a' is already in use"), "{}", err);
+
+    let package = Package::load_from_vec(builder.write_to_vec().unwrap()).unwrap();
+    assert_eq!(load(&package, "a"), b"a");
+    assert_eq!(load(&package, "dir/b"), b"b");
+}
+
+const PNG: &[u8] = b"\x89PNG\r\n\x1A\n\0\0\0\x0DIHDR";
+
+/// Packs PNG data and some text under various extensions, returning each resource's UID path
+/// and media type.
+fn sniffed_media_types(mode: SniffMode) -> Vec<(String, String)> {
+    let mut builder = PackageBuilder::new(options("builder").with_sniff_mode(mode)).unwrap();
+    builder.add_bytes("png_unknown.unknown", PNG.to_vec(), None).unwrap();
+    builder.add_bytes("png_txt.txt", PNG.to_vec(), None).unwrap();
+    builder.add_reader("png_reader.txt", Cursor::new(PNG.to_vec()), None).unwrap();
+    builder.add_bytes("png_explicit.txt", PNG.to_vec(), Some("image/x-explicit")).unwrap();
+    builder.add_bytes("text_unknown.unknown", b"some text".to_vec(), None).unwrap();
+    builder.add_bytes("text_json.json", b"{}".to_vec(), None).unwrap();
+    let package = Package::load_from_vec(builder.write_to_vec().unwrap()).unwrap();
+
+    // peeking at a reader's contents mustn't consume them
+    assert_eq!(load(&package, "png_reader"), PNG);
+
+    let mut media_types: Vec<_> = package.get_all_resource_descriptors().into_iter()
+        .map(|desc| (desc.identifier.components.join("/"), desc.media_type.clone()))
+        .collect();
+    media_types.sort();
+    media_types
+}
+
+fn expected_media_types(types: &[(&str, &str)]) -> Vec<(String, String)> {
+    types.iter().map(|(path, media_type)| (path.to_string(), media_type.to_string())).collect()
+}
+
+#[test]
+fn media_types_come_from_extensions_when_sniffing_is_disabled() {
+    assert_eq!(sniffed_media_types(SniffMode::Disabled), expected_media_types(&[
+        ("png_explicit", "image/x-explicit"),
+        ("png_reader", "text/plain"),
+        ("png_txt", "text/plain"),
+        ("png_unknown", DEFAULT_MEDIA_TYPE),
+        ("text_json", "application/json"),
+        ("text_unknown", DEFAULT_MEDIA_TYPE),
+    ]));
+}
+
+#[test]
+fn fallback_sniffing_only_applies_to_unknown_extensions() {
+    assert_eq!(sniffed_media_types(SniffMode::Fallback), expected_media_types(&[
+        ("png_explicit", "image/x-explicit"),
+        ("png_reader", "text/plain"),
+        ("png_txt", "text/plain"),
+        ("png_unknown", "image/png"),
+        ("text_json", "application/json"),
+        ("text_unknown", "text/plain"),
+    ]));
+}
+
+#[test]
+fn override_sniffing_takes_precedence_over_extensions() {
+    // generic formats such as plain text don't override the extension
+    assert_eq!(sniffed_media_types(SniffMode::Override), expected_media_types(&[
+        ("png_explicit", "image/x-explicit"),
+        ("png_reader", "image/png"),
+        ("png_txt", "image/png"),
+        ("png_unknown", "image/png"),
+        ("text_json", "application/json"),
+        ("text_unknown", "text/plain"),
+    ]));
+}
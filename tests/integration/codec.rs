@@ -0,0 +1,194 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use arp::{
+    CodecMagic, CodecRegistry, CompressionLevel, CompressionType, Compressor, Decompressor,
+    Package, PackingOptions,
+};
+
+use crate::common::{
+    build, builder, deflate_options, incompressible, load, media_types, uid, NAMESPACE,
+};
+
+const RLE_MAGIC: CodecMagic = *b"rl";
+
+/// A run-length codec storing each run as a count followed by the repeated byte.
+struct RleCodec;
+
+impl Compressor for RleCodec {
+    fn magic(&self) -> CodecMagic {
+        RLE_MAGIC
+    }
+
+    fn compress(&self, data: &[u8], _level: CompressionLevel) -> Result<Vec<u8>, String> {
+        let mut out = Vec::new();
+        for run in data.chunk_by(|a, b| a == b) {
+            for chunk in run.chunks(u8::MAX as usize) {
+                out.extend_from_slice(&[chunk.len() as u8, chunk[0]]);
+            }
+        }
+        Ok(out)
+    }
+}
+
+impl Decompressor for RleCodec {
+    fn magic(&self) -> CodecMagic {
+        RLE_MAGIC
+    }
+
+    fn decompress(&self, data: &[u8], unpacked_len: u64) -> Result<Vec<u8>, String> {
+        if !data.len().is_multiple_of(2) {
+            return Err("Truncated run".to_owned());
+        }
+        let out: Vec<u8> = data.chunks(2)
+            .flat_map(|run| std::iter::repeat_n(run[1], run[0] as usize))
+            .collect();
+        if out.len() as u64 != unpacked_len {
+            return Err("Unexpected unpacked length".to_owned());
+        }
+        Ok(out)
+    }
+}
+
+fn rle_codecs() -> Arc<CodecRegistry> {
+    let mut codecs = CodecRegistry::default();
+    codecs.register_compressor(Arc::new(RleCodec)).unwrap();
+    codecs.register_decompressor(Arc::new(RleCodec)).unwrap();
+    Arc::new(codecs)
+}
+
+fn rle_package() -> Vec<u8> {
+    let options = PackingOptions::new_v2(
+        "codec",
+        NAMESPACE,
+        None,
+        Some(CompressionType::Custom(RLE_MAGIC)),
+        None::<&Path>,
+    ).unwrap().with_codecs(rle_codecs());
+
+    let mut runs = vec![b'a'; 1000];
+    runs.extend_from_slice(&[b'b'; 300]);
+    builder(options, &[("runs.txt", runs)]).write_to_vec().unwrap()
+}
+
+#[test]
+fn custom_codec_round_trip() {
+    let package = Package::load_from_vec_with_codecs(rle_package(), rle_codecs()).unwrap();
+    assert_eq!(
+        package.get_meta().compression_type,
+        Some(CompressionType::Custom(RLE_MAGIC)),
+    );
+
+    let resource = package.find_resource(&uid("runs")).unwrap();
+    assert!(resource.packed_size < resource.size);
+    let mut expected = vec![b'a'; 1000];
+    expected.extend_from_slice(&[b'b'; 300]);
+    assert_eq!(resource.load().unwrap(), expected);
+
+    // streamed reads go through the decompressor's default buffered implementation
+    let mut streamed = Vec::new();
+    resource.write_to(&mut streamed).unwrap();
+    assert_eq!(streamed, expected);
+}
+
+#[test]
+fn unregistered_codec_is_rejected_at_load() {
+    let data = rle_package();
+    let err = Package::load_from_vec(data.clone()).err().unwrap();
+    assert!(err.contains("'rl' is not registered"), "{}", err);
+
+    // registering only a compressor isn't enough to read the package
+    let mut codecs = CodecRegistry::default();
+    codecs.register_compressor(Arc::new(RleCodec)).unwrap();
+    assert!(Package::load_from_vec_with_codecs(data, Arc::new(codecs)).is_err());
+}
+
+#[test]
+fn packing_with_unregistered_codec_fails() {
+    let options = PackingOptions::new_v2(
+        "codec",
+        NAMESPACE,
+        None,
+        Some(CompressionType::Custom(RLE_MAGIC)),
+        None::<&Path>,
+    ).unwrap();
+    assert!(builder(options, &[("a.txt", "a")]).write_to_vec().is_err());
+}
+
+#[test]
+fn codec_magic_cannot_begin_with_null() {
+    struct NullCodec;
+
+    impl Compressor for NullCodec {
+        fn magic(&self) -> CodecMagic {
+            [0, b'x']
+        }
+
+        fn compress(&self, data: &[u8], _level: CompressionLevel) -> Result<Vec<u8>, String> {
+            Ok(data.to_vec())
+        }
+    }
+
+    assert!(CodecRegistry::default().register_compressor(Arc::new(NullCodec)).is_err());
+}
+
+/// Returns the resource's data as stored in the package.
+fn packed_data(package: &Arc<Package>, path: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    package.find_resource(&uid(path)).unwrap().write_packed_to(&mut data).unwrap();
+    data
+}
+
+#[test]
+fn data_which_grows_when_compressed_is_stored_raw() {
+    let noise = incompressible(4096, 1);
+    let text = b"compressible ".repeat(100);
+    let package = build(deflate_options("flags"), &[("noise.bin", &noise), ("text.txt", &text)]);
+
+    // the node is flagged as uncompressed, or it couldn't be loaded back
+    assert_eq!(packed_data(&package, "noise"), noise);
+    assert_eq!(load(&package, "noise"), noise);
+
+    assert!(packed_data(&package, "text").len() < text.len());
+    assert_eq!(load(&package, "text"), text);
+}
+
+#[test]
+fn uncompressed_media_types_are_stored_raw() {
+    let text = b"compressible ".repeat(100);
+    let resources = [("image.png", &text), ("sound.ogg", &text), ("text.txt", &text)];
+
+    let package = build(deflate_options("flags"), &resources);
+    assert_eq!(packed_data(&package, "image"), text);
+    assert_eq!(packed_data(&package, "sound"), text);
+    assert_ne!(packed_data(&package, "text"), text);
+
+    let options = deflate_options("flags").with_uncompressed_media_types(["TEXT/*"]);
+    let package = build(options, &resources);
+    assert_ne!(packed_data(&package, "image"), text);
+    assert_eq!(packed_data(&package, "text"), text);
+    for path in ["image", "sound", "text"] {
+        assert_eq!(load(&package, path), text);
+    }
+}
+
+#[test]
+fn v1_packages_compress_every_resource() {
+    // v1 node descriptors have no flags, so nothing can be marked as stored raw
+    let noise = incompressible(4096, 1);
+    let text = b"compressible ".repeat(100);
+    let options = PackingOptions::new_v1(
+        "flags",
+        NAMESPACE,
+        None,
+        Some(CompressionType::Deflate),
+        None::<&Path>,
+    ).unwrap().with_media_types(media_types());
+    let package = build(options, &[("image.png", &text), ("noise.bin", &noise)]);
+    assert_eq!(package.get_meta().major_version, 1);
+
+    assert!(packed_data(&package, "image").len() < text.len());
+    assert!(packed_data(&package, "noise").len() > noise.len());
+    assert_eq!(load(&package, "image"), text);
+    assert_eq!(load(&package, "noise"), noise);
+}
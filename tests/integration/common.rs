@@ -0,0 +1,136 @@
+//! Helpers shared by the integration tests.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use arp::{
+    CodecMagic, CompressionLevel, CompressionType, Compressor, MediaTypeRegistry, Package,
+    PackageBuilder, PackingOptions, ResourceIdentifier,
+};
+
+pub const NAMESPACE: &str = "test";
+
+/// A scratch directory which is removed when dropped.
+pub struct TestDir(PathBuf);
+
+impl TestDir {
+    pub fn new() -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "arp-test-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed),
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Returns the names and contents of all files in the directory, sorted by name.
+    pub fn snapshot(&self) -> Vec<(String, Vec<u8>)> {
+        let mut files: Vec<_> = fs::read_dir(&self.0).unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                (entry.file_name().into_string().unwrap(), fs::read(entry.path()).unwrap())
+            })
+            .collect();
+        files.sort();
+        files
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A compressor which always fails, for checking that packed data is copied as-is.
+pub struct FailingCompressor;
+
+impl Compressor for FailingCompressor {
+    fn magic(&self) -> CodecMagic {
+        CompressionType::Deflate.get_magic()
+    }
+
+    fn compress(&self, _data: &[u8], _level: CompressionLevel) -> Result<Vec<u8>, String> {
+        Err("Data should not have been recompressed".to_owned())
+    }
+}
+
+/// Generates data which deflate can't shrink, so that resources occupy predictable space.
+pub fn incompressible(len: usize, seed: u32) -> Vec<u8> {
+    let mut state = seed.wrapping_mul(0x9E37_79B9) | 1;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+/// The extension mappings the tests rely on, so that they pass without the builtin table.
+pub fn media_types() -> MediaTypeRegistry {
+    let mut registry = MediaTypeRegistry::empty();
+    registry.load_csv("txt,text/plain\njson,application/json\npng,image/png\nogg,audio/ogg")
+        .unwrap();
+    registry
+}
+
+/// Options for an uncompressed v2 package in the test namespace.
+pub fn options(name: &str) -> PackingOptions {
+    PackingOptions::new_v2(name, NAMESPACE, None, None, None::<&Path>).unwrap()
+        .with_media_types(media_types())
+}
+
+/// Options for a deflate-compressed v2 package in the test namespace.
+pub fn deflate_options(name: &str) -> PackingOptions {
+    PackingOptions::new_v2(name, NAMESPACE, None, Some(CompressionType::Deflate), None::<&Path>)
+        .unwrap().with_media_types(media_types())
+}
+
+/// Returns a builder populated with the given paths and contents.
+pub fn builder<C: AsRef<[u8]>>(options: PackingOptions, resources: &[(&str, C)])
+    -> PackageBuilder {
+    let mut builder = PackageBuilder::new(options).unwrap();
+    for (path, contents) in resources {
+        builder.add_bytes(path, contents.as_ref().to_vec(), None).unwrap();
+    }
+    builder
+}
+
+/// Builds a package in memory from the given paths and contents.
+pub fn build<C: AsRef<[u8]>>(options: PackingOptions, resources: &[(&str, C)]) -> Arc<Package> {
+    Package::load_from_vec(builder(options, resources).write_to_vec().unwrap()).unwrap()
+}
+
+/// Returns the identifier of the resource at `path` in the test namespace.
+pub fn uid(path: &str) -> ResourceIdentifier {
+    ResourceIdentifier::parse(format!("{}:{}", NAMESPACE, path)).unwrap()
+}
+
+/// Loads the data of the resource at `path` in the test namespace.
+pub fn load(package: &Arc<Package>, path: &str) -> Vec<u8> {
+    package.find_resource(&uid(path)).unwrap().load().unwrap()
+}
+
+/// Replaces the only occurrence of `from` in a serialized package with `to`, which must be the
+/// same length so that the layout of the package is unchanged.
+pub fn replace_once(data: &mut [u8], from: &[u8], to: &[u8]) {
+    assert_eq!(from.len(), to.len());
+    let offsets: Vec<_> = data.windows(from.len())
+        .enumerate()
+        .filter(|(_, window)| *window == from)
+        .map(|(off, _)| off)
+        .collect();
+    assert_eq!(offsets.len(), 1, "Marker must occur exactly once");
+    data[offsets[0]..(offsets[0] + to.len())].copy_from_slice(to);
+}
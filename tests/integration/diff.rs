@@ -0,0 +1,125 @@
+use std::path::Path;
+
+use arp::{CompressionLevel, CompressionType, Package, PackingOptions};
+
+use crate::common::{build, builder, deflate_options, media_types, options, uid};
+
+#[test]
+fn identical_packages_have_empty_diff() {
+    let resources = [("a.txt", "a"), ("dir/b.txt", "b")];
+    let diff = arp::diff(&build(options("old"), &resources), &build(options("new"), &resources))
+        .unwrap();
+    assert!(diff.is_empty());
+}
+
+#[test]
+fn diff_lists_added_removed_and_modified_resources() {
+    let old = build(options("old"), &[
+        ("a.txt", "a"),
+        ("dir/b.txt", "b"),
+        ("dir/c.txt", "c"),
+        ("e.txt", "same size"),
+    ]);
+    let new = build(options("new"), &[
+        ("a.txt", "a"),
+        ("dir/c.txt", "longer c"),
+        ("dir/d.txt", "d"),
+        ("e.txt", "same s1ze"),
+        ("f.txt", "f"),
+    ]);
+
+    let diff = arp::diff(&old, &new).unwrap();
+    assert!(diff.header.is_empty());
+    assert_eq!(diff.added, vec!["dir/d", "f"]);
+    assert_eq!(diff.removed, vec!["dir/b"]);
+
+    let modified: Vec<_> = diff.modified.iter()
+        .map(|res| (res.path.as_str(), res.content_changed, res.old_size, res.new_size))
+        .collect();
+    assert_eq!(modified, vec![("dir/c", true, 1, 8), ("e", true, 9, 9)]);
+}
+
+#[test]
+fn diff_reports_metadata_only_changes() {
+    let mut old = builder(options("old"), &[("a.txt", "a")]);
+    old.add_bytes("b.bin", b"b".to_vec(), Some("application/x-old")).unwrap();
+    let old = Package::load_from_vec(old.write_to_vec().unwrap()).unwrap();
+
+    let mut new = builder(options("new"), &[("a.json", "a")]);
+    new.add_bytes("b.bin", b"b".to_vec(), Some("application/x-new")).unwrap();
+    let new = Package::load_from_vec(new.write_to_vec().unwrap()).unwrap();
+
+    let diff = arp::diff(&old, &new).unwrap();
+    assert!(diff.added.is_empty() && diff.removed.is_empty());
+    assert_eq!(diff.modified.len(), 2);
+
+    let a = &diff.modified[0];
+    assert_eq!(a.path, "a");
+    assert!(!a.content_changed);
+    assert_eq!((a.old_extension.as_str(), a.new_extension.as_str()), ("txt", "json"));
+    assert_eq!(a.old_media_type, "text/plain");
+    assert_eq!(a.new_media_type, "application/json");
+
+    let b = &diff.modified[1];
+    assert_eq!(b.path, "b");
+    assert!(!b.content_changed);
+    assert_eq!(b.old_extension, b.new_extension);
+    assert_eq!(b.old_media_type, "application/x-old");
+    assert_eq!(b.new_media_type, "application/x-new");
+}
+
+#[test]
+fn diff_reports_header_changes() {
+    let old = build(options("old"), &[("a.txt", "a")]);
+    let new_options = PackingOptions::new_v1(
+        "new",
+        "other",
+        None,
+        Some(CompressionType::Deflate),
+        None::<&Path>,
+    ).unwrap().with_media_types(media_types());
+    let new = build(new_options, &[("a.txt", "a")]);
+
+    let diff = arp::diff(&old, &new).unwrap();
+    let header: Vec<_> = diff.header.iter()
+        .map(|change| (change.field, change.old.as_str(), change.new.as_str()))
+        .collect();
+    assert_eq!(header, vec![
+        ("version", "2", "1"),
+        ("namespace", "test", "other"),
+        ("compression", "none", "df"),
+    ]);
+    // resources are compared by path, regardless of namespace
+    assert!(diff.modified.is_empty());
+}
+
+#[test]
+fn differently_compressed_resources_are_compared_by_content() {
+    let text = "compressible ".repeat(1000);
+    let changed = text.replacen("compressible", "compressable", 1);
+
+    let old = build(
+        deflate_options("diff").with_compression_level(CompressionLevel::BEST),
+        &[("a.txt", &text), ("b.txt", &text)],
+    );
+    let new = build(
+        deflate_options("diff").with_compression_level(CompressionLevel::new(1).unwrap()),
+        &[("a.txt", &text), ("b.txt", &changed)],
+    );
+    let old_a = old.find_resource(&uid("a")).unwrap();
+    let new_a = new.find_resource(&uid("a")).unwrap();
+    // otherwise the CRCs would be enough to tell the contents apart
+    assert_ne!(old_a.get_crc(), new_a.get_crc());
+
+    let diff = arp::diff(&old, &new).unwrap();
+    let modified: Vec<_> = diff.modified.iter()
+        .map(|res| (res.path.as_str(), res.content_changed))
+        .collect();
+    assert_eq!(modified, vec![("b", true)]);
+
+    // the same goes for resources stored raw in one package and compressed in the other
+    let raw = build(options("raw"), &[("a.txt", &text), ("b.txt", &text)]);
+    let diff = arp::diff(&raw, &new).unwrap();
+    let modified: Vec<_> = diff.modified.iter().map(|res| res.path.as_str()).collect();
+    assert_eq!(modified, vec!["b"]);
+}
@@ -0,0 +1,120 @@
+use std::fs;
+
+use arp::{extract_to_dir, ExistingFilePolicy, ExtractOptions, Package, PackageBuilder};
+
+use crate::common::{
+    build, builder, deflate_options, incompressible, options, replace_once, TestDir,
+};
+
+#[test]
+fn crafted_names_are_rejected_at_load_time() {
+    let cases: [(&str, &[u8], &[u8]); 7] = [
+        ("qq/a.txt", b"qq", b".."),
+        ("q/a.txt", b"q", b"."),
+        ("zzz.txt", b"zzz", b"../"),
+        ("azb.txt", b"azb", b"a/b"),
+        ("azb.txt", b"azb", b"a\\b"),
+        ("azb.txt", b"azb", b"a\x01b"),
+        ("a.qq", b"qq", b".."),
+    ];
+
+    for (path, from, to) in cases {
+        let mut data = builder(options("crafted"), &[(path, "contents")])
+            .write_to_vec().unwrap();
+        // the unmodified package must be fine, or the check below proves nothing
+        assert!(Package::load_from_vec(data.clone()).is_ok());
+        replace_once(&mut data, from, to);
+        assert!(
+            Package::load_from_vec(data).is_err(),
+            "Crafted name {:?} was accepted",
+            String::from_utf8_lossy(to),
+        );
+    }
+}
+
+#[test]
+fn builder_rejects_dot_components() {
+    let mut builder = PackageBuilder::new(options("crafted")).unwrap();
+    assert!(builder.add_bytes("../a.txt", b"a".to_vec(), None).is_err());
+    assert!(builder.add_bytes("dir/./a.txt", b"a".to_vec(), None).is_err());
+    assert!(builder.add_bytes("dir/a.txt", b"a".to_vec(), None).is_ok());
+}
+
+#[test]
+fn extract_to_dir_writes_files() {
+    let dir = TestDir::new();
+    let package = build(options("crafted"), &[("a.txt", "a"), ("dir/sub/b.txt", "b")]);
+    let resources = package.get_all_resource_descriptors();
+
+    let written = extract_to_dir(&resources, dir.path(), &ExtractOptions::default()).unwrap();
+    assert_eq!(written.len(), 2);
+    assert_eq!(fs::read(dir.path().join("a.txt")).unwrap(), b"a");
+    assert_eq!(fs::read(dir.path().join("dir/sub/b.txt")).unwrap(), b"b");
+
+    fs::write(dir.path().join("a.txt"), b"local").unwrap();
+    fs::remove_file(dir.path().join("dir/sub/b.txt")).unwrap();
+    let skip = ExtractOptions::default().with_existing(ExistingFilePolicy::Skip);
+    assert_eq!(extract_to_dir(&resources, dir.path(), &skip).unwrap().len(), 1);
+    assert_eq!(fs::read(dir.path().join("a.txt")).unwrap(), b"local");
+
+    let error = ExtractOptions::default().with_existing(ExistingFilePolicy::Error);
+    assert!(extract_to_dir(&resources, dir.path(), &error).is_err());
+
+    let flat_dir = TestDir::new();
+    let flatten = ExtractOptions::default().with_flatten(true);
+    extract_to_dir(&resources, flat_dir.path(), &flatten).unwrap();
+    assert_eq!(fs::read(flat_dir.path().join("b.txt")).unwrap(), b"b");
+}
+
+#[test]
+fn flattened_collisions_are_rejected() {
+    let dir = TestDir::new();
+    let package = build(options("flat"), &[("a/x.txt", "a"), ("b/x.txt", "b"), ("c.txt", "c")]);
+    let resources = package.get_all_resource_descriptors();
+
+    let flatten = ExtractOptions::default().with_flatten(true);
+    let err = extract_to_dir(&resources, dir.path(), &flatten).unwrap_err();
+    assert!(err.contains("would both be extracted to"), "{}", err);
+    assert!(err.contains("test:a/x") && err.contains("test:b/x"), "{}", err);
+    // nothing is written, not even resources which don't collide
+    assert!(dir.snapshot().is_empty());
+
+    let written = extract_to_dir(&resources, dir.path(), &ExtractOptions::default()).unwrap();
+    assert_eq!(written.len(), 3);
+}
+
+#[test]
+fn files_which_would_contain_other_files_are_rejected() {
+    let dir = TestDir::new();
+    // a resource without an extension is extracted to the path the other package's directory needs
+    let file = build(options("file"), &[("x", "x")]);
+    let nested = build(options("nested"), &[("x/y.txt", "y"), ("z.txt", "z")]);
+    let mut resources = file.get_all_resource_descriptors();
+    resources.extend(nested.get_all_resource_descriptors());
+
+    for resources in [resources.clone(), resources.into_iter().rev().collect()] {
+        let err = extract_to_dir(&resources, dir.path(), &ExtractOptions::default()).unwrap_err();
+        assert!(err.contains("would be extracted beneath"), "{}", err);
+        assert!(err.contains("test:x/y") && err.contains("test:x "), "{}", err);
+        assert!(dir.snapshot().is_empty());
+    }
+}
+
+#[test]
+fn extracted_data_is_streamed_and_checked() {
+    let dir = TestDir::new();
+    // larger than anything the packer buffers
+    let large = incompressible(5 << 20, 1);
+    let package = build(deflate_options("large"), &[("large.bin", &large)]);
+    extract_to_dir(&package.get_all_resource_descriptors(), dir.path(), &ExtractOptions::default())
+        .unwrap();
+    assert!(fs::read(dir.path().join("large.bin")).unwrap() == large);
+
+    let mut data = builder(options("corrupt"), &[("a.txt", "alpha")]).write_to_vec().unwrap();
+    replace_once(&mut data, b"alpha", b"alphx");
+    let package = Package::load_from_vec(data).unwrap();
+    let resources = package.get_all_resource_descriptors();
+    assert!(extract_to_dir(&resources, dir.path(), &ExtractOptions::default()).is_err());
+    // the corrupt data is only noticed once it has been written, so the file is removed again
+    assert!(!dir.path().join("a.txt").exists());
+}
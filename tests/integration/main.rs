@@ -0,0 +1,15 @@
+//! Integration tests, built as a single crate so that they share the helpers in `common`.
+
+mod common;
+
+#[cfg(feature = "arptool")]
+mod arptool;
+mod builder;
+mod codec;
+mod diff;
+mod extract;
+mod media_types;
+mod merge;
+mod pack;
+mod patch;
+mod verify;
@@ -0,0 +1,183 @@
+use std::fs;
+use std::path::Path;
+
+use arp::{MediaTypeRegistry, PackingOptions, DEFAULT_MEDIA_TYPE};
+
+use crate::common::{build, uid, TestDir, NAMESPACE};
+
+#[test]
+fn later_layers_take_precedence() {
+    let mut registry = MediaTypeRegistry::empty();
+    registry.load_csv("png,image/png\ntxt,text/plain\ndat,application/x-first").unwrap();
+    registry.load_csv("dat,application/x-second\nbin,application/x-second").unwrap();
+    registry.insert("txt", "text/x-inserted");
+
+    assert_eq!(registry.get_media_type("png"), Some("image/png"));
+    assert_eq!(registry.get_media_type("dat"), Some("application/x-second"));
+    assert_eq!(registry.get_media_type("txt"), Some("text/x-inserted"));
+    assert_eq!(registry.get_media_type("bin"), Some("application/x-second"));
+    assert_eq!(registry.get_media_type("unknown"), None);
+
+    // the earlier type's only extension has been remapped
+    assert_eq!(registry.get_extension("application/x-first"), None);
+    assert_eq!(registry.get_extension("text/plain"), None);
+}
+
+#[test]
+fn lookups_are_case_insensitive() {
+    let mut registry = MediaTypeRegistry::empty();
+    registry.load_csv("PNG,image/png\njpg,Image/JPEG").unwrap();
+
+    assert_eq!(registry.get_media_type("png"), Some("image/png"));
+    assert_eq!(registry.get_media_type("Png"), Some("image/png"));
+    // media types are returned as they were given
+    assert_eq!(registry.get_media_type("JPG"), Some("Image/JPEG"));
+
+    assert_eq!(registry.get_extension("IMAGE/PNG"), Some("png"));
+    assert_eq!(registry.get_extension("image/jpeg"), Some("jpg"));
+
+    // a differently cased mapping in a later layer replaces the earlier one
+    registry.insert("Png", "image/x-png");
+    assert_eq!(registry.get_media_type("PNG"), Some("image/x-png"));
+}
+
+#[test]
+fn preferred_extension_is_first_listed_by_latest_layer() {
+    let mut registry = MediaTypeRegistry::empty();
+    registry.load_csv("jpeg,image/jpeg\njpg,image/jpeg\njpe,image/jpeg").unwrap();
+    assert_eq!(registry.get_extension("image/jpeg"), Some("jpeg"));
+
+    registry.load_csv("jpg,image/jpeg").unwrap();
+    assert_eq!(registry.get_extension("image/jpeg"), Some("jpg"));
+
+    // once the preferred extension is remapped, the smallest remaining one is used
+    registry.insert("jpg", "image/x-other");
+    assert_eq!(registry.get_extension("image/jpeg"), Some("jpe"));
+    assert_eq!(registry.get_extension("image/x-other"), Some("jpg"));
+    assert_eq!(registry.get_extension("image/unknown"), None);
+}
+
+#[test]
+fn csv_ignores_comments_and_whitespace() {
+    let mut registry = MediaTypeRegistry::empty();
+    registry.load_csv("# extension,media type\n\n  png , image/png \r\n").unwrap();
+    assert_eq!(registry.get_media_type("png"), Some("image/png"));
+    assert_eq!(registry.get_media_type("# extension"), None);
+}
+
+#[test]
+fn csv_map_skips_comments() {
+    let media_types = arp::load_media_types_from_csv("# png,image/x-comment\n\npng,image/png\n");
+    assert_eq!(media_types.len(), 1);
+    assert_eq!(media_types.get("png"), Some(&"image/png"));
+    assert_eq!(media_types.get("# png"), None);
+}
+
+#[test]
+fn malformed_csv_is_rejected() {
+    let mut registry = MediaTypeRegistry::empty();
+    registry.insert("png", "image/png");
+
+    let err = registry.load_csv("txt,text/plain\n\ngif image/gif\n").unwrap_err();
+    assert!(err.contains("line 3"), "{}", err);
+
+    // nothing from the rejected content is applied
+    assert_eq!(registry.get_media_type("txt"), None);
+    assert_eq!(registry.get_media_type("png"), Some("image/png"));
+}
+
+const GLOBS2: &str = "\
+# comment
+50:text/x-csrc:*.c:cs
+50:application/x-compressed-tar:*.tar.gz
+50:text/x-c-header:*.[ch]
+50:text/x-makefile:Makefile
+10:text/x-low:*.dat
+80:application/x-high:*.dat
+50:application/x-mid:*.dat
+50:image/png:*.png
+not a glob line
+";
+
+#[test]
+fn globs2_maps_simple_extensions() {
+    let mut registry = MediaTypeRegistry::empty();
+    registry.load_globs2(GLOBS2);
+
+    // trailing flags are ignored
+    assert_eq!(registry.get_media_type("c"), Some("text/x-csrc"));
+    assert_eq!(registry.get_media_type("png"), Some("image/png"));
+    // only globs matching a single extension are used
+    assert_eq!(registry.get_media_type("gz"), None);
+    assert_eq!(registry.get_media_type("tar.gz"), None);
+    assert_eq!(registry.get_media_type("h"), None);
+    assert_eq!(registry.get_extension("text/x-makefile"), None);
+}
+
+#[test]
+fn globs2_prefers_heavier_globs() {
+    let mut registry = MediaTypeRegistry::empty();
+    registry.load_globs2(GLOBS2);
+    assert_eq!(registry.get_media_type("dat"), Some("application/x-high"));
+    assert_eq!(registry.get_extension("text/x-low"), None);
+}
+
+const MIME_TYPES: &str = "\
+# MIME type\t\tExtensions
+image/jpeg\t\t\tjpeg jpg jpe
+text/plain  txt   text
+
+application/x-no-extensions
+  audio/ogg oga ogg
+";
+
+#[test]
+fn mime_types_lists_extensions_per_type() {
+    let mut registry = MediaTypeRegistry::empty();
+    registry.load_mime_types(MIME_TYPES);
+
+    for ext in ["jpeg", "jpg", "jpe"] {
+        assert_eq!(registry.get_media_type(ext), Some("image/jpeg"));
+    }
+    assert_eq!(registry.get_media_type("text"), Some("text/plain"));
+    assert_eq!(registry.get_media_type("ogg"), Some("audio/ogg"));
+    assert_eq!(registry.get_media_type("#"), None);
+
+    // the first extension listed for a type is preferred
+    assert_eq!(registry.get_extension("image/jpeg"), Some("jpeg"));
+    assert_eq!(registry.get_extension("audio/ogg"), Some("oga"));
+    assert_eq!(registry.get_extension("application/x-no-extensions"), None);
+}
+
+#[test]
+fn mime_database_files_are_loaded() {
+    let dir = TestDir::new();
+    let globs2_path = dir.path().join("globs2");
+    let mime_types_path = dir.path().join("mime.types");
+    fs::write(&globs2_path, GLOBS2).unwrap();
+    fs::write(&mime_types_path, "image/x-png png\n").unwrap();
+
+    let mut registry = MediaTypeRegistry::empty();
+    registry.load_globs2_file(&globs2_path).unwrap();
+    registry.load_mime_types_file(&mime_types_path).unwrap();
+    assert_eq!(registry.get_media_type("c"), Some("text/x-csrc"));
+    assert_eq!(registry.get_media_type("png"), Some("image/x-png"));
+
+    assert!(registry.load_globs2_file(dir.path().join("missing")).is_err());
+    assert!(registry.load_mime_types_file(dir.path().join("missing")).is_err());
+}
+
+#[test]
+fn builtin_table_is_used_by_default() {
+    let png_type = if cfg!(feature = "builtin-media-types") {
+        Some("image/png")
+    } else {
+        None
+    };
+    assert_eq!(MediaTypeRegistry::builtin().get_media_type("png"), png_type);
+
+    let options = PackingOptions::new_v2("builtin", NAMESPACE, None, None, None::<&Path>).unwrap();
+    let package = build(options, &[("a.png", "a")]);
+    let resource = package.find_resource(&uid("a")).unwrap();
+    assert_eq!(resource.media_type, png_type.unwrap_or(DEFAULT_MEDIA_TYPE));
+}
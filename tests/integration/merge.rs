@@ -0,0 +1,86 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use arp::{CodecRegistry, CompressionType, ConflictPolicy, Package, PackingOptions};
+
+use crate::common::{build, builder, incompressible, load, FailingCompressor, TestDir, NAMESPACE};
+
+fn options(compression_type: Option<CompressionType>) -> PackingOptions {
+    PackingOptions::new_v2("merged", NAMESPACE, None, compression_type, None::<&Path>).unwrap()
+}
+
+#[test]
+fn conflict_policies() {
+    let a = build(options(None), &[("x.txt", "a"), ("a.txt", "a")]);
+    let b = build(options(None), &[("x.txt", "b"), ("dir/b.txt", "b")]);
+    let packages = [a, b];
+
+    let merge = |policy| {
+        let builder = arp::merge(&packages, options(None), policy)?;
+        Package::load_from_vec(builder.write_to_vec()?)
+    };
+
+    let first = merge(ConflictPolicy::FirstWins).unwrap();
+    assert_eq!(first.get_all_resource_descriptors().len(), 3);
+    assert_eq!(load(&first, "x"), b"a");
+    assert_eq!(load(&first, "dir/b"), b"b");
+
+    let last = merge(ConflictPolicy::LastWins).unwrap();
+    assert_eq!(load(&last, "x"), b"b");
+    assert_eq!(load(&last, "a"), b"a");
+
+    let err = merge(ConflictPolicy::Error).err().unwrap();
+    assert_eq!(err, "Resource 'x' in package 2 conflicts with resource 'x' in package 1");
+}
+
+#[test]
+fn resources_conflict_with_directories() {
+    let dir = TestDir::new();
+    builder(options(None), &[("x.txt", "x"), ("y/a.txt", "a"), ("y/b.txt", "b")])
+        .write_to_dir(dir.path()).unwrap();
+    let first = Package::load_from_file(dir.path().join("merged.arp")).unwrap();
+    let second = build(options(None), &[("x/c.txt", "c"), ("y.txt", "y"), ("z.txt", "z")]);
+    let packages = [first, second];
+
+    let merge = |policy| {
+        let builder = arp::merge(&packages, options(None), policy)?;
+        let merged = Package::load_from_vec(builder.write_to_vec()?)?;
+        let mut paths: Vec<_> = merged.get_all_resource_descriptors().into_iter()
+            .map(|desc| desc.identifier.components.join("/"))
+            .collect();
+        paths.sort();
+        Ok::<_, String>(paths)
+    };
+
+    assert_eq!(merge(ConflictPolicy::FirstWins).unwrap(), vec!["x", "y/a", "y/b", "z"]);
+    // a resource replaces every resource beneath its path, and a directory the resource at it
+    assert_eq!(merge(ConflictPolicy::LastWins).unwrap(), vec!["x/c", "y", "z"]);
+
+    let err = merge(ConflictPolicy::Error).unwrap_err();
+    assert_eq!(err, "Resource 'x/c' in package 2 conflicts with resource 'x' in 'merged'");
+}
+
+#[test]
+fn matching_compression_copies_packed_data() {
+    let text = b"compressible ".repeat(1000);
+    // stored raw, and large enough to be streamed rather than buffered
+    let large = incompressible(5 * 1024 * 1024, 1);
+    let a = build(options(Some(CompressionType::Deflate)), &[("a.txt", &text)]);
+    let b = build(options(Some(CompressionType::Deflate)), &[("b.bin", &large)]);
+    let packages = [a, b];
+
+    let mut codecs = CodecRegistry::default();
+    codecs.register_compressor(Arc::new(FailingCompressor)).unwrap();
+    let opts = options(Some(CompressionType::Deflate)).with_codecs(Arc::new(codecs));
+    let builder = arp::merge(&packages, opts, ConflictPolicy::Error).unwrap();
+    let merged = Package::load_from_vec(builder.write_to_vec().unwrap()).unwrap();
+    assert_eq!(load(&merged, "a"), text);
+    assert_eq!(load(&merged, "b"), large);
+
+    // a different compression type requires the data to be decompressed
+    let builder = arp::merge(&packages, options(None), ConflictPolicy::Error).unwrap();
+    let merged = Package::load_from_vec(builder.write_to_vec().unwrap()).unwrap();
+    assert_eq!(merged.get_meta().compression_type, None);
+    assert_eq!(load(&merged, "a"), text);
+    assert_eq!(load(&merged, "b"), large);
+}
@@ -0,0 +1,705 @@
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use arp::{
+    CompressionLevel, CompressionType, Package, PackageBuilder, PackingOptions, SymlinkPolicy,
+};
+
+use crate::common::{
+    deflate_options, incompressible, media_types, options, uid, TestDir, NAMESPACE,
+};
+
+const PACKAGE_NAME: &str = "test";
+const PART_LEN: u64 = 8192;
+
+/// A reader which fails after yielding a fixed number of bytes.
+struct FailingReader {
+    remaining: usize,
+}
+
+impl Read for FailingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            return Err(io::Error::other("injected failure"));
+        }
+        let len = buf.len().min(self.remaining);
+        buf[..len].fill(0x42);
+        self.remaining -= len;
+        Ok(len)
+    }
+}
+
+fn multi_part_builder(resource_count: u32) -> PackageBuilder {
+    let options = PackingOptions::new_v2(
+        PACKAGE_NAME,
+        NAMESPACE,
+        Some(PART_LEN),
+        None,
+        None::<&Path>,
+    ).unwrap();
+    let mut builder = PackageBuilder::new(options).unwrap();
+    for i in 0..resource_count {
+        builder.add_bytes(format!("res_{}.bin", i), incompressible(6000, i), None).unwrap();
+    }
+    builder
+}
+
+#[test]
+fn failed_write_leaves_existing_package_untouched() {
+    let dir = TestDir::new();
+    multi_part_builder(3).write_to_dir(dir.path()).unwrap();
+    let before = dir.snapshot();
+    assert_eq!(before.len(), 3);
+
+    // fail partway through the last resource, after several new parts have been staged
+    let mut builder = multi_part_builder(5);
+    builder.add_reader("broken.bin", FailingReader { remaining: 5000 }, None).unwrap();
+    let res = builder.write_to_dir(dir.path());
+
+    assert!(res.is_err());
+    assert_eq!(dir.snapshot(), before);
+}
+
+#[test]
+fn failed_write_to_new_dir_leaves_no_files() {
+    let dir = TestDir::new();
+    let target = dir.path().join("out");
+
+    let options = options(PACKAGE_NAME);
+    let mut builder = PackageBuilder::new(options).unwrap();
+    builder.add_bytes("a.txt", b"hello".to_vec(), None).unwrap();
+    builder.add_reader("broken.bin", FailingReader { remaining: 0 }, None).unwrap();
+
+    assert!(builder.write_to_dir(&target).is_err());
+    assert_eq!(fs::read_dir(&target).unwrap().count(), 0);
+}
+
+#[test]
+fn rewrite_removes_stale_parts() {
+    let dir = TestDir::new();
+    multi_part_builder(5).write_to_dir(dir.path()).unwrap();
+    let part_count = dir.snapshot().len();
+    assert!(part_count > 2);
+
+    multi_part_builder(2).write_to_dir(dir.path()).unwrap();
+    let names: Vec<_> = dir.snapshot().into_iter().map(|(name, _)| name).collect();
+    assert_eq!(names, vec!["test.part001.arp", "test.part002.arp"]);
+
+    multi_part_builder(1).write_to_dir(dir.path()).unwrap();
+    let names: Vec<_> = dir.snapshot().into_iter().map(|(name, _)| name).collect();
+    assert_eq!(names, vec!["test.arp"]);
+
+    let package = Package::load_from_file(dir.path().join("test.arp")).unwrap();
+    assert_eq!(package.get_all_resource_descriptors().len(), 1);
+}
+
+#[test]
+fn streamed_resources_are_moved_to_new_parts() {
+    const MIB: usize = 1024 * 1024;
+
+    let dir = TestDir::new();
+    let src = dir.path().join("src");
+    fs::create_dir_all(&src).unwrap();
+    // each file is above the streaming threshold, and only one incompressible file fits per part
+    let files = [
+        ("a.bin", incompressible(9 * MIB, 1)),
+        ("b.txt", b"compressible ".repeat(9 * MIB / 13)),
+        ("c.bin", incompressible(9 * MIB, 2)),
+        ("d.bin", incompressible(9 * MIB, 3)),
+    ];
+    for (name, data) in &files {
+        fs::write(src.join(name), data).unwrap();
+    }
+
+    for version in [1, 2] {
+        let new_options = match version {
+            1 => PackingOptions::new_v1,
+            _ => PackingOptions::new_v2,
+        };
+        let options = new_options(
+            PACKAGE_NAME,
+            NAMESPACE,
+            Some(10 * MIB as u64),
+            Some(CompressionType::Deflate),
+            None::<&Path>,
+        ).unwrap().with_compression_level(CompressionLevel::new(1).unwrap());
+
+        let out = dir.path().join(format!("out_v{}", version));
+        arp::create_arp_from_fs(&src, &out, options).unwrap();
+
+        let package = Package::load_from_file(out.join("test.part001.arp")).unwrap();
+        assert_eq!(package.get_meta().total_parts, 3, "v{}", version);
+        let mut resources = package.get_all_resource_descriptors();
+        resources.sort_by(|a, b| a.identifier.cmp(&b.identifier));
+        let parts: Vec<_> = resources.iter().map(|desc| desc.get_part()).collect();
+        assert_eq!(parts, vec![1, 1, 2, 3], "v{}", version);
+
+        for (desc, (_, data)) in resources.iter().zip(&files) {
+            assert!(desc.load().unwrap() == *data, "v{} {}", version, desc.identifier);
+        }
+        // the compressible file shrinks, while the others are stored raw where possible
+        assert!(resources[1].packed_size < MIB as u64);
+        if version >= 2 {
+            assert_eq!(resources[0].packed_size, resources[0].size);
+        }
+    }
+}
+
+fn incremental_options() -> PackingOptions {
+    deflate_options(PACKAGE_NAME).with_incremental(true)
+}
+
+/// Overwrites a file with contents of the same length while keeping its modification time, so
+/// that the change can only be noticed by comparing contents.
+fn rewrite_keeping_mtime(path: &Path, contents: &[u8]) {
+    let mtime = fs::metadata(path).unwrap().modified().unwrap();
+    fs::write(path, contents).unwrap();
+    fs::File::options().write(true).open(path).unwrap().set_modified(mtime).unwrap();
+}
+
+/// Packs `src` into `out` and returns the contents of the resource `a`.
+fn pack_and_load_a(src: &Path, out: &Path, options: PackingOptions) -> Vec<u8> {
+    arp::create_arp_from_fs(src, out, options).unwrap();
+    let package = Package::load_from_file(out.join("test.arp")).unwrap();
+    let contents = contents(&package);
+    contents.into_iter().find(|(path, _)| path == "a").unwrap().1
+}
+
+#[test]
+fn incremental_build_reuses_unchanged_files() {
+    let dir = TestDir::new();
+    let (src, out) = (dir.path().join("src"), dir.path().join("out"));
+    fs::create_dir_all(&src).unwrap();
+    fs::write(src.join("a.txt"), b"original ".repeat(100)).unwrap();
+    fs::write(src.join("b.txt"), b"other ".repeat(100)).unwrap();
+
+    assert_eq!(pack_and_load_a(&src, &out, incremental_options()), b"original ".repeat(100));
+    assert!(out.join("test.arpcache").is_file());
+
+    // the size and modification time are unchanged, so the previous data is trusted
+    rewrite_keeping_mtime(&src.join("a.txt"), &b"modified ".repeat(100));
+    assert_eq!(pack_and_load_a(&src, &out, incremental_options()), b"original ".repeat(100));
+
+    // a different size is always noticed
+    fs::write(src.join("a.txt"), b"resized ".repeat(100)).unwrap();
+    assert_eq!(pack_and_load_a(&src, &out, incremental_options()), b"resized ".repeat(100));
+}
+
+#[test]
+fn incremental_build_compares_contents_when_mtime_changes() {
+    let dir = TestDir::new();
+    let (src, out) = (dir.path().join("src"), dir.path().join("out"));
+    fs::create_dir_all(&src).unwrap();
+    let path = src.join("a.txt");
+    fs::write(&path, b"original").unwrap();
+    pack_and_load_a(&src, &out, incremental_options());
+
+    // same size and different contents, with the modification time moved
+    fs::write(&path, b"modified").unwrap();
+    let mtime = fs::metadata(&path).unwrap().modified().unwrap() + Duration::from_secs(10);
+    fs::File::options().write(true).open(&path).unwrap().set_modified(mtime).unwrap();
+    assert_eq!(pack_and_load_a(&src, &out, incremental_options()), b"modified");
+}
+
+#[test]
+fn incremental_build_repacks_when_settings_change() {
+    let dir = TestDir::new();
+    let (src, out) = (dir.path().join("src"), dir.path().join("out"));
+    fs::create_dir_all(&src).unwrap();
+    let path = src.join("a.txt");
+
+    let mut changed_types = media_types();
+    changed_types.insert("txt", "application/x-test");
+    let changes: [Box<dyn Fn(PackingOptions) -> PackingOptions>; 3] = [
+        Box::new(move |options| options.with_media_types(changed_types.clone())),
+        Box::new(|options| options.with_uncompressed_media_types(["text/*"])),
+        Box::new(|options| options.with_compression_level(CompressionLevel::FASTEST)),
+    ];
+    for (i, change) in changes.iter().enumerate() {
+        fs::write(&path, b"original").unwrap();
+        pack_and_load_a(&src, &out, incremental_options());
+
+        // the data would be reused if the settings were the same
+        rewrite_keeping_mtime(&path, b"modified");
+        let options = change(incremental_options());
+        assert_eq!(pack_and_load_a(&src, &out, options), b"modified", "change {}", i);
+    }
+}
+
+#[test]
+fn incremental_build_recovers_from_damaged_cache() {
+    let dir = TestDir::new();
+    let (src, out) = (dir.path().join("src"), dir.path().join("out"));
+    fs::create_dir_all(&src).unwrap();
+    let path = src.join("a.txt");
+    fs::write(&path, b"original").unwrap();
+    pack_and_load_a(&src, &out, incremental_options());
+    rewrite_keeping_mtime(&path, b"modified");
+
+    // a corrupt cache is ignored and rebuilt
+    let cache_path = out.join("test.arpcache");
+    let mut cache = fs::read(&cache_path).unwrap();
+    cache.truncate(cache.len() / 2);
+    fs::write(&cache_path, &cache).unwrap();
+    assert_eq!(pack_and_load_a(&src, &out, incremental_options()), b"modified");
+    assert!(fs::read(&cache_path).unwrap().len() > cache.len());
+
+    rewrite_keeping_mtime(&path, b"replaced");
+    fs::remove_file(&cache_path).unwrap();
+    assert_eq!(pack_and_load_a(&src, &out, incremental_options()), b"replaced");
+    assert!(cache_path.is_file());
+}
+
+#[test]
+fn non_incremental_build_removes_cache() {
+    let dir = TestDir::new();
+    let (src, out) = (dir.path().join("src"), dir.path().join("out"));
+    fs::create_dir_all(&src).unwrap();
+    let path = src.join("a.txt");
+    fs::write(&path, b"original").unwrap();
+    pack_and_load_a(&src, &out, incremental_options());
+
+    rewrite_keeping_mtime(&path, b"modified");
+    let options = incremental_options().with_incremental(false);
+    assert_eq!(pack_and_load_a(&src, &out, options), b"modified");
+    assert!(!out.join("test.arpcache").exists());
+}
+
+/// Writes a small tree of files, creating them in the given order.
+fn write_tree(root: &Path, paths: &[&str]) {
+    for path in paths {
+        let full_path = root.join(path);
+        fs::create_dir_all(full_path.parent().unwrap()).unwrap();
+        fs::write(&full_path, format!("contents of {}", path).repeat(50)).unwrap();
+    }
+}
+
+#[test]
+fn packing_is_deterministic() {
+    let paths = ["b.txt", "a/z.json", "a/y.png", "c/d/e.bin", "f.txt", "c/b.txt"];
+
+    let dir = TestDir::new();
+    let tree_1 = dir.path().join("tree_1");
+    let tree_2 = dir.path().join("tree_2");
+    write_tree(&tree_1, &paths);
+    write_tree(&tree_2, &paths.iter().rev().copied().collect::<Vec<_>>());
+
+    let options = || options(PACKAGE_NAME);
+    let packed_1 = arp::pack_to_vec(&tree_1, options()).unwrap();
+    let packed_2 = arp::pack_to_vec(&tree_2, options().with_threads(4)).unwrap();
+
+    // packing the same tree again must not pick up anything from the environment either
+    let packed_3 = arp::pack_to_vec(&tree_1, options()).unwrap();
+
+    assert_eq!(packed_1, packed_2);
+    assert_eq!(packed_1, packed_3);
+}
+
+#[test]
+fn in_memory_round_trip() {
+    let dir = TestDir::new();
+    let src = dir.path().join("src");
+    write_tree(&src, &["a.txt", "dir/b.json", "dir/sub/c.bin"]);
+
+    let data = arp::pack_to_vec(&src, options(PACKAGE_NAME)).unwrap();
+    let package = Package::load_from_vec(data).unwrap();
+    assert_eq!(package.get_meta().total_parts, 1);
+
+    let expected: Vec<_> = [("a", "a.txt"), ("dir/b", "dir/b.json"), ("dir/sub/c", "dir/sub/c.bin")]
+        .iter()
+        .map(|(uid_path, path)| (uid_path.to_string(), fs::read(src.join(path)).unwrap()))
+        .collect();
+    assert_eq!(contents(&package), expected);
+}
+
+#[test]
+fn in_memory_packages_must_fit_in_one_part() {
+    let dir = TestDir::new();
+    let src = dir.path().join("src");
+    fs::create_dir_all(&src).unwrap();
+    for i in 0..3 {
+        fs::write(src.join(format!("res_{}.bin", i)), incompressible(6000, i)).unwrap();
+    }
+    let options = PackingOptions::new_v2(
+        PACKAGE_NAME,
+        NAMESPACE,
+        Some(PART_LEN),
+        None,
+        None::<&Path>,
+    ).unwrap();
+    let err = arp::pack_to_vec(&src, options).unwrap_err();
+    assert!(err.contains("does not fit in a single part"), "{}", err);
+
+    // likewise, a part of a multi-part package can't be loaded from memory
+    let out = dir.path().join("out");
+    multi_part_builder(3).write_to_dir(&out).unwrap();
+    let data = fs::read(out.join(format!("{}.part001.arp", PACKAGE_NAME))).unwrap();
+    assert!(Package::load_from_vec(data).is_err());
+}
+
+#[test]
+fn output_is_independent_of_thread_count() {
+    let build = |threads| {
+        let options = deflate_options(PACKAGE_NAME).with_threads(threads);
+        let mut builder = PackageBuilder::new(options).unwrap();
+        for i in 0..32 {
+            let contents = format!("resource {} ", i).repeat(200 + i * 10);
+            builder.add_bytes(format!("dir_{}/res_{}.txt", i % 4, i), contents.into_bytes(), None)
+                .unwrap();
+            builder.add_bytes(format!("raw_{}.bin", i), incompressible(1000, i as u32), None)
+                .unwrap();
+        }
+        builder.write_to_vec().unwrap()
+    };
+
+    let single = build(1);
+    for threads in [2, 4, 8] {
+        assert_eq!(build(threads), single, "{} threads", threads);
+    }
+}
+
+#[test]
+fn unset_compression_level_uses_default() {
+    let build = |level: Option<CompressionLevel>| {
+        let mut options = deflate_options(PACKAGE_NAME);
+        if let Some(level) = level {
+            options = options.with_compression_level(level);
+        }
+        let mut builder = PackageBuilder::new(options).unwrap();
+        builder.add_bytes("a.txt", "compressible ".repeat(1000).into_bytes(), None).unwrap();
+        builder.write_to_vec().unwrap()
+    };
+
+    assert_eq!(CompressionLevel::default(), CompressionLevel::DEFAULT);
+    assert_eq!(build(None), build(Some(CompressionLevel::DEFAULT)));
+    assert_ne!(build(None), build(Some(CompressionLevel::FASTEST)));
+}
+
+#[test]
+fn builder_output_is_independent_of_insertion_order() {
+    let resources = [("x/b.txt", "b"), ("a.txt", "a"), ("x/a.txt", "xa"), ("y/c.txt", "c")];
+
+    let build = |order: &mut dyn Iterator<Item = &(&str, &str)>| {
+        let options = options(PACKAGE_NAME);
+        let mut builder = PackageBuilder::new(options).unwrap();
+        for (path, contents) in order {
+            builder.add_bytes(path, contents.as_bytes().to_vec(), None).unwrap();
+        }
+        builder.write_to_vec().unwrap()
+    };
+
+    assert_eq!(build(&mut resources.iter()), build(&mut resources.iter().rev()));
+}
+
+/// Returns the identifiers and contents of all resources in the package, sorted by identifier.
+fn contents(package: &Arc<Package>) -> Vec<(String, Vec<u8>)> {
+    let mut resources: Vec<_> = package.get_all_resource_descriptors().into_iter()
+        .map(|desc| (desc.identifier.components.join("/"), desc.load().unwrap()))
+        .collect();
+    resources.sort();
+    resources
+}
+
+#[test]
+fn repack_changes_parts_and_namespace() {
+    let dir = TestDir::new();
+    multi_part_builder(4).write_to_dir(dir.path()).unwrap();
+    let original = Package::load_from_file(dir.path().join("test.part001.arp")).unwrap();
+    assert_eq!(original.get_meta().total_parts, 4);
+
+    let options = PackingOptions::new_v2("single", "other", None, None, None::<&Path>)
+        .unwrap();
+    let single = arp::repack(&original, options).unwrap();
+    single.write_to_dir(dir.path()).unwrap();
+    let single = Package::load_from_file(dir.path().join("single.arp")).unwrap();
+    assert_eq!(single.get_namespace(), "other");
+    assert_eq!(single.get_meta().total_parts, 1);
+    assert_eq!(contents(&single), contents(&original));
+
+    let options = PackingOptions::new_v2(
+        "split",
+        "other",
+        Some(PART_LEN),
+        Some(CompressionType::Deflate),
+        None::<&Path>,
+    ).unwrap();
+    arp::repack(&single, options).unwrap().write_to_dir(dir.path()).unwrap();
+    let split = Package::load_from_file(dir.path().join("split.part001.arp")).unwrap();
+    assert_eq!(split.get_meta().total_parts, 4);
+    assert_eq!(contents(&split), contents(&original));
+}
+
+fn update_builder() -> PackageBuilder {
+    let options = options(PACKAGE_NAME);
+    PackageBuilder::new(options).unwrap()
+}
+
+#[test]
+fn update_in_place_replaces_and_adds_resources() {
+    let dir = TestDir::new();
+    multi_part_builder(3).write_to_dir(dir.path()).unwrap();
+    let path = dir.path().join("test.part001.arp");
+    let mut expected = contents(&Package::load_from_file(&path).unwrap());
+
+    let mut builder = update_builder();
+    builder.add_bytes("res_1.bin", b"replaced".to_vec(), None).unwrap();
+    builder.add_bytes("new/nested.txt", b"added".to_vec(), None).unwrap();
+    builder.update_in_place(&path).unwrap();
+
+    expected[1].1 = b"replaced".to_vec();
+    expected.insert(0, ("new/nested".to_owned(), b"added".to_vec()));
+    let updated = Package::load_from_file(&path).unwrap();
+    assert_eq!(updated.get_meta().total_parts, 3);
+    assert_eq!(contents(&updated), expected);
+
+    let total_len = || dir.snapshot().iter().map(|(_, data)| data.len()).sum::<usize>();
+    let len_before = total_len();
+    assert_eq!(arp::compact(&path, Some(PART_LEN)).unwrap(), path);
+    let compacted = Package::load_from_file(&path).unwrap();
+    assert_eq!(contents(&compacted), expected);
+    assert!(total_len() < len_before);
+}
+
+#[test]
+fn compact_requires_part_length_for_multi_part_packages() {
+    let dir = TestDir::new();
+    multi_part_builder(3).write_to_dir(dir.path()).unwrap();
+    let path = dir.path().join("test.part001.arp");
+    let before = dir.snapshot();
+
+    let err = arp::compact(&path, None).unwrap_err();
+    assert!(err.contains("maximum part length is required"), "{}", err);
+    assert_eq!(dir.snapshot(), before);
+}
+
+#[test]
+fn compact_returns_path_of_first_part() {
+    let dir = TestDir::new();
+    multi_part_builder(3).write_to_dir(dir.path()).unwrap();
+    let split_path = dir.path().join("test.part001.arp");
+    let expected = contents(&Package::load_from_file(&split_path).unwrap());
+
+    // the whole package fits in one part of the new length
+    let single_path = arp::compact(&split_path, Some(1 << 20)).unwrap();
+    assert_eq!(single_path, dir.path().join("test.arp"));
+    let names: Vec<_> = dir.snapshot().into_iter().map(|(name, _)| name).collect();
+    assert_eq!(names, vec!["test.arp"]);
+    assert_eq!(contents(&Package::load_from_file(&single_path).unwrap()), expected);
+
+    // a single-part package may be compacted without a part length, or split into parts
+    assert_eq!(arp::compact(&single_path, None).unwrap(), single_path);
+    assert_eq!(arp::compact(&single_path, Some(PART_LEN)).unwrap(), split_path);
+    let compacted = Package::load_from_file(&split_path).unwrap();
+    assert_eq!(compacted.get_meta().total_parts, 3);
+    assert_eq!(contents(&compacted), expected);
+    assert!(!single_path.exists());
+}
+
+#[test]
+fn failed_update_leaves_package_readable() {
+    let dir = TestDir::new();
+    multi_part_builder(1).write_to_dir(dir.path()).unwrap();
+    let path = dir.path().join("test.arp");
+    let expected = contents(&Package::load_from_file(&path).unwrap());
+
+    let mut builder = update_builder();
+    builder.add_bytes("res_0.bin", b"replaced".to_vec(), None).unwrap();
+    builder.add_reader("broken.bin", FailingReader { remaining: 100 }, None).unwrap();
+    assert!(builder.update_in_place(&path).is_err());
+
+    assert_eq!(contents(&Package::load_from_file(&path).unwrap()), expected);
+}
+
+#[test]
+fn find_resources_matching_globs() {
+    let mut builder = update_builder();
+    for path in ["a.txt", "tex/t.png", "tex/sub/s.png", "tex/sub/raw", "snd/s.ogg"] {
+        builder.add_bytes(path, path.as_bytes().to_vec(), None).unwrap();
+    }
+    let package = Package::load_from_vec(builder.write_to_vec().unwrap()).unwrap();
+
+    let find = |pattern| package.find_resources_matching(pattern).into_iter()
+        .map(|desc| desc.identifier.components.join("/"))
+        .collect::<Vec<_>>();
+    assert_eq!(find("tex/*"), vec!["tex/sub/raw", "tex/sub/s", "tex/t"]);
+    assert_eq!(find("tex/sub/r*"), vec!["tex/sub/raw"]);
+    assert_eq!(find("s"), vec!["snd/s", "tex/sub/s"]);
+    assert_eq!(find("sub"), vec!["tex/sub/raw", "tex/sub/s"]);
+    assert!(find("missing/**").is_empty());
+}
+
+/// Packs `root` with the given options and returns the UID paths of the packed resources.
+fn packed_paths(root: &Path, configure: impl FnOnce(PackingOptions) -> PackingOptions)
+    -> Vec<String> {
+    let options = options(PACKAGE_NAME);
+    let packed = arp::pack_to_vec(root, configure(options)).unwrap();
+    contents(&Package::load_from_vec(packed).unwrap()).into_iter()
+        .map(|(path, _)| path)
+        .collect()
+}
+
+#[test]
+fn include_patterns_match_directories() {
+    let dir = TestDir::new();
+    write_tree(dir.path(), &["assets/a.png", "assets/sub/b.png", "src/assets.txt", "c.txt"]);
+
+    for pattern in ["assets", "assets/", "/assets", "assets/**"] {
+        let paths = packed_paths(dir.path(), |options| options.with_include([pattern]));
+        assert_eq!(paths, vec!["assets/a", "assets/sub/b"], "pattern '{}'", pattern);
+    }
+
+    let paths = packed_paths(dir.path(), |options| {
+        options.with_include(["assets"]).with_exclude(["sub/"])
+    });
+    assert_eq!(paths, vec!["assets/a"]);
+}
+
+#[test]
+fn nested_ignore_files_override_parents() {
+    let dir = TestDir::new();
+    write_tree(dir.path(), &[
+        "a.log",
+        "keep.log",
+        "build/out.bin",
+        "sub/b.log",
+        "sub/keep.log",
+        "sub/build/out.bin",
+        "sub/deep/c.log",
+    ]);
+    fs::write(dir.path().join(".arpignore"), "*.log
+/build/
+").unwrap();
+    fs::write(dir.path().join("sub/.arpignore"), "!*.log
+deep/
+").unwrap();
+
+    let paths = packed_paths(dir.path(), |options| options);
+    assert_eq!(paths, vec!["sub/b", "sub/build/out", "sub/keep"]);
+}
+
+/// Writes a tree containing a symlinked file and a symlinked directory.
+#[cfg(unix)]
+fn write_symlink_tree(root: &Path) {
+    use std::os::unix::fs::symlink;
+
+    write_tree(root, &["a.txt", "dir/b.txt"]);
+    symlink(root.join("a.txt"), root.join("link.txt")).unwrap();
+    symlink(root.join("dir"), root.join("link_dir")).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks_are_skipped_by_default() {
+    let dir = TestDir::new();
+    write_symlink_tree(dir.path());
+    // other special files are skipped along with symlinks
+    let _socket = std::os::unix::net::UnixListener::bind(dir.path().join("socket")).unwrap();
+
+    let paths = packed_paths(dir.path(), |options| options);
+    assert_eq!(paths, vec!["a", "dir/b"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn followed_symlinks_are_packed_as_copies() {
+    let dir = TestDir::new();
+    write_symlink_tree(dir.path());
+
+    let paths = packed_paths(dir.path(), |options| {
+        options.with_symlink_policy(SymlinkPolicy::Follow)
+    });
+    assert_eq!(paths, vec!["a", "dir/b", "link", "link_dir/b"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn symlink_policy_error_rejects_special_files() {
+    let options = || options(PACKAGE_NAME)
+        .with_symlink_policy(SymlinkPolicy::Error);
+
+    let dir = TestDir::new();
+    write_symlink_tree(dir.path());
+    let err = arp::pack_to_vec(dir.path(), options()).unwrap_err();
+    assert!(err.contains("is a symlink"), "{}", err);
+
+    let dir = TestDir::new();
+    write_tree(dir.path(), &["a.txt"]);
+    let _socket = std::os::unix::net::UnixListener::bind(dir.path().join("socket")).unwrap();
+    let err = arp::pack_to_vec(dir.path(), options()).unwrap_err();
+    assert!(err.contains("not a directory or regular file"), "{}", err);
+}
+
+#[cfg(unix)]
+#[test]
+fn followed_symlink_cycles_are_rejected() {
+    let dir = TestDir::new();
+    write_tree(dir.path(), &["dir/a.txt"]);
+    std::os::unix::fs::symlink(dir.path().join("dir"), dir.path().join("dir/loop")).unwrap();
+
+    let options = options(PACKAGE_NAME)
+        .with_symlink_policy(SymlinkPolicy::Follow);
+    let err = arp::pack_to_vec(dir.path(), options).unwrap_err();
+    assert!(err.contains("forms a cycle"), "{}", err);
+}
+
+#[test]
+fn descriptors_report_packed_size() {
+    let options = deflate_options("sizes");
+    let mut builder = PackageBuilder::new(options).unwrap();
+    builder.add_bytes("text.txt", b"compressible ".repeat(100), None).unwrap();
+    builder.add_bytes("noise.bin", incompressible(1000, 1), None).unwrap();
+    let package = Package::load_from_vec(builder.write_to_vec().unwrap()).unwrap();
+
+    for desc in package.get_all_resource_descriptors() {
+        match desc.name.as_str() {
+            "text" => assert!(desc.packed_size < desc.size),
+            // stored raw because compressing it doesn't help
+            _ => assert_eq!(desc.packed_size, desc.size),
+        }
+    }
+}
+
+#[test]
+fn descriptors_report_part_and_crc() {
+    let dir = TestDir::new();
+    multi_part_builder(3).write_to_dir(dir.path()).unwrap();
+    let package = Package::load_from_file(dir.path().join(format!("{}.part001.arp", PACKAGE_NAME)))
+        .unwrap();
+
+    let resources = package.get_all_resource_descriptors();
+    let mut parts: Vec<_> = resources.iter().map(|desc| desc.get_part()).collect();
+    parts.sort();
+    parts.dedup();
+    assert!(parts.len() > 1);
+    assert!(parts.iter().all(|part| (1..=package.get_meta().total_parts).contains(part)));
+
+    // every resource's data differs, so their CRCs should too
+    let crcs: Vec<_> = resources.iter().map(|desc| desc.get_crc()).collect();
+    assert!(crcs.iter().enumerate().all(|(i, crc)| !crcs[..i].contains(crc)));
+}
+
+#[test]
+fn write_to_streams_resource_data() {
+    let options = deflate_options("stream");
+    let text = b"a line of compressible text\n".repeat(100_000);
+    let mut builder = PackageBuilder::new(options).unwrap();
+    builder.add_bytes("text.txt", text.clone(), None).unwrap();
+    builder.add_bytes("noise.bin", incompressible(200_000, 2), None).unwrap();
+    let package = Package::load_from_vec(builder.write_to_vec().unwrap()).unwrap();
+
+    for desc in package.get_all_resource_descriptors() {
+        let mut streamed = Vec::new();
+        desc.write_to(&mut streamed).unwrap();
+        assert_eq!(streamed, desc.load().unwrap());
+
+        let mut packed = Vec::new();
+        desc.write_packed_to(&mut packed).unwrap();
+        assert_eq!(packed.len() as u64, desc.packed_size);
+    }
+    let mut streamed = Vec::new();
+    package.find_resource(&uid("text")).unwrap().write_to(&mut streamed).unwrap();
+    assert_eq!(streamed, text);
+}
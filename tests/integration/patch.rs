@@ -0,0 +1,141 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use arp::{CodecRegistry, Package, PackageBuilder, PackageKind, PackageSet, PackingOptions};
+
+use crate::common::{
+    deflate_options, incompressible, load, options, uid, FailingCompressor, TestDir, NAMESPACE,
+};
+
+fn build(resources: &[(&str, &str)]) -> Arc<Package> {
+    crate::common::build(options("base"), resources)
+}
+
+#[test]
+fn patch_applies_changes_and_deletions() {
+    let old = build(&[("a.txt", "a"), ("b.txt", "b"), ("dir/c.txt", "c"), ("dir/d.txt", "d")]);
+    let new = build(&[("a.txt", "a"), ("b.txt", "b2"), ("dir/d.txt", "d"), ("e.txt", "e")]);
+
+    let patch_data = arp::make_patch(&old, &new, options("patch")).unwrap()
+        .write_to_vec().unwrap();
+    let patch = Package::load_from_vec(patch_data).unwrap();
+    assert_eq!(patch.get_kind(), PackageKind::Patch);
+    assert_eq!(patch.get_tombstones(), vec![uid("dir/c")]);
+
+    let mut patched: Vec<_> = patch.get_all_resource_descriptors().into_iter()
+        .map(|desc| desc.identifier.to_string())
+        .collect();
+    patched.sort();
+    assert_eq!(patched, vec!["test:b", "test:e"]);
+
+    let set = PackageSet::new(vec![patch, old]);
+    assert_eq!(set.find_resource(&uid("b")).unwrap().load().unwrap(), b"b2");
+    assert_eq!(set.find_resource(&uid("a")).unwrap().load().unwrap(), b"a");
+    assert!(set.find_resource(&uid("dir/c")).is_err());
+
+    let mut listed: Vec<_> = set.get_all_resource_descriptors().into_iter()
+        .map(|desc| (desc.identifier.to_string(), desc.load().unwrap()))
+        .collect();
+    listed.sort();
+    let mut expected: Vec<_> = new.get_all_resource_descriptors().into_iter()
+        .map(|desc| (desc.identifier.to_string(), desc.load().unwrap()))
+        .collect();
+    expected.sort();
+    assert_eq!(listed, expected);
+}
+
+#[test]
+fn patch_replaces_resources_with_directories_and_back() {
+    // a resource becomes a directory of the same name, and a directory becomes a resource
+    let old = build(&[("a.txt", "a"), ("b/c.txt", "c"), ("b/d.txt", "d")]);
+    let new = build(&[("a/e.txt", "e"), ("b.txt", "b")]);
+
+    let patch_data = arp::make_patch(&old, &new, options("patch")).unwrap()
+        .write_to_vec().unwrap();
+    let patch = Package::load_from_vec(patch_data).unwrap();
+    assert!(patch.get_tombstones().is_empty());
+
+    let set = PackageSet::new(vec![patch, old]);
+    assert!(set.find_resource(&uid("a")).is_err());
+    assert!(set.find_resource(&uid("b/c")).is_err());
+    assert_eq!(set.find_resource(&uid("a/e")).unwrap().load().unwrap(), b"e");
+    assert_eq!(set.find_resource(&uid("b")).unwrap().load().unwrap(), b"b");
+
+    let mut listed: Vec<_> = set.get_all_resource_descriptors().into_iter()
+        .map(|desc| desc.identifier.to_string())
+        .collect();
+    listed.sort();
+    assert_eq!(listed, vec!["test:a/e", "test:b"]);
+}
+
+#[test]
+fn patch_copies_packed_data() {
+    let text = b"compressible ".repeat(1000);
+    // stored raw, and large enough to be streamed rather than buffered
+    let large = incompressible(5 * 1024 * 1024, 1);
+    let old = crate::common::build(deflate_options("old"), &[("a.txt", b"a".to_vec())]);
+    let new = crate::common::build(
+        deflate_options("new"),
+        &[("a.txt", text.clone()), ("b.bin", large.clone())],
+    );
+
+    let mut codecs = CodecRegistry::default();
+    codecs.register_compressor(Arc::new(FailingCompressor)).unwrap();
+    let options = deflate_options("patch").with_codecs(Arc::new(codecs));
+    let patch_data = arp::make_patch(&old, &new, options).unwrap().write_to_vec().unwrap();
+    let patch = Package::load_from_vec(patch_data).unwrap();
+    assert_eq!(load(&patch, "a"), text);
+    assert_eq!(load(&patch, "b"), large);
+}
+
+#[test]
+fn tombstone_hides_resources_beneath_it() {
+    let base = build(&[("dir/a.txt", "a"), ("dir/sub/b.txt", "b"), ("c.txt", "c")]);
+
+    let mut builder = PackageBuilder::new(options("patch").with_kind(PackageKind::Patch))
+        .unwrap();
+    builder.add_tombstone("dir").unwrap();
+    let patch = Package::load_from_vec(builder.write_to_vec().unwrap()).unwrap();
+
+    let set = PackageSet::new(vec![patch, base]);
+    assert!(set.find_resource(&uid("dir/sub/b")).is_err());
+    let listed: Vec<_> = set.get_all_resource_descriptors().into_iter()
+        .map(|desc| desc.identifier.to_string())
+        .collect();
+    assert_eq!(listed, vec!["test:c"]);
+}
+
+#[test]
+fn tombstones_require_patch_package() {
+    let mut builder = PackageBuilder::new(options("base")).unwrap();
+    assert!(builder.add_tombstone("a").is_err());
+
+    let v1_options = PackingOptions::new_v1("patch", NAMESPACE, None, None, None::<&Path>)
+        .unwrap()
+        .with_kind(PackageKind::Patch);
+    assert!(PackageBuilder::new(v1_options).is_err());
+}
+
+#[test]
+fn set_loaded_from_dir_prefers_patches() {
+    let test_dir = TestDir::new();
+    let dir = test_dir.path();
+
+    let mut base = PackageBuilder::new(options("a_base")).unwrap();
+    base.add_bytes("a.txt", b"base".to_vec(), None).unwrap();
+    base.add_bytes("b.txt", b"base".to_vec(), None).unwrap();
+    base.write_to_dir(dir).unwrap();
+    for (name, contents) in [("patch_1", "first"), ("patch_2", "second")] {
+        let mut patch = PackageBuilder::new(options(name).with_kind(PackageKind::Patch))
+            .unwrap();
+        patch.add_bytes("a.txt", contents.as_bytes().to_vec(), None).unwrap();
+        patch.write_to_dir(dir).unwrap();
+    }
+    fs::write(dir.join("notes.txt"), b"not a package").unwrap();
+
+    let set = PackageSet::load_from_dir(dir).unwrap();
+    assert_eq!(set.get_packages().len(), 3);
+    assert_eq!(set.find_resource(&uid("a")).unwrap().load().unwrap(), b"second");
+    assert_eq!(set.find_resource(&uid("b")).unwrap().load().unwrap(), b"base");
+}
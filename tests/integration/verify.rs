@@ -0,0 +1,98 @@
+use std::fs;
+use std::path::Path;
+
+use arp::{Package, PackageBuilder, PackingOptions};
+
+use crate::common::{
+    builder, deflate_options, incompressible, options, replace_once, uid, TestDir, NAMESPACE,
+};
+
+// offset of the resource count within the package header
+const HEADER_RES_CNT_OFF: usize = 0x56;
+
+#[test]
+fn intact_package_verifies() {
+    let data = builder(options("verify"), &[("a.txt", "alpha"), ("dir/b.txt", "beta")])
+        .write_to_vec().unwrap();
+    let report = arp::verify(&Package::load_from_vec(data).unwrap());
+    assert!(report.is_ok());
+    assert_eq!(report.resources.len(), 2);
+}
+
+#[test]
+fn corrupt_resource_data_is_reported() {
+    let mut data = builder(options("verify"), &[("a.txt", "alpha"), ("dir/b.txt", "beta")])
+        .write_to_vec().unwrap();
+    replace_once(&mut data, b"beta", b"bete");
+
+    let package = Package::load_from_vec(data).unwrap();
+    let report = arp::verify(&package);
+    assert!(!report.is_ok());
+    assert!(report.errors.is_empty());
+    let failed: Vec<_> = report.resources.iter()
+        .filter(|res| res.error.is_some())
+        .map(|res| res.identifier.to_string())
+        .collect();
+    assert_eq!(failed, vec!["test:dir/b"]);
+
+    // streamed reads can only detect the corruption at the end
+    assert!(package.find_resource(&uid("dir/b")).unwrap().write_to(&mut Vec::new()).is_err());
+}
+
+#[test]
+fn wrong_unpacked_length_is_reported() {
+    let text = "compressible ".repeat(1000);
+    let mut data = builder(deflate_options("verify"), &[("a.txt", &text)]).write_to_vec().unwrap();
+    assert!(arp::verify(&Package::load_from_vec(data.clone()).unwrap()).is_ok());
+
+    // the descriptor's unpacked length is the only place the resource's size appears
+    let size = (text.len() as u64).to_le_bytes();
+    replace_once(&mut data, &size, &(text.len() as u64 + 1).to_le_bytes());
+    let report = arp::verify(&Package::load_from_vec(data).unwrap());
+    assert!(report.resources[0].error.is_some());
+}
+
+#[test]
+fn large_resources_are_verified() {
+    let dir = TestDir::new();
+    let path = dir.path().join("large.bin");
+    // larger than anything the packer buffers
+    fs::write(&path, incompressible(5 << 20, 1)).unwrap();
+
+    let mut builder = PackageBuilder::new(options("verify")).unwrap();
+    builder.add_file("large.bin", &path, None).unwrap();
+    let report = arp::verify(&Package::load_from_vec(builder.write_to_vec().unwrap()).unwrap());
+    assert!(report.is_ok());
+    assert_eq!(report.resources[0].size, 5 << 20);
+}
+
+#[test]
+fn inconsistent_header_counts_are_reported() {
+    let mut data = builder(options("verify"), &[("a.txt", "alpha")]).write_to_vec().unwrap();
+    data[HEADER_RES_CNT_OFF] += 1;
+
+    let report = arp::verify(&Package::load_from_vec(data).unwrap());
+    assert!(!report.is_ok());
+    assert_eq!(report.errors.len(), 1);
+}
+
+#[test]
+fn corrupt_part_header_is_reported() {
+    let dir = TestDir::new();
+    let options = PackingOptions::new_v2("verify", NAMESPACE, Some(8000), None, None::<&Path>)
+        .unwrap();
+    let noise = |seed: u8| (0..6000u32).map(|i| (i as u8).wrapping_mul(seed)).collect::<Vec<_>>();
+    builder(options, &[("a.bin", noise(3)), ("b.bin", noise(5)), ("c.bin", noise(7))])
+        .write_to_dir(dir.path()).unwrap();
+    let part_1 = dir.path().join("verify.part001.arp");
+    assert!(arp::verify(&Package::load_from_file(&part_1).unwrap()).is_ok());
+
+    let part_2 = dir.path().join("verify.part002.arp");
+    let mut data = fs::read(&part_2).unwrap();
+    data[0] ^= 0xFF;
+    fs::write(&part_2, data).unwrap();
+
+    let report = arp::verify(&Package::load_from_file(&part_1).unwrap());
+    assert!(!report.is_ok());
+    assert!(report.errors.iter().any(|err| err.starts_with("Part 2")));
+}